@@ -0,0 +1,63 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Compares listing a large tree with and without full paths (-f).
+//!
+//! Run with `cargo bench --bench fullpath`.
+
+use std::{
+    env, fs,
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const DIRS: usize = 200;
+const FILES_PER_DIR: usize = 100;
+const RUNS: u32 = 5;
+
+fn create_tree(root: &Path) {
+    for d in 0..DIRS {
+        let dir = root.join(format!("dir{}", d)).join("nested");
+        fs::create_dir_all(&dir).unwrap();
+        for f in 0..FILES_PER_DIR {
+            fs::write(dir.join(format!("file{}.txt", f)), b"").unwrap();
+        }
+    }
+}
+
+fn measure(root: &Path, args: &[&str]) -> Duration {
+    let mut total = Duration::ZERO;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let status = Command::new(env!("CARGO_BIN_EXE_aldar"))
+            .args(args)
+            .arg(root)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        total += start.elapsed();
+        assert!(status.success());
+    }
+    total / RUNS
+}
+
+fn main() {
+    let root = env::temp_dir().join(format!("aldar-bench-{}", std::process::id()));
+    create_tree(&root);
+
+    let plain = measure(&root, &["-n"]);
+    let fullpath = measure(&root, &["-n", "-f"]);
+
+    println!(
+        "{} entries: plain {:?}, fullpath {:?} ({:.2}x)",
+        DIRS * (FILES_PER_DIR + 2),
+        plain,
+        fullpath,
+        fullpath.as_secs_f64() / plain.as_secs_f64()
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
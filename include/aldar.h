@@ -0,0 +1,62 @@
+/*
+ * Copyright © 2022 The Aldar Authors
+ *
+ * Use of this source code is governed by an BSD-style
+ * license that can be found in the LICENSE file.
+ */
+
+/* C API of aldar, available if the crate is built with the ffi feature. */
+
+#ifndef ALDAR_H
+#define ALDAR_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The call succeeded. */
+#define ALDAR_OK 0
+/* The run failed or entries couldn't be read, see aldar_last_error. */
+#define ALDAR_ERROR 1
+/* A required pointer was null or a string not valid UTF-8. */
+#define ALDAR_INVALID_ARGUMENT -1
+
+typedef struct AldarHandle AldarHandle;
+
+/* Receives len bytes of output, a return value other than 0 aborts the run. */
+typedef int (*AldarWriteFn)(const uint8_t *data, size_t len, void *user_data);
+
+/* Creates a handle listing the current directory, release it with aldar_free. */
+AldarHandle *aldar_new(void);
+void aldar_free(AldarHandle *handle);
+
+/* Adds a directory to list, each one is printed as separate tree. */
+int aldar_add_path(AldarHandle *handle, const char *path);
+
+void aldar_show_hidden(AldarHandle *handle, bool show);
+void aldar_show_dirs_only(AldarHandle *handle, bool dirs_only);
+/* Negative for no limit. */
+void aldar_set_max_level(AldarHandle *handle, int level);
+void aldar_show_size(AldarHandle *handle, bool show);
+void aldar_show_date(AldarHandle *handle, bool show);
+void aldar_show_fullpath(AldarHandle *handle, bool show);
+void aldar_use_ascii(AldarHandle *handle, bool ascii);
+
+/* Stores the NUL terminated output in out, release it with aldar_string_free. */
+int aldar_run_to_buffer(AldarHandle *handle, char **out);
+/* Passes the output to callback in chunks as it is written. */
+int aldar_run_with_callback(AldarHandle *handle, AldarWriteFn callback, void *user_data);
+
+/* Error of the last failed run or NULL, valid until the next run or aldar_free. */
+const char *aldar_last_error(const AldarHandle *handle);
+void aldar_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* ALDAR_H */
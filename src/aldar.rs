@@ -3,131 +3,501 @@
 // Use of this source code is governed by an BSD-style
 // license that can be found in the LICENSE file.
 
-use colored::*;
-use regex::{RegexSet, RegexSetBuilder};
-use simple_error::SimpleError;
+use chrono::{DateTime, Local};
+use log::{debug, info, trace, warn};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::{
+    borrow::Cow,
     cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
     env,
     error::Error,
-    fs::{self, DirEntry},
-    io::{self, Write},
-    path::PathBuf, char::REPLACEMENT_CHARACTER,
+    io::{self, BufWriter, Write},
+    mem,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{self, AtomicBool},
+        mpsc, Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime},
 };
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+#[cfg(feature = "git")]
+use crate::git::GitIndex;
+#[cfg(feature = "hash")]
+use crate::hash;
+use crate::{
+    archive::{ArchiveFs, ArchiveProvider, Archives},
+    arena::{self, EntryArena},
+    builder::AldarBuilder,
+    color,
+    color::Colorizer,
+    compat::{self, Compat},
+    error::AldarError,
+    escape::{self, Charset, Escaping, Latin1Encoder, Normalization},
+    event::{Event, EventFn},
+    format::{Formatter, Item, ReportFormat, Root, Summary, TextFormatter},
+    fsutil::{self, AldarExt, Backend, DirLimit, Entry, FileId, FileSystem},
+    icons::Icons,
+    mounts::{Mount, MountTable},
+    pathtree::PathTree,
+    pattern::Matcher,
+    sort::{self, Comparator, Grouping, SortKey},
+    stat::{self, Fields, Stat},
+    tree::TreeNode,
+    units::{self, Units},
+};
+
+// Minimum time between two progress reports.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Decides whether an entry is listed.
+pub type Filter = Box<dyn Fn(&Entry) -> bool + Send + Sync>;
+
+/// Returns text printed after the name of an entry, if any.
+pub type Annotator = Box<dyn Fn(&Entry) -> Option<String> + Send + Sync>;
 
-use crate::fsutil::AldarExt;
+/// Receives the progress of a scan.
+pub type ProgressFn = Box<dyn FnMut(Progress) + Send>;
 
-const KB_SIZE: u64 = 1 << 10;
-const MB_SIZE: u64 = 1 << 20;
-const GB_SIZE: u64 = 1 << 30;
-const TB_SIZE: u64 = 1 << 40;
-const EB_SIZE: u64 = 1 << 50;
-const PB_SIZE: u64 = 1 << 60;
+// Receives nodes while they are read, returns false to stop the scan.
+pub(crate) type NodeSink = Box<dyn FnMut(&TreeNode) -> bool + Send>;
+
+/// Progress of a scan, reported periodically while directories are read.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress<'a> {
+    /// Directories found so far.
+    pub dirs: u64,
+    /// Files found so far.
+    pub files: u64,
+    /// The directory read last.
+    pub path: &'a Path,
+}
+
+/// Statistics of a finished run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Stats {
+    /// Directories listed.
+    pub dirs: u64,
+    /// Files listed.
+    pub files: u64,
+    /// Total size of the listed files. Sizes are only fetched if needed, e.g. with
+    /// [`Aldar::show_size`], otherwise this is 0.
+    pub bytes: u64,
+    /// Entries or directories which couldn't be read.
+    pub errors: u64,
+    /// Time the run took, 0 on wasm32-unknown-unknown which has no clock.
+    pub duration: Duration,
+}
 
 /// Represents a glyphset.
-#[derive(Debug)]
-pub struct GlyphSet(&'static str, &'static str, &'static str);
+#[derive(Clone, Debug)]
+pub struct GlyphSet {
+    pipe: Cow<'static, str>,
+    item: Cow<'static, str>,
+    last: Cow<'static, str>,
+}
+
+impl GlyphSet {
+    /// Creates a glyphset continuing a level with `pipe`, prefixing entries with `item` and the
+    /// last entry of a directory with `last`. Indentation follows their display width.
+    pub fn new(pipe: impl Into<String>, item: impl Into<String>, last: impl Into<String>) -> Self {
+        GlyphSet {
+            pipe: Cow::Owned(pipe.into()),
+            item: Cow::Owned(item.into()),
+            last: Cow::Owned(last.into()),
+        }
+    }
+}
 
 impl Glyphs for GlyphSet {
     fn pipe(&self) -> String {
-        self.0.to_owned()
+        self.pipe.to_string()
     }
 
     fn last(&self) -> String {
-        self.1.to_owned()
+        self.last.to_string()
     }
 
     fn item(&self) -> String {
-        self.2.to_owned()
+        self.item.to_string()
     }
 }
 
 /// Unicode glyphset uses unicode charachters.
-pub const UNICODE_GLYPHSET: GlyphSet = GlyphSet("│", "└──", "├──");
+pub const UNICODE_GLYPHSET: GlyphSet = GlyphSet {
+    pipe: Cow::Borrowed("│"),
+    item: Cow::Borrowed("├──"),
+    last: Cow::Borrowed("└──"),
+};
 
 /// Unicode glyphset uses unicode charachters.
-pub const ASCII_GLYPHSET: GlyphSet = GlyphSet("|", "`--", "|--");
+pub const ASCII_GLYPHSET: GlyphSet = GlyphSet {
+    pipe: Cow::Borrowed("|"),
+    item: Cow::Borrowed("|--"),
+    last: Cow::Borrowed("`--"),
+};
 
-pub trait Glyphs {
+pub trait Glyphs: Send + Sync {
     fn pipe(&self) -> String;
     fn item(&self) -> String;
     fn last(&self) -> String;
 }
 
+// Callbacks and the writer are only used through &mut self, their mutexes just make Aldar Sync.
 pub struct Aldar {
     show_hidden_files: bool,
     dir_only: bool,
+    #[cfg(feature = "regex")]
     ignore_case: bool,
     level: i32,
-
+    follow_links: bool,
+    bfs: bool,
+    bfs_depth: usize,
+    skip_remote_fs: bool,
+    show_mounts: bool,
+    mounts: MountTable,
+    // Mounts used instead of the ones of the system, e.g. in tests
+    mount_table: Option<MountTable>,
+    compact: bool,
+    post_order: bool,
+
+    roots: Vec<PathBuf>,
     path: PathBuf,
+    canonical_path: Option<String>,
 
-    output: Box<dyn Write>,
+    output: BufWriter<CountingWriter>,
+    formatter: Box<dyn Formatter>,
+    colorizer: Option<Arc<dyn Colorizer>>,
     glyphs: Box<dyn Glyphs>,
 
     // Formatting options
     print_fullpath: bool,
+    relative_paths: bool,
+    absolute_root: bool,
+    cwd: PathBuf,
     print_size: bool,
+    print_date: bool,
+    print_depth: bool,
+    #[cfg(feature = "hash")]
+    compute_digests: bool,
+    use_ctime: bool,
     human_readable: bool,
-    replace_nonprintables: bool,
+    units: Units,
+    digit_separator: Option<String>,
+    escaping: Escaping,
+    charset: Charset,
+    normalization: Normalization,
+    hyperlinks: bool,
+    icons: Option<Icons>,
+    max_width: usize,
+    compat: Compat,
 
     // Filter options
-    exclude_pattern: Option<RegexSetBuilder>,
-    include_pattern: Option<RegexSetBuilder>,
-
-    exclude_matcher: Option<RegexSet>,
-    include_matcher: Option<RegexSet>,
-
-    // Statistics
+    #[cfg(feature = "regex")]
+    smart_case: bool,
+    #[cfg(feature = "regex")]
+    exclude_pattern: Option<Vec<String>>,
+    #[cfg(feature = "regex")]
+    include_pattern: Option<Vec<String>>,
+    highlight_matches: bool,
+
+    exclude_matcher: Option<Matcher>,
+    include_matcher: Option<Matcher>,
+
+    #[cfg(feature = "git")]
+    tracked_only: bool,
+    #[cfg(feature = "git")]
+    git_index: Option<GitIndex>,
+
+    // Sort options
+    sort_keys: Vec<SortKey>,
+    sort_ignore_case: bool,
+    comparator: Comparator,
+    filters: Vec<Filter>,
+    annotators: Vec<Annotator>,
+    custom_sort: bool,
+    grouping: Grouping,
+    reverse: bool,
+    unsorted: bool,
+
+    // Parallel traversal, directories are read ahead by the pool
+    threads: usize,
+    pool: Option<ThreadPool>,
+    max_open_dirs: usize,
+    dir_limit: DirLimit,
+    backend: Backend,
+    filesystem: Option<Box<dyn FileSystem>>,
+    archives: Archives,
+    fields: Fields,
+    prefetched: HashMap<PathBuf, io::Result<Vec<io::Result<Entry>>>>,
+
+    // Directories on the current path, used to detect symbolic link loops
+    visited: Vec<FileId>,
+
+    // Statistics, entries are counted once printed and while read for the progress
     proc_dirs: u64,
     proc_files: u64,
+    proc_bytes: u64,
+    found_dirs: u64,
+    found_files: u64,
+    stats: Stats,
+    errors: Vec<AldarError>,
+    // Peak number of entries read but not yet printed
+    peak_buffered: usize,
+    perf_stats: bool,
+    report: bool,
+    report_format: Option<ReportFormat>,
+    started: Option<Instant>,
+    calls_before: u64,
+    on_progress: Option<Mutex<ProgressFn>>,
+    on_event: Option<Mutex<EventFn>>,
+    // Receives the nodes of build_tree while they are read, returns false to stop
+    pub(crate) node_sink: Option<Mutex<NodeSink>>,
+    cancellation: Option<Arc<AtomicBool>>,
+    last_progress: Option<Instant>,
 
     indent: Vec<String>,
     sz_last: usize,
     sz_item: usize,
 }
 
+// Counts the bytes written to the output and encodes them as Latin-1 if needed.
+struct CountingWriter {
+    inner: Mutex<Box<dyn Write + Send>>,
+    written: u64,
+    latin1: Option<Latin1Encoder>,
+}
+
+impl CountingWriter {
+    fn new(inner: Box<dyn Write + Send>) -> CountingWriter {
+        CountingWriter {
+            inner: Mutex::new(inner),
+            written: 0,
+            latin1: None,
+        }
+    }
+
+    fn inner(&mut self) -> &mut Box<dyn Write + Send> {
+        self.inner.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(encoder) = self.latin1.as_mut() {
+            let encoded = encoder.encode(buf);
+            self.inner().write_all(&encoded)?;
+            self.written += encoded.len() as u64;
+            return Ok(buf.len());
+        }
+
+        let n = self.inner().write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+// A directory being listed by the iterative traversal.
+struct Frame {
+    entries: arena::IntoIter,
+    lvl: i32,
+    total: u64,
+    started: Option<Instant>,
+    prefetched: Vec<PathBuf>,
+    parent: Option<Box<PendingDir>>,
+}
+
+// A directory whose node is being built, finished once all its entries are done.
+struct TreeFrame {
+    node: TreeNode,
+    entries: arena::IntoIter,
+    lvl: i32,
+    pushed: bool,
+}
+
+// A directory whose contents are being listed, finished once its frame is done.
+struct PendingDir {
+    entry: Entry,
+    last: bool,
+    prefix: Option<String>,
+    pushed: usize,
+}
+
+enum Visit {
+    Leaf(u64),
+    Descend(Vec<Entry>, i32, Box<PendingDir>),
+}
+
+impl Default for Aldar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Aldar {
+    /// Returns a consuming builder, an alternative to the setters below.
+    pub fn builder() -> AldarBuilder {
+        AldarBuilder::new()
+    }
+
     /// Creates a new Aldar command.
     pub fn new() -> Self {
         let mut default = PathBuf::new();
         default.push(".");
 
         let current_dir = env::current_dir().unwrap_or(default);
-        Self {
+        let mut aldar = Self {
             show_hidden_files: false,
             dir_only: false,
+            #[cfg(feature = "regex")]
             ignore_case: false,
             level: -1,
+            follow_links: false,
+            bfs: false,
+            bfs_depth: 0,
+            skip_remote_fs: false,
+            show_mounts: false,
+            mounts: MountTable::default(),
+            mount_table: None,
+            compact: false,
+            post_order: false,
+            roots: vec![current_dir.clone()],
             path: current_dir,
+            canonical_path: None,
             glyphs: Box::new(UNICODE_GLYPHSET),
-            output: Box::new(io::stdout()),
+            output: BufWriter::new(CountingWriter::new(Box::new(io::stdout()))),
+            formatter: Box::new(TextFormatter::default()),
+            colorizer: None,
             print_fullpath: false,
+            relative_paths: false,
+            absolute_root: false,
+            cwd: PathBuf::new(),
             print_size: false,
+            print_date: false,
+            print_depth: false,
+            #[cfg(feature = "hash")]
+            compute_digests: false,
+            use_ctime: false,
             human_readable: false,
-            replace_nonprintables: false,
+            units: Units::Iec,
+            digit_separator: None,
+            escaping: Escaping::None,
+            charset: Charset::Utf8,
+            normalization: Normalization::None,
+            hyperlinks: false,
+            icons: None,
+            max_width: 0,
+            compat: Compat::None,
+            #[cfg(feature = "regex")]
+            smart_case: false,
+            #[cfg(feature = "regex")]
             exclude_pattern: None,
+            #[cfg(feature = "regex")]
             include_pattern: None,
+            highlight_matches: false,
             exclude_matcher: None,
             include_matcher: None,
+            #[cfg(feature = "git")]
+            tracked_only: false,
+            #[cfg(feature = "git")]
+            git_index: None,
+            sort_keys: vec![SortKey::Name],
+            sort_ignore_case: false,
+            comparator: sort::pipeline(&[SortKey::Name], false),
+            filters: vec![],
+            annotators: vec![],
+            custom_sort: false,
+            grouping: Grouping::DirsFirst,
+            reverse: false,
+            unsorted: false,
+            threads: 1,
+            pool: None,
+            max_open_dirs: 0,
+            dir_limit: DirLimit::new(1),
+            backend: Backend::Std,
+            filesystem: None,
+            archives: Archives::default(),
+            fields: Fields::ALL,
+            prefetched: HashMap::new(),
+            visited: vec![],
             proc_dirs: 0,
             proc_files: 0,
+            proc_bytes: 0,
+            found_dirs: 0,
+            found_files: 0,
+            stats: Stats::default(),
+            errors: vec![],
+            peak_buffered: 0,
+            perf_stats: false,
+            report: true,
+            report_format: None,
+            started: None,
+            on_progress: None,
+            on_event: None,
+            cancellation: None,
+            node_sink: None,
+            last_progress: None,
+            calls_before: 0,
             indent: vec![],
-            sz_item: UNICODE_GLYPHSET.item().chars().count(),
-            sz_last: UNICODE_GLYPHSET.last().chars().count() + 1,
-        }
+            sz_item: 0,
+            sz_last: 0,
+        };
+        aldar.measure_glyphs();
+        aldar
     }
 
     // Configures to use given writer.
-    pub fn use_writer(&mut self, writer: Box<dyn Write>) -> &mut Aldar {
-        self.output = writer;
+    pub fn use_writer(&mut self, writer: Box<dyn Write + Send>) -> &mut Aldar {
+        self.output = BufWriter::new(CountingWriter::new(writer));
+        self.use_charset(self.charset)
+    }
+
+    /// Configures the output format, plain text by default. The formatter follows the conventions
+    /// configured with [`Aldar::use_compat`] and colors with the [`Aldar::use_colorizer`] one.
+    pub fn use_formatter(&mut self, formatter: Box<dyn Formatter>) -> &mut Aldar {
+        self.formatter = formatter;
+        self.formatter.use_compat(self.compat);
+        if let Some(colorizer) = self.colorizer.as_ref() {
+            self.formatter.use_colorizer(colorizer.clone());
+        }
+        self
+    }
+
+    /// Configures how names are colored by the formatter in use, also by one configured later.
+    pub fn use_colorizer(&mut self, colorizer: Box<dyn Colorizer>) -> &mut Aldar {
+        let colorizer = Arc::from(colorizer);
+        self.formatter.use_colorizer(Arc::clone(&colorizer));
+        self.colorizer = Some(colorizer);
+        self
+    }
+
+    /// Follows the output conventions of another tool, so scripts parsing its output keep
+    /// working. The formatter in use is told about them, also one configured later.
+    pub fn use_compat(&mut self, compat: Compat) -> &mut Aldar {
+        self.compat = compat;
+        self.formatter.use_compat(compat);
         self
     }
 
     // Configures on which path aldar should operate.
     pub fn use_path(&mut self, path: String) -> &mut Aldar {
-        self.path.clear();
-        self.path.push(path.to_owned());
+        self.roots = vec![PathBuf::from(path)];
+        self
+    }
+
+    // Configures on which paths aldar should operate, each one is printed as separate tree.
+    pub fn use_paths(&mut self, paths: Vec<String>) -> &mut Aldar {
+        self.roots = paths.into_iter().map(PathBuf::from).collect();
         self
     }
 
@@ -144,16 +514,23 @@ impl Aldar {
     }
 
     /// Configures whether or not to ignore case when pattern matching is used.
+    #[cfg(feature = "regex")]
     pub fn case_sensitive(&mut self, ignore_case: bool) -> &mut Aldar {
         self.ignore_case = ignore_case;
         self
     }
 
+    /// Configures whether patterns without uppercase characters should match case insensitive.
+    #[cfg(feature = "regex")]
+    pub fn smart_case(&mut self, smart_case: bool) -> &mut Aldar {
+        self.smart_case = smart_case;
+        self
+    }
+
     /// Configures which glyphset to use.
     pub fn use_glyphset(&mut self, glyphs: Box<dyn Glyphs>) -> &mut Aldar {
         self.glyphs = glyphs;
-        self.sz_item = UNICODE_GLYPHSET.item().chars().count();
-        self.sz_last = UNICODE_GLYPHSET.last().chars().count() + 1;
+        self.measure_glyphs();
         self
     }
 
@@ -163,303 +540,2156 @@ impl Aldar {
         self
     }
 
-    /// Configures whether to show full path for items or not.
-    pub fn show_fullpath(&mut self, show_fullpath: bool) -> &mut Aldar {
-        self.print_fullpath = show_fullpath;
+    /// Configures whether symbolic links to directories are descended into.
+    pub fn follow_links(&mut self, follow_links: bool) -> &mut Aldar {
+        self.follow_links = follow_links;
         self
     }
 
-    /// Configures whether to show size for items or not.
-    pub fn show_size(&mut self, show_size: bool) -> &mut Aldar {
-        self.print_size = show_size;
+    /// Configures whether the tree is listed level by level instead of depth first.
+    pub fn breadth_first(&mut self, bfs: bool) -> &mut Aldar {
+        self.bfs = bfs;
         self
     }
 
-    /// Configures whether to show size in a human readable manner for items or not.
-    pub fn show_human_readable(&mut self, show_human_readable: bool) -> &mut Aldar {
-        self.human_readable = show_human_readable;
+    /// Configures whether network and FUSE filesystems mounted inside the tree are descended into.
+    pub fn skip_remote_fs(&mut self, skip_remote_fs: bool) -> &mut Aldar {
+        self.skip_remote_fs = skip_remote_fs;
         self
     }
 
-    /// Configures whether to replace non printables characters with a ?.
-    pub fn do_replace_nonprintable_chars(&mut self, replace_nonprintables: bool) -> &mut Aldar {
-        self.replace_nonprintables = replace_nonprintables;
+    /// Configures whether mount points are annotated with their device and filesystem type.
+    pub fn show_mounts(&mut self, show_mounts: bool) -> &mut Aldar {
+        self.show_mounts = show_mounts;
         self
     }
 
-    /// Configures aldar to use given strings as include patterns.
-    pub fn set_include_patterns(&mut self, patterns: &[&str]) -> &mut Aldar {
-        self.include_pattern = Some(RegexSetBuilder::new(patterns));
+    /// Configures whether chains of directories containing a single directory are printed on one line.
+    pub fn compact_dirs(&mut self, compact: bool) -> &mut Aldar {
+        self.compact = compact;
         self
     }
 
-    /// Configures aldar to use given strings as exclude patterns.
-    pub fn set_exclude_patterns(&mut self, patterns: &[&str]) -> &mut Aldar {
-        self.exclude_pattern = Some(RegexSetBuilder::new(patterns));
+    /// Configures whether directories are printed after their contents with cumulative sizes.
+    pub fn post_order(&mut self, post_order: bool) -> &mut Aldar {
+        self.post_order = post_order;
         self
     }
 
+    /// Configures how many threads read directories in parallel (0: number of CPUs).
+    pub fn use_threads(&mut self, threads: usize) -> &mut Aldar {
+        self.threads = threads;
+        self
+    }
 
+    /// Reports timing, metadata calls and bytes written to stderr at the end of a run.
+    pub fn show_perf_stats(&mut self, perf_stats: bool) -> &mut Aldar {
+        self.perf_stats = perf_stats;
+        self
+    }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        self.proc_dirs = 0;
-        self.proc_files = 0;
+    /// Configures whether the report with the number of directories and files is printed at the
+    /// end, the formatter isn't asked to end the output without it.
+    pub fn show_report(&mut self, report: bool) -> &mut Aldar {
+        self.report = report;
+        self
+    }
 
-        // Build include pattern if any was specified
-        if let Some(builder) = self.include_pattern.as_mut() {
-            builder.case_insensitive(self.ignore_case);
-            let matcher = builder.build();
-            if matcher.is_err() {
-                return Err(Box::new(SimpleError::new(
-                    "invalid include pattern specified",
-                )));
-            }
+    /// Configures a template the report is written from instead of the formatter, e.g.
+    /// `{dirs} dirs, {files} files, {size}`.
+    pub fn use_report_format(&mut self, format: Option<ReportFormat>) -> &mut Aldar {
+        self.report_format = format;
+        self
+    }
 
-            self.include_matcher = Some(matcher.unwrap());
-        }
+    /// Limits how many directories are read at once (0: derived from the open file limit).
+    pub fn use_max_open_dirs(&mut self, max_open_dirs: usize) -> &mut Aldar {
+        self.max_open_dirs = max_open_dirs;
+        self
+    }
 
-        // Build exclude pattern if any was specified
-        if let Some(builder) = self.exclude_pattern.as_mut() {
-            builder.case_insensitive(self.ignore_case);
-            let matcher = builder.build();
-            if matcher.is_err() {
-                return Err(Box::new(SimpleError::new(
-                    "invalid exclude pattern specified",
-                )));
-            }
+    /// Sets the implementation used to read directories.
+    pub fn use_backend(&mut self, backend: Backend) -> &mut Aldar {
+        self.backend = backend;
+        self
+    }
 
-            self.exclude_matcher = Some(matcher.unwrap());
-        }
+    /// Reads the tree from the given filesystem instead of the local one, the backend is not used
+    /// then. Git, mount and hash information still need the local one.
+    pub fn use_filesystem(&mut self, filesystem: Box<dyn FileSystem>) -> &mut Aldar {
+        self.filesystem = Some(filesystem);
+        self
+    }
 
-        let working_dir = self.path.to_str().unwrap_or_else(|| ".").to_string();
+    /// Shows the entries of archives as if the files were directories, listed by the provider of
+    /// their format without extracting them. Archives given as root are always shown that way.
+    /// Only archives of the local filesystem are expanded.
+    pub fn show_archives(&mut self, show_archives: bool) -> &mut Aldar {
+        self.archives.nested = show_archives;
+        self
+    }
 
-        writeln!(self.output.as_mut(), "{}", working_dir.blue()).ok();
+    /// Adds a provider listing the entries of an archive format, it takes precedence over the
+    /// ones added before. With the `zip` and `tar` features those formats are provided.
+    pub fn add_archive_provider(&mut self, provider: Box<dyn ArchiveProvider>) -> &mut Aldar {
+        self.archives.add_provider(provider);
+        self
+    }
 
-        self.show_dir(&working_dir, 0).ok();
+    /// Configures whether to show full path for items or not.
+    pub fn show_fullpath(&mut self, show_fullpath: bool) -> &mut Aldar {
+        self.print_fullpath = show_fullpath;
+        self
+    }
 
-        writeln!(
-            self.output.as_mut(),
-            "\n{} directories, {} files",
-            self.proc_dirs,
-            self.proc_files
-        )
-        .ok();
-        Ok(())
+    /// Configures whether full paths are shown relative to the current working directory.
+    pub fn show_relative_paths(&mut self, relative_paths: bool) -> &mut Aldar {
+        self.relative_paths = relative_paths;
+        self
     }
 
-    fn show_dir(&mut self, working_dir: &str, lvl: i32) -> Result<(), Box<dyn Error>> {
-        // Bail out if level is reached
-        if self.level > -1 && lvl > self.level {
-            return Ok(());
-        }
+    /// Configures whether the header of a tree is the canonical absolute path of its root.
+    pub fn show_absolute_root(&mut self, absolute_root: bool) -> &mut Aldar {
+        self.absolute_root = absolute_root;
+        self
+    }
 
-        let dirs = self.fetch_directory(working_dir)?;
-        let sz = dirs.len();
+    /// Configures whether to show size for items or not.
+    pub fn show_size(&mut self, show_size: bool) -> &mut Aldar {
+        self.print_size = show_size;
+        self
+    }
 
-        for (i, entry) in dirs.iter().enumerate() {
-            self.print_entry(entry, sz == i + 1);
+    /// Configures whether to show the date of the last modification for items or not.
+    pub fn show_date(&mut self, show_date: bool) -> &mut Aldar {
+        self.print_date = show_date;
+        self
+    }
 
-            if entry.is_dir() {
-                if let Some(p) = entry.path().to_str() {
-                    self.do_indent(sz == i + 1);
-                    self.show_dir(p, lvl + 1).ok();
-                    self.do_unindent();
-                }
-            }
-        }
+    /// Configures whether the depth of each entry is printed, e.g. `[2]`. The entries of a
+    /// root have depth 1.
+    pub fn show_depth(&mut self, show_depth: bool) -> &mut Aldar {
+        self.print_depth = show_depth;
+        self
+    }
 
-        Ok(())
+    /// Configures whether the SHA-256 digests of files are computed, in parallel on the pool of
+    /// [`Aldar::use_threads`]. They are kept in the nodes of [`Aldar::build_tree`].
+    #[cfg(feature = "hash")]
+    pub fn compute_digests(&mut self, compute_digests: bool) -> &mut Aldar {
+        self.compute_digests = compute_digests;
+        self
     }
 
-    fn fetch_directory(&mut self, working_dir: &str) -> Result<Vec<DirEntry>, Box<dyn Error>> {
-        if let Some(set) = self.exclude_matcher.as_ref() {
-            if set.is_match(working_dir) {
-                return Ok(vec![]);
-            }
-        }
+    /// Configures whether the shown date is the last status change instead of the last modification.
+    pub fn use_change_time(&mut self, use_ctime: bool) -> &mut Aldar {
+        self.use_ctime = use_ctime;
+        self
+    }
 
-        let mut entries: Vec<DirEntry> = fs::read_dir(working_dir)?
-            .filter_map(|r| {
-                if !r.is_ok() {
-                    return None;
-                }
+    /// Configures whether to show size in a human readable manner for items or not.
+    pub fn show_human_readable(&mut self, show_human_readable: bool) -> &mut Aldar {
+        self.human_readable = show_human_readable;
+        self
+    }
 
-                let entry = r.unwrap();
-                // Skip hidden files except if it's required
-                if !self.show_hidden_files && entry.is_hidden() {
-                    return None;
-                }
+    /// Configures the units of human readable sizes, binary ones (KiB, MiB) by default.
+    pub fn use_units(&mut self, units: Units) -> &mut Aldar {
+        self.units = units;
+        self
+    }
 
-                // Skip files if only directories is desired.
-                if self.dir_only && !entry.is_dir() {
-                    return None
-                }
+    /// Separates the thousands of sizes printed in bytes, e.g. `1,234,567` with a comma.
+    pub fn use_digit_separator(&mut self, separator: Option<String>) -> &mut Aldar {
+        self.digit_separator = separator;
+        self
+    }
 
-                if !entry.is_dir() {
-                    if let Some(matcher) = self.include_matcher.as_ref() {
-                        if !matcher.is_match(entry.file_name().to_str().unwrap()) {
-                            return None;
-                        }
-                    }
+    /// Configures whether to replace non printables characters with a ?, same as
+    /// [`Escaping::Replace`].
+    pub fn do_replace_nonprintable_chars(&mut self, replace_nonprintables: bool) -> &mut Aldar {
+        self.escaping = match replace_nonprintables {
+            true => Escaping::Replace,
+            false => Escaping::None,
+        };
+        self
+    }
 
-                    if let Some(matcher) = self.exclude_matcher.as_ref() {
-                        if matcher.is_match(entry.file_name().to_str().unwrap()) {
-                            return None;
-                        }
-                    }
-                }                
+    /// Configures the character set of the output, names are transliterated to it. The glyphs
+    /// are kept, pick ASCII ones for other charsets than UTF-8.
+    pub fn use_charset(&mut self, charset: Charset) -> &mut Aldar {
+        self.charset = charset;
+        self.output.get_mut().latin1 = match charset {
+            Charset::Latin1 => Some(Latin1Encoder::default()),
+            _ => None,
+        };
+        self
+    }
 
-                if entry.is_dir() {
-                    self.proc_dirs += 1;
-                } else {
-                    self.proc_files += 1;
-                }
+    /// Configures whether names link to their `file://` URL, terminals supporting OSC 8 make
+    /// them clickable.
+    pub fn show_hyperlinks(&mut self, hyperlinks: bool) -> &mut Aldar {
+        self.hyperlinks = hyperlinks;
+        self
+    }
 
-                Some(entry)
-            })
-            .collect();
+    /// Configures whether an icon is printed before each name, the default ones need a Nerd
+    /// Font.
+    pub fn show_icons(&mut self, icons: bool) -> &mut Aldar {
+        self.icons = icons.then(|| self.icons.take().unwrap_or_default());
+        self
+    }
 
-        entries.sort_by(|a, b| {
-            if a.path().is_dir() && b.path().is_file() {
-                return Ordering::Less;
-            }
+    /// Configures the icons printed before each name and turns them on.
+    pub fn use_icons(&mut self, icons: Icons) -> &mut Aldar {
+        self.icons = Some(icons);
+        self
+    }
 
-            if b.path().is_dir() && a.path().is_file() {
-                return Ordering::Greater;
-            }
+    /// Shortens names with an ellipsis so lines don't get wider than `width` columns, e.g. the
+    /// width of the terminal (0: no limit).
+    pub fn use_max_width(&mut self, width: usize) -> &mut Aldar {
+        self.max_width = width;
+        self
+    }
+
+    /// Configures the Unicode normalization form names are printed, sorted and matched against
+    /// patterns in. Patterns are normalized the same way.
+    pub fn use_normalization(&mut self, normalization: Normalization) -> &mut Aldar {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Configures how names are escaped, e.g. quoted so they can be pasted into a shell.
+    pub fn use_escaping(&mut self, escaping: Escaping) -> &mut Aldar {
+        self.escaping = escaping;
+        self
+    }
+
+    /// Configures whether only files tracked by git should be printed.
+    #[cfg(feature = "git")]
+    pub fn show_tracked_only(&mut self, tracked_only: bool) -> &mut Aldar {
+        self.tracked_only = tracked_only;
+        self
+    }
+
+    /// Configures by which keys the entries of a directory are sorted, later keys break ties.
+    pub fn use_sort_keys(&mut self, keys: &[SortKey]) -> &mut Aldar {
+        self.sort_keys = keys.to_vec();
+        self.custom_sort = false;
+        self
+    }
+
+    /// Configures a custom comparator to sort the entries of a directory, replacing the sort key.
+    pub fn sort_with(
+        &mut self,
+        comparator: impl Fn(&Entry, &Entry) -> Ordering + Send + Sync + 'static,
+    ) -> &mut Aldar {
+        self.comparator = Box::new(comparator);
+        self.custom_sort = true;
+        self
+    }
+
+    /// Configures a callback invoked with the progress of a scan at most every 100ms.
+    pub fn on_progress(&mut self, callback: impl FnMut(Progress) + Send + 'static) -> &mut Aldar {
+        self.on_progress = Some(Mutex::new(Box::new(callback)));
+        self
+    }
+
+    /// Configures a callback invoked with every [`Event`] of a run, on the thread running it.
+    pub fn on_event(&mut self, callback: impl FnMut(Event) + Send + 'static) -> &mut Aldar {
+        self.on_event = Some(Mutex::new(Box::new(callback)));
+        self
+    }
 
-            a.path().as_path().cmp(b.path().as_path())
+    /// Returns a channel receiving the events of the following runs, it replaces a callback set
+    /// with [`Aldar::on_event`]. Events are buffered until received, so a run on another thread
+    /// never waits for the receiver.
+    pub fn events(&mut self) -> mpsc::Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.on_event(move |event| {
+            sender.send(event).ok();
         });
+        receiver
+    }
 
-        Ok(entries)
+    /// Configures a token to abort a scan, once set no further entries are read or printed. The
+    /// summary of what was listed so far is still printed and the run fails with
+    /// [`AldarError::Cancelled`].
+    pub fn with_cancellation(&mut self, token: Arc<AtomicBool>) -> &mut Aldar {
+        self.cancellation = Some(token);
+        self
     }
 
-    fn print_entry(&mut self, entry: &DirEntry, last: bool) {
-        let mut indent = self.indent.clone();
-        if last {
-            indent.push(self.glyphs.last());
-        } else {
-            indent.push(self.glyphs.item());
-        }
+    /// Adds a predicate an entry must satisfy to be listed, on top of the other filters.
+    /// Directories which are filtered out are not descended. Paths read from a list don't
+    /// have an entry and are not passed to filters.
+    pub fn filter_with(
+        &mut self,
+        filter: impl Fn(&Entry) -> bool + Send + Sync + 'static,
+    ) -> &mut Aldar {
+        self.filters.push(Box::new(filter));
+        self
+    }
 
-        if self.print_size {
-            indent.push(self.size_as_str(entry.size()));
-        }
+    /// Adds a hook whose text is printed after the name of each entry it returns some for, e.g.
+    /// to decorate entries with ticket IDs or build status. Texts of several hooks are printed
+    /// in the order the hooks were added.
+    pub fn annotate_with(
+        &mut self,
+        annotator: impl Fn(&Entry) -> Option<String> + Send + Sync + 'static,
+    ) -> &mut Aldar {
+        self.annotators.push(Box::new(annotator));
+        self
+    }
 
-        let mut file_name = match entry.file_name().to_os_string().to_str() {
-            Some(s) => s.to_string(),
-            _ => return,
-        };
+    /// Configures whether to ignore case when sorting by name.
+    pub fn sort_ignore_case(&mut self, ignore_case: bool) -> &mut Aldar {
+        self.sort_ignore_case = ignore_case;
+        self
+    }
 
-        if self.print_fullpath {
-            let fp = self.path.canonicalize();
-            if fp.is_ok() {
-                if let Some(base) = fp.unwrap().to_str() {
-                    file_name = entry.full_rel_path(base);
-                }
-            }
-        }
+    /// Configures whether directories are listed before or after files or mixed with them.
+    pub fn use_grouping(&mut self, grouping: Grouping) -> &mut Aldar {
+        self.grouping = grouping;
+        self
+    }
 
+    /// Configures whether the sort order is reversed, directories are still listed first.
+    pub fn reverse_sort(&mut self, reverse: bool) -> &mut Aldar {
+        self.reverse = reverse;
+        self
+    }
 
-        // This is a quite naive replacement logic, maybe replace it with some more robust function.
-        if self.replace_nonprintables {
-            file_name = file_name.chars().map(|f| {
-                if f.is_control() {
-                    return REPLACEMENT_CHARACTER;
-                }
-                f
-            }).collect();
-        }
+    /// Configures whether entries are listed in directory order without sorting them.
+    pub fn no_sort(&mut self, unsorted: bool) -> &mut Aldar {
+        self.unsorted = unsorted;
+        self
+    }
 
+    /// Configures aldar to use given strings as include patterns, fails if one of them is not
+    /// a valid regular expression.
+    #[cfg(feature = "regex")]
+    pub fn set_include_patterns(&mut self, patterns: &[&str]) -> Result<&mut Aldar, AldarError> {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        self.include_matcher = Some(Matcher::new(&patterns, self.ignore_case, self.smart_case)?);
+        self.include_pattern = Some(patterns);
+        Ok(self)
+    }
 
-        if entry.is_dir() && entry.is_hidden() {
-            file_name = file_name.purple().to_string();
-        } else if entry.is_dir() {
-            file_name = file_name.blue().to_string();
-        } else if entry.is_executable() {
-            file_name = file_name.magenta().to_string();
-        } else if entry.is_hidden() {
-            file_name = file_name.cyan().to_string();
-        }
+    /// Configures whether the part of a file name matched by an include pattern is highlighted.
+    #[cfg(feature = "regex")]
+    pub fn highlight_matches(&mut self, highlight: bool) -> &mut Aldar {
+        self.highlight_matches = highlight;
+        self
+    }
 
-        writeln!(
-            self.output.as_mut(),
-            "{} {}",
-            indent.concat().to_string(),
-            file_name
-        )
-        .ok();
+    /// Configures aldar to use given strings as exclude patterns, fails if one of them is not
+    /// a valid regular expression.
+    #[cfg(feature = "regex")]
+    pub fn set_exclude_patterns(&mut self, patterns: &[&str]) -> Result<&mut Aldar, AldarError> {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        self.exclude_matcher = Some(Matcher::new(&patterns, self.ignore_case, self.smart_case)?);
+        self.exclude_pattern = Some(patterns);
+        Ok(self)
     }
 
-    fn do_indent(&mut self, is_last: bool) {
-        if is_last {
-            self.indent
-                .push(String::from_utf8(vec![b' '; self.sz_last]).unwrap());
-            return;
-        }
+    /// Returns true if a name passes the include and exclude patterns, as the names of files
+    /// are checked while listing. Directories are listed independent of the patterns.
+    #[cfg(feature = "regex")]
+    pub fn matches_patterns(&self, name: &str) -> bool {
+        let name = self.normalization.apply(name);
+        let included = self
+            .include_matcher
+            .as_ref()
+            .is_none_or(|matcher| matcher.is_match(&name));
+        let excluded = self
+            .exclude_matcher
+            .as_ref()
+            .is_some_and(|matcher| matcher.is_match(&name));
+        included && !excluded
+    }
 
-        self.indent.push(
-            self.glyphs.pipe()
-                + String::from_utf8(vec![b' '; self.sz_item])
-                    .unwrap()
-                    .as_ref(),
-        );
+    /// Lists the configured roots and returns the statistics of the run, the summary is printed
+    /// by the formatter as well.
+    pub fn run(&mut self) -> Result<Stats, AldarError> {
+        let result = self.show_roots();
+        // Output is buffered, make sure everything printed so far is written even on errors.
+        let flushed = self.output.flush().map_err(AldarError::Write);
+        self.print_perf_stats();
+        flushed.and(result).map(|_| self.stats)
     }
 
-    fn do_unindent(&mut self) {
-        self.indent.pop();
+    /// Returns the roots listed by a run.
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
     }
 
-    fn size_as_str(&self, sz: u64) -> String {
-        let create_str = |n: f64, unit: &str| -> String {
-            let str_sz: String;
-            if n.fract() == 0 as f64 {
-                str_sz = format!("{:.0}{}", n, unit);
-            } else {
-                str_sz = format!("{:.2}{}", n, unit);
-            }
+    /// Returns the statistics of the last run, also if it failed after listing, e.g. because
+    /// entries couldn't be read.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
 
-            if self.human_readable {
-                if str_sz.len() < 9 {
-                    return format!(" [{: >8}]", str_sz);
-                }
-                return format!(" [{: >8.4E}{}]", n, unit);
-            }
+    /// Prints the tree of a flat list of paths without accessing the filesystem.
+    pub fn run_with_paths(&mut self, tree: &PathTree) -> Result<Stats, AldarError> {
+        self.prepare()?;
 
-            if str_sz.len() < 12 {
-                return format!(" [{: >11}]", str_sz);
-            }
-            return format!(" [{: >11.4E}{}]", n, unit);
+        let root = Root {
+            name: ".",
+            error: None,
+            link: None,
         };
+        self.formatter
+            .begin(&mut self.output, &root)
+            .map_err(AldarError::Write)?;
+        self.show_path_tree(tree, 0).map_err(AldarError::Write)?;
+
+        self.print_report().map_err(AldarError::Write)?;
+        self.output.flush().map_err(AldarError::Write)?;
+        self.check_errors().map(|_| self.stats)
+    }
 
-        if !self.human_readable {
-            return create_str(sz as f64, "");
+    /// Builds the tree of the configured root without printing it. Filters, sorting and the
+    /// level limit apply as for [`Aldar::run`], all metadata fields are fetched. Several roots
+    /// become the children of a node without name. Missing roots are reported as
+    /// [`AldarError::RootNotFound`].
+    pub fn build_tree(&mut self) -> Result<TreeNode, AldarError> {
+        self.prepare()?;
+        self.fields = Fields::ALL;
+
+        let mut trees = vec![];
+        for root in self.roots.clone() {
+            trees.push(self.build_root(root)?);
         }
 
-        if sz > PB_SIZE {
-            return create_str((sz as f64) / (PB_SIZE as f64), "PB");
+        if self.is_cancelled() {
+            return Err(AldarError::Cancelled);
         }
 
-        if sz > EB_SIZE {
-            return create_str((sz as f64) / (EB_SIZE as f64), "EB");
+        match trees.len() {
+            1 => Ok(trees.pop().unwrap()),
+            _ => Ok(TreeNode {
+                children: trees,
+                ..TreeNode::default()
+            }),
         }
+    }
 
-        if sz > TB_SIZE {
-            return create_str((sz as f64) / (TB_SIZE as f64), "TB");
+    fn build_root(&mut self, root: PathBuf) -> Result<TreeNode, AldarError> {
+        self.enter_root(root)?;
+        let path = self.path.clone();
+        let metadata = match self.filesystem().metadata(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(AldarError::RootNotFound(path))
+            }
+            metadata => metadata.ok(),
+        };
+
+        let mut node = TreeNode {
+            name: fsutil::display_name(path.as_os_str()).into_owned(),
+            path: path.clone(),
+            is_dir: true,
+            metadata,
+            ..TreeNode::default()
+        };
+
+        let entries = match self.fetch_directory(&path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                node.error = Some(e.to_string());
+                self.record_error(&path, e);
+                self.visit(&node)?;
+                return Ok(node);
+            }
+        };
+        self.visit(&node)?;
+
+        // Nodes passed to a sink are not kept, only the root is returned
+        let keep = self.node_sink.is_none();
+        // Built with an explicit stack like the printed tree, so deep trees can't overflow.
+        let mut stack = vec![TreeFrame {
+            node,
+            entries: EntryArena::pack(entries).into_iter(),
+            lvl: 0,
+            pushed: false,
+        }];
+
+        loop {
+            if self.is_cancelled() {
+                return Err(AldarError::Cancelled);
+            }
+
+            let frame = stack.last_mut().unwrap();
+            let lvl = frame.lvl;
+            let entry = match frame.entries.next() {
+                Some(entry) => entry,
+                None => {
+                    let frame = stack.pop().unwrap();
+                    if frame.pushed {
+                        self.visited.pop();
+                    }
+                    match stack.last_mut() {
+                        Some(parent) if keep => parent.node.children.push(frame.node),
+                        Some(_) => {}
+                        None => return Ok(frame.node),
+                    }
+                    continue;
+                }
+            };
+
+            let mut node = TreeNode::from_entry(&entry);
+            let id = match self.follow_links && entry.is_dir() {
+                true => self.filesystem().file_id(&entry.path()),
+                false => None,
+            };
+
+            let level_reached = self.level > -1 && lvl + 1 > self.level;
+            let looped = id.as_ref().is_some_and(|id| self.visited.contains(id));
+            let remote = self.skip_remote_fs && self.is_remote_mount(&entry);
+            if !entry.is_dir() || level_reached || looped || remote {
+                self.visit(&node)?;
+                if keep {
+                    stack.last_mut().unwrap().node.children.push(node);
+                }
+                continue;
+            }
+
+            match self.fetch_directory(&entry.path()) {
+                Ok(children) => {
+                    self.visit(&node)?;
+                    let pushed = id.is_some();
+                    if let Some(id) = id {
+                        self.visited.push(id);
+                    }
+                    stack.push(TreeFrame {
+                        node,
+                        entries: EntryArena::pack(children).into_iter(),
+                        lvl: lvl + 1,
+                        pushed,
+                    });
+                }
+                Err(e) => {
+                    node.error = Some(e.to_string());
+                    self.record_error(&entry.path(), e);
+                    self.visit(&node)?;
+                    if keep {
+                        stack.last_mut().unwrap().node.children.push(node);
+                    }
+                }
+            }
+        }
+    }
+
+    // Passes a node without children to the sink as soon as it is read, fails if the sink is
+    // gone.
+    fn visit(&mut self, node: &TreeNode) -> Result<(), AldarError> {
+        let sink = match self.node_sink.as_mut() {
+            Some(sink) => sink.get_mut().unwrap_or_else(PoisonError::into_inner),
+            None => return Ok(()),
+        };
+        match sink(node) {
+            true => Ok(()),
+            false => Err(AldarError::Cancelled),
+        }
+    }
+
+    fn show_roots(&mut self) -> Result<(), AldarError> {
+        self.prepare()?;
+
+        for root in self.roots.clone() {
+            if self.is_cancelled() {
+                break;
+            }
+            self.show_root(root)?;
+        }
+
+        self.print_report().map_err(AldarError::Write)?;
+        if self.is_cancelled() {
+            return Err(AldarError::Cancelled);
+        }
+        self.check_errors()
+    }
+
+    // Checks the parts of the configuration not validated by their setters.
+    pub(crate) fn validate(&self) -> Result<(), AldarError> {
+        if self.filesystem.is_none() && !self.backend.is_supported() {
+            return Err(AldarError::UnsupportedBackend);
+        }
+        Ok(())
+    }
+
+    fn prepare(&mut self) -> Result<(), AldarError> {
+        self.proc_dirs = 0;
+        self.proc_files = 0;
+        self.proc_bytes = 0;
+        self.found_dirs = 0;
+        self.found_files = 0;
+        self.stats = Stats::default();
+        self.errors.clear();
+        self.peak_buffered = 0;
+        self.started = now();
+        self.last_progress = None;
+        self.calls_before = stat::calls();
+        self.output.get_mut().written = 0;
+        // A virtual filesystem doesn't need one, e.g. on wasm where there is none.
+        self.cwd = match (env::current_dir(), self.filesystem.is_some()) {
+            (Ok(cwd), _) => cwd,
+            (Err(_), true) => PathBuf::new(),
+            (Err(source), false) => {
+                return Err(AldarError::Io {
+                    path: PathBuf::from("."),
+                    source,
+                })
+            }
+        };
+
+        self.validate()?;
+
+        // Without threads, e.g. on wasm, directories are read on the calling thread.
+        let threads = match cfg!(target_family = "wasm") {
+            true => 1,
+            false => self.threads,
+        };
+        self.pool = match threads {
+            1 => None,
+            n => Some(
+                ThreadPoolBuilder::new()
+                    .num_threads(n)
+                    .build()
+                    .map_err(AldarError::ThreadPool)?,
+            ),
+        };
+        self.dir_limit = DirLimit::new(match self.max_open_dirs {
+            0 => fsutil::default_max_open_dirs(),
+            n => n,
+        });
+
+        self.mounts = match (self.skip_remote_fs || self.show_mounts, &self.mount_table) {
+            (true, Some(table)) => table.clone(),
+            (true, None) => MountTable::load(),
+            (false, _) => MountTable::default(),
+        };
+
+        // Patterns are validated when set, rebuilt here as case sensitivity may have changed since
+        #[cfg(feature = "regex")]
+        if let Some(patterns) = self.include_pattern.as_ref() {
+            let patterns = self.normalize_all(patterns);
+            self.include_matcher =
+                Some(Matcher::new(&patterns, self.ignore_case, self.smart_case)?);
+        }
+
+        #[cfg(feature = "regex")]
+        if let Some(patterns) = self.exclude_pattern.as_ref() {
+            let patterns = self.normalize_all(patterns);
+            self.exclude_matcher =
+                Some(Matcher::new(&patterns, self.ignore_case, self.smart_case)?);
+        }
+
+        if !self.custom_sort {
+            self.comparator = sort::pipeline(&self.sort_keys, self.sort_ignore_case);
+        }
+
+        self.fields = self.required_fields();
+        self.log_options(threads);
+
+        Ok(())
+    }
+
+    fn log_options(&self, threads: usize) {
+        #[cfg(feature = "regex")]
+        debug!(
+            "patterns include={:?} exclude={:?} ignore-case={} smart-case={}",
+            self.include_pattern.as_deref().unwrap_or_default(),
+            self.exclude_pattern.as_deref().unwrap_or_default(),
+            self.ignore_case,
+            self.smart_case
+        );
+        debug!(
+            "filters hidden={} dirs-only={} max-level={} custom={}",
+            self.show_hidden_files,
+            self.dir_only,
+            self.level,
+            self.filters.len()
+        );
+        debug!(
+            "traversal threads={} follow-links={} fields={:?}",
+            threads, self.follow_links, self.fields
+        );
+    }
+
+    // Returns the metadata fields needed by the enabled columns and sort keys.
+    fn required_fields(&self) -> Fields {
+        // A custom comparator, filter or annotator may look at anything
+        if self.custom_sort || !self.filters.is_empty() || !self.annotators.is_empty() {
+            return Fields::ALL;
+        }
+
+        let sorts_by = |key| !self.unsorted && self.sort_keys.contains(&key);
+        Fields {
+            size: self.print_size
+                || self.post_order
+                || sorts_by(SortKey::Size)
+                || self
+                    .report_format
+                    .as_ref()
+                    .is_some_and(ReportFormat::needs_size),
+            modified: (self.print_date && !self.use_ctime) || sorts_by(SortKey::Mtime),
+            changed: (self.print_date && self.use_ctime) || sorts_by(SortKey::Ctime),
+            // Executables are only highlighted with colors
+            mode: color::enabled(),
+        }
+    }
+
+    fn print_report(&mut self) -> io::Result<()> {
+        self.stats = Stats {
+            dirs: self.proc_dirs,
+            files: self.proc_files,
+            bytes: self.proc_bytes,
+            errors: self.errors.len() as u64,
+            duration: self.started.map(|s| s.elapsed()).unwrap_or_default(),
+        };
+
+        if self.report {
+            self.write_report()?;
+        }
+        self.formatter.finish(&mut self.output)
+    }
+
+    fn write_report(&mut self) -> io::Result<()> {
+        let summary = Summary {
+            dirs: self.proc_dirs,
+            files: self.proc_files,
+            errors: self.errors.len() as u64,
+            dirs_only: self.dir_only,
+        };
+
+        if let Some(format) = self.report_format.as_ref() {
+            let size = match (self.human_readable, self.digit_separator.as_deref()) {
+                (true, _) => self.units.format(self.stats.bytes),
+                (false, Some(separator)) => units::group_digits(self.stats.bytes, separator),
+                (false, None) => self.stats.bytes.to_string(),
+            };
+            let line = format.render(&self.stats, &size);
+            return self.formatter.summary(&mut self.output, &summary, &line);
+        }
+
+        self.formatter.end(&mut self.output, &summary)
+    }
+
+    // Printed to stderr once the output is flushed, so the written bytes are complete.
+    fn print_perf_stats(&self) {
+        let started = match (self.perf_stats, self.started) {
+            (true, Some(started)) => started,
+            _ => return,
+        };
+
+        let elapsed = started.elapsed().as_secs_f64();
+        let entries = self.proc_dirs + self.proc_files;
+        let rate = match elapsed > 0.0 {
+            true => entries as f64 / elapsed,
+            false => 0.0,
+        };
+
+        eprintln!("wall time:      {:.3}s", elapsed);
+        eprintln!("entries:        {} ({:.0}/s)", entries, rate);
+        eprintln!("metadata calls: {}", stat::calls() - self.calls_before);
+        eprintln!("bytes written:  {}", self.output.get_ref().written);
+    }
+
+    fn check_errors(&mut self) -> Result<(), AldarError> {
+        if !self.errors.is_empty() {
+            return Err(AldarError::Traversal {
+                errors: mem::take(&mut self.errors),
+            });
+        }
+        Ok(())
+    }
+
+    // Prepares the state shared by all directories of a root.
+    fn enter_root(&mut self, root: PathBuf) -> Result<(), AldarError> {
+        let root = fsutil::expand_home(&root);
+        self.path = match self.filesystem {
+            Some(_) => fsutil::normalize_path(&root),
+            None => fsutil::resolve_root(&root),
+        };
+        self.archives.add_root(&self.path);
+
+        #[cfg(feature = "git")]
+        if self.tracked_only {
+            if !self.path.exists() {
+                return Err(AldarError::RootNotFound(self.path.clone()));
+            }
+            match GitIndex::discover(&self.path) {
+                Ok(index) => self.git_index = Some(index),
+                Err(source) => {
+                    return Err(AldarError::Git {
+                        path: self.path.clone(),
+                        source,
+                    })
+                }
+            }
+        }
+
+        // Resolved once per root for the header and the full paths of followed links
+        self.canonical_path = match self.path.canonicalize() {
+            Ok(p) => Some(fsutil::display_name(p.as_os_str()).into_owned()),
+            Err(e) => {
+                debug!("not resolved path={} error={}", self.path.display(), e);
+                None
+            }
+        };
+
+        self.visited.clear();
+        if self.follow_links {
+            if let Some(id) = self.filesystem().file_id(&self.path) {
+                self.visited.push(id);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn show_root(&mut self, root: PathBuf) -> Result<(), AldarError> {
+        self.enter_root(root)?;
+        let working_dir = self.path.clone();
+
+        let header = match (self.absolute_root, self.canonical_path.as_ref()) {
+            (true, Some(p)) => p.clone(),
+            (true, None) => {
+                fsutil::display_name(self.cwd.join(&self.path).as_os_str()).into_owned()
+            }
+            (false, _) => fsutil::display_name(working_dir.as_os_str()).into_owned(),
+        };
+        let header = self.normalization.apply(&header);
+        let header = escape::transliterate(&header, self.charset);
+        let header = escape::escape(&header, self.escaping).into_owned();
+        let link = self.link(&working_dir);
+
+        let dirs = match self.fetch_directory(&working_dir) {
+            Ok(dirs) => dirs,
+            Err(e) => {
+                let note = error_note(&e, self.compat);
+                self.record_error(&working_dir, e);
+                let root = Root {
+                    name: &header,
+                    error: Some(&note),
+                    link: link.as_deref(),
+                };
+                return self
+                    .formatter
+                    .begin(&mut self.output, &root)
+                    .map_err(AldarError::Write);
+            }
+        };
+
+        let root = Root {
+            name: &header,
+            error: None,
+            link: link.as_deref(),
+        };
+        self.formatter
+            .begin(&mut self.output, &root)
+            .map_err(AldarError::Write)?;
+        self.emit(|_| Event::DirEntered {
+            path: working_dir.clone(),
+            depth: 0,
+        });
+
+        let started = now();
+        let result = match self.bfs {
+            true => self.show_dir_bfs(dirs),
+            false => self.show_dir(dirs, 0).map(|_| ()),
+        };
+        result.map_err(AldarError::Write)?;
+        if let Some(started) = started {
+            info!(
+                "listed root path={} elapsed={:?}",
+                working_dir.display(),
+                started.elapsed()
+            );
+        }
+
+        if !self.is_cancelled() {
+            self.emit(|_| Event::DirLeft {
+                path: working_dir,
+                depth: 0,
+            });
+        }
+        Ok(())
+    }
+
+    // Prints the given entries and their subtrees and returns their cumulative size. Uses an
+    // explicit stack instead of recursion, so deeply nested trees can't overflow the call stack.
+    // Output is streamed: each directory is read, sorted and printed before its subdirectories
+    // are read, so only the entries of the directories on the current path (and with threads the
+    // read ahead subdirectories) are buffered, never the whole tree.
+    fn show_dir(&mut self, dirs: Vec<Entry>, lvl: i32) -> io::Result<u64> {
+        let prefetched = self.prefetch(&dirs, lvl);
+        let mut stack = vec![Frame {
+            entries: EntryArena::pack(dirs).into_iter(),
+            lvl,
+            total: 0,
+            started: now(),
+            prefetched,
+            parent: None,
+        }];
+
+        loop {
+            if self.is_cancelled() {
+                // Nothing more is printed, the state is left as for a finished directory.
+                self.indent.clear();
+                self.prefetched.clear();
+                return Ok(0);
+            }
+
+            let frame = stack.last_mut().unwrap();
+            if let Some(entry) = frame.entries.next() {
+                let last = frame.entries.len() == 0;
+                let lvl = frame.lvl;
+                match self.show_entry(entry, last, lvl)? {
+                    Visit::Leaf(size) => stack.last_mut().unwrap().total += size,
+                    Visit::Descend(children, depth, parent) => {
+                        self.do_indent(last);
+                        self.emit(|a| Event::DirEntered {
+                            path: parent.entry.path(),
+                            depth: a.indent.len(),
+                        });
+                        let prefetched = self.prefetch(&children, depth);
+                        stack.push(Frame {
+                            entries: EntryArena::pack(children).into_iter(),
+                            lvl: depth,
+                            total: 0,
+                            started: now(),
+                            prefetched,
+                            parent: Some(parent),
+                        });
+                        self.track_buffered(&stack);
+                    }
+                }
+                continue;
+            }
+
+            let frame = stack.pop().unwrap();
+            self.discard_prefetched(frame.prefetched);
+
+            let mut parent = match frame.parent {
+                Some(parent) => parent,
+                None => return Ok(frame.total),
+            };
+
+            self.emit(|a| Event::DirLeft {
+                path: parent.entry.path(),
+                depth: a.indent.len(),
+            });
+            self.do_unindent();
+            if let Some(started) = frame.started {
+                debug!(
+                    "listed subtree path={} bytes={} elapsed={:?}",
+                    parent.entry.path().display(),
+                    frame.total,
+                    started.elapsed()
+                );
+            }
+
+            // In post-order the directory follows its contents and shows their cumulative size.
+            if self.post_order {
+                parent.entry.set_size(frame.total);
+                self.print_entry(&parent.entry, parent.last, parent.prefix.as_deref(), None)?;
+            }
+
+            for _ in 0..parent.pushed {
+                self.visited.pop();
+            }
+
+            stack.last_mut().unwrap().total += frame.total;
+        }
+    }
+
+    // Records the peak number of entries read but not yet printed.
+    fn track_buffered(&mut self, stack: &[Frame]) {
+        let prefetched: usize = self
+            .prefetched
+            .values()
+            .map(|r| r.as_ref().map_or(0, Vec::len))
+            .sum();
+        let buffered = stack.iter().map(|f| f.entries.len()).sum::<usize>() + prefetched;
+        self.peak_buffered = self.peak_buffered.max(buffered);
+    }
+
+    // Reads the subdirectories of the given entries in parallel, so they are ready once printed.
+    // Output stays deterministic as filtering, sorting and printing happen on this thread.
+    fn prefetch(&mut self, dirs: &[Entry], lvl: i32) -> Vec<PathBuf> {
+        let pool = match self.pool.as_ref() {
+            Some(pool) if !(self.level > -1 && lvl + 1 > self.level) => pool,
+            _ => return vec![],
+        };
+
+        let paths: Vec<PathBuf> = dirs
+            .iter()
+            .filter(|e| e.is_dir())
+            // Reading a dead network mount would block a thread of the pool
+            .filter(|e| !(self.skip_remote_fs && self.is_remote_mount(e)))
+            .map(|e| e.path())
+            .filter(|p| match self.exclude_matcher.as_ref() {
+                Some(set) => !set.is_match(&p.to_string_lossy()),
+                None => true,
+            })
+            .collect();
+
+        if paths.len() < 2 {
+            return vec![];
+        }
+
+        let results: Vec<_> = pool.install(|| {
+            paths
+                .par_iter()
+                .map(|p| (p.clone(), self.read_dir(p)))
+                .collect()
+        });
+        self.prefetched.extend(results);
+
+        paths
+    }
+
+    // Drops read ahead directories which were never printed, e.g. because of loops.
+    fn discard_prefetched(&mut self, paths: Vec<PathBuf>) {
+        for p in paths {
+            self.prefetched.remove(&p);
+        }
+    }
+
+    // Prints an entry, directories to descend into are returned with their contents.
+    fn show_entry(&mut self, mut entry: Entry, last: bool, lvl: i32) -> io::Result<Visit> {
+        if !entry.is_dir() {
+            self.print_entry(&entry, last, None, None)?;
+            return Ok(Visit::Leaf(entry.size()));
+        }
+
+        // Only followed links can create loops, so directories are tracked only then.
+        let id = match self.follow_links {
+            true => self.filesystem().file_id(&entry.path()),
+            false => None,
+        };
+
+        if let Some(id) = id.as_ref() {
+            if self.visited.contains(id) {
+                debug!("not descended path={} reason=loop", entry.path().display());
+                self.print_entry(&entry, last, None, Some("[recursive, not followed]"))?;
+                return Ok(Visit::Leaf(entry.size()));
+            }
+        }
+
+        // Bail out if level is reached
+        if self.level > -1 && lvl + 1 > self.level {
+            trace!(
+                "not descended path={} reason=max-level",
+                entry.path().display()
+            );
+            self.print_entry(&entry, last, None, None)?;
+            return Ok(Visit::Leaf(entry.size()));
+        }
+
+        if self.skip_remote_fs && self.is_remote_mount(&entry) {
+            debug!(
+                "not descended path={} reason=remote-fs",
+                entry.path().display()
+            );
+            self.print_entry(
+                &entry,
+                last,
+                None,
+                Some("[remote filesystem, not descended]"),
+            )?;
+            return Ok(Visit::Leaf(entry.size()));
+        }
+
+        // Read the directory before printing it, so failures can be reported inline.
+        let children = match self.fetch_directory(&entry.path()) {
+            Ok(children) => children,
+            Err(e) => {
+                let note = error_note(&e, self.compat);
+                self.record_error(&entry.path(), e);
+                self.print_entry(&entry, last, None, Some(&note))?;
+                return Ok(Visit::Leaf(entry.size()));
+            }
+        };
+
+        let mut pushed = 0;
+        if let Some(id) = id {
+            self.visited.push(id);
+            pushed += 1;
+        }
+
+        // Collapse chains of directories which only contain a single directory
+        let mut children = children;
+        let mut depth = lvl + 1;
+        let mut chain = vec![];
+        while self.compact
+            && children.len() == 1
+            && children[0].is_dir()
+            && !(self.level > -1 && depth + 1 > self.level)
+        {
+            let id = match self.follow_links {
+                true => self.filesystem().file_id(&children[0].path()),
+                false => None,
+            };
+            if id.as_ref().is_some_and(|id| self.visited.contains(id)) {
+                break;
+            }
+
+            let grandchildren = match self.fetch_directory(&children[0].path()) {
+                Ok(grandchildren) => grandchildren,
+                _ => break,
+            };
+
+            if let Some(id) = id {
+                self.visited.push(id);
+                pushed += 1;
+            }
+
+            chain.push(fsutil::display_name(&entry.file_name()).into_owned());
+            entry = children.pop().unwrap();
+            children = grandchildren;
+            depth += 1;
+        }
+
+        let prefix = match chain.is_empty() {
+            true => None,
+            false => Some(chain.join("/") + "/"),
+        };
+
+        // In post-order the directory is printed once its contents are done.
+        if !self.post_order {
+            self.print_entry(&entry, last, prefix.as_deref(), None)?;
+        }
+
+        Ok(Visit::Descend(
+            children,
+            depth,
+            Box::new(PendingDir {
+                entry,
+                last,
+                prefix,
+                pushed,
+            }),
+        ))
+    }
+
+    fn show_dir_bfs(&mut self, dirs: Vec<Entry>) -> io::Result<()> {
+        let mut visited: HashSet<FileId> = self.visited.drain(..).collect();
+        // Buffered directories are packed, only the level being printed is fully materialized.
+        let mut queue = VecDeque::from([(EntryArena::pack(dirs), 1)]);
+
+        while let Some((arena, depth)) = queue.pop_front() {
+            let dirs = arena.unpack();
+            // Entries are printed with their depth instead of indentation lines.
+            self.indent = vec![format!("[{}]", depth)];
+            self.bfs_depth = depth as usize;
+            let prefetched = self.prefetch(&dirs, depth - 1);
+
+            for entry in dirs.iter() {
+                if self.is_cancelled() {
+                    self.indent.clear();
+                    self.prefetched.clear();
+                    return Ok(());
+                }
+
+                if !entry.is_dir() || (self.level > -1 && depth > self.level) {
+                    self.print_entry(entry, false, None, None)?;
+                    continue;
+                }
+
+                if self.follow_links {
+                    if let Some(id) = self.filesystem().file_id(&entry.path()) {
+                        if !visited.insert(id) {
+                            self.print_entry(
+                                entry,
+                                false,
+                                None,
+                                Some("[recursive, not followed]"),
+                            )?;
+                            continue;
+                        }
+                    }
+                }
+
+                let children = self.fetch_directory(&entry.path());
+
+                match children {
+                    Ok(children) => {
+                        self.print_entry(entry, false, None, None)?;
+                        queue.push_back((EntryArena::pack(children), depth + 1));
+                    }
+                    Err(e) => {
+                        let note = error_note(&e, self.compat);
+                        self.record_error(&entry.path(), e);
+                        self.print_entry(entry, false, None, Some(&note))?;
+                    }
+                }
+            }
+            self.discard_prefetched(prefetched);
+        }
+
+        self.indent.clear();
+        Ok(())
+    }
+
+    fn show_path_tree(&mut self, tree: &PathTree, lvl: i32) -> io::Result<()> {
+        // Bail out if level is reached
+        if self.level > -1 && lvl > self.level {
+            return Ok(());
+        }
+
+        let mut children: Vec<(&String, &PathTree)> = tree
+            .children()
+            .filter(|(name, node)| {
+                if !self.show_hidden_files && name.starts_with('.') {
+                    return false;
+                }
+
+                if node.is_dir() {
+                    return true;
+                }
+
+                if self.dir_only {
+                    return false;
+                }
+
+                let name = self.normalization.apply(name);
+                if let Some(matcher) = self.include_matcher.as_ref() {
+                    if !matcher.is_match(&name) {
+                        return false;
+                    }
+                }
+
+                match self.exclude_matcher.as_ref() {
+                    Some(matcher) => !matcher.is_match(&name),
+                    None => true,
+                }
+            })
+            .collect();
+
+        // Paths are sorted like entries, keys needing metadata find none
+        if !self.unsorted {
+            let entry = |name: &String, node: &PathTree| {
+                let name = match self.custom_sort {
+                    true => Cow::Borrowed(name.as_str()),
+                    false => self.normalization.apply(name),
+                };
+                Entry::with_stat(PathBuf::from(&*name), Some(Stat::of_kind(node.is_dir())))
+            };
+            let mut keyed: Vec<_> = children
+                .into_iter()
+                .map(|(name, node)| (entry(name, node), name, node))
+                .collect();
+            let grouping = self.grouping();
+            keyed.sort_by(|(a, ..), (b, ..)| self.compare(grouping, a, b));
+            children = keyed
+                .into_iter()
+                .map(|(_, name, node)| (name, node))
+                .collect();
+        }
+
+        let sz = children.len();
+        for (i, (name, node)) in children.into_iter().enumerate() {
+            let mut indent = self.indent.clone();
+            indent.push(match sz == i + 1 {
+                true => self.glyphs.last(),
+                false => self.glyphs.item(),
+            });
+
+            match node.is_dir() {
+                true => self.proc_dirs += 1,
+                false => self.proc_files += 1,
+            }
+
+            let item = Item {
+                entry: None,
+                name,
+                is_dir: node.is_dir(),
+                depth: lvl as usize + 1,
+                prefix: &indent.concat(),
+                columns: &self.join_columns(self.depth_column(lvl as usize + 1).as_slice()),
+                notes: &[],
+                link: None,
+                icon: self
+                    .icons
+                    .as_ref()
+                    .map(|i| i.icon_of_path(name, node.is_dir())),
+                highlight: self.highlight(name, node.is_dir()),
+            };
+            self.formatter.entry(&mut self.output, &item)?;
+
+            if node.is_dir() {
+                self.do_indent(sz == i + 1);
+                self.show_path_tree(node, lvl + 1)?;
+                self.do_unindent();
+            }
+        }
+        Ok(())
+    }
+
+    // Passes the event to the callback, events are only created if there is one.
+    fn emit(&mut self, event: impl FnOnce(&Aldar) -> Event) {
+        if self.on_event.is_none() {
+            return;
+        }
+
+        let event = event(self);
+        if let Some(callback) = self.on_event.as_mut() {
+            callback.get_mut().unwrap_or_else(PoisonError::into_inner)(event);
+        }
+    }
+
+    // Reports the progress if a callback is set and the last report is long enough ago.
+    fn report_progress(&mut self, path: &Path) {
+        let callback = match self.on_progress.as_mut() {
+            Some(callback) => callback.get_mut().unwrap_or_else(PoisonError::into_inner),
+            None => return,
+        };
+
+        if self
+            .last_progress
+            .is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.last_progress = now();
+
+        callback(Progress {
+            dirs: self.found_dirs,
+            files: self.found_files,
+            path,
+        });
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(|token| token.load(atomic::Ordering::Relaxed))
+    }
+
+    // Archives are read from the local filesystem, so only it expands them.
+    fn filesystem(&self) -> ArchiveFs<'_> {
+        match self.filesystem.as_deref() {
+            Some(filesystem) => ArchiveFs::new(filesystem, None),
+            None => ArchiveFs::new(&self.backend, Some(&self.archives)),
+        }
+    }
+
+    // Reads a directory, readers wait here if too many directories are open.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<io::Result<Entry>>> {
+        let _permit = self.dir_limit.acquire();
+        self.filesystem()
+            .read_dir(dir, self.follow_links, self.fields)
+    }
+
+    // Counts a failed read, the run fails with all of them once the tree is printed.
+    fn record_error(&mut self, path: &Path, source: io::Error) {
+        warn!("read failed path={} error={}", path.display(), source);
+        self.emit(|_| Event::Error {
+            path: path.to_path_buf(),
+            error: io::Error::new(source.kind(), source.to_string()),
+        });
+        self.errors.push(AldarError::Io {
+            path: path.to_path_buf(),
+            source,
+        });
+    }
+
+    fn is_remote_mount(&self, entry: &Entry) -> bool {
+        self.mount_of(entry).is_some_and(|m| m.is_remote())
+    }
+
+    // Returns the filesystem mounted at the given entry if it is a mount point. Paths of a custom
+    // filesystem are looked up as they are.
+    fn mount_of(&self, entry: &Entry) -> Option<&Mount> {
+        if self.filesystem.is_some() {
+            return self.mounts.get(&entry.path());
+        }
+        match entry.path().canonicalize() {
+            Ok(p) => self.mounts.get(&p),
+            _ => None,
+        }
+    }
+
+    fn fetch_directory(&mut self, working_dir: &Path) -> io::Result<Vec<Entry>> {
+        if let Some(set) = self.exclude_matcher.as_ref() {
+            if set.is_match(&working_dir.to_string_lossy()) {
+                debug!("skipped dir path={} reason=excluded", working_dir.display());
+                return Ok(vec![]);
+            }
+        }
+
+        // Tracked paths are stored relative to the canonical repository root.
+        #[cfg(feature = "git")]
+        let canonical_dir = match self.git_index {
+            Some(_) => Some(working_dir.canonicalize()?),
+            None => None,
+        };
+
+        let raw_entries = match self.prefetched.remove(working_dir) {
+            Some(result) => result?,
+            None => self.read_dir(working_dir)?,
+        };
+
+        let mut entries: Vec<Entry> = raw_entries
+            .into_iter()
+            .filter_map(|r| {
+                let entry = match r {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        self.record_error(working_dir, e);
+                        return None;
+                    }
+                };
+
+                // Skip entries git does not know about
+                #[cfg(feature = "git")]
+                if let (Some(index), Some(dir)) = (self.git_index.as_ref(), canonical_dir.as_ref())
+                {
+                    if !index.is_tracked(&dir.join(entry.file_name())) {
+                        trace!("skipped path={} reason=untracked", entry.path().display());
+                        return None;
+                    }
+                }
+
+                // Skip hidden files except if it's required
+                if !self.show_hidden_files && entry.is_hidden() {
+                    trace!("skipped path={} reason=hidden", entry.path().display());
+                    return None;
+                }
+
+                // Skip files if only directories is desired.
+                if self.dir_only && !entry.is_dir() {
+                    return None;
+                }
+
+                if !entry.is_dir() {
+                    let file_name = entry.file_name();
+                    let name = self
+                        .normalization
+                        .apply(&file_name.to_string_lossy())
+                        .into_owned();
+                    if let Some(matcher) = self.include_matcher.as_ref() {
+                        if !matcher.is_match(&name) {
+                            trace!(
+                                "skipped path={} reason=not-included",
+                                entry.path().display()
+                            );
+                            return None;
+                        }
+                    }
+
+                    if let Some(matcher) = self.exclude_matcher.as_ref() {
+                        if matcher.is_match(&name) {
+                            trace!("skipped path={} reason=excluded", entry.path().display());
+                            return None;
+                        }
+                    }
+                }
+
+                if !self.filters.iter().all(|f| f(&entry)) {
+                    trace!("skipped path={} reason=filter", entry.path().display());
+                    return None;
+                }
+
+                match entry.is_dir() {
+                    true => self.found_dirs += 1,
+                    false => self.found_files += 1,
+                }
+
+                Some(entry)
+            })
+            .collect();
+
+        self.report_progress(working_dir);
+
+        #[cfg(feature = "hash")]
+        if self.compute_digests {
+            hash::hash_entries(&mut entries, self.pool.as_ref());
+        }
+
+        if self.unsorted {
+            return Ok(entries);
+        }
+
+        let grouping = self.grouping();
+        match self.normalization {
+            Normalization::None => entries.sort_by(|a, b| self.compare(grouping, a, b)),
+            _ if self.custom_sort => entries.sort_by(|a, b| self.compare(grouping, a, b)),
+            // Sort keys compare the normalized names, each entry is renamed once for that
+            normalization => {
+                let renamed = |e: &Entry| {
+                    let name = e.file_name();
+                    e.renamed(&normalization.apply(&name.to_string_lossy()))
+                };
+                let mut keyed: Vec<_> = entries.into_iter().map(|e| (renamed(&e), e)).collect();
+                keyed.sort_by(|(a, _), (b, _)| self.compare(grouping, a, b));
+                entries = keyed.into_iter().map(|(_, e)| e).collect();
+            }
+        }
+
+        Ok(entries)
+    }
+
+    // Orders two entries of a directory by their group, then by the sort keys.
+    fn compare(&self, grouping: Grouping, a: &Entry, b: &Entry) -> Ordering {
+        let group = grouping.compare(a, b);
+        if group != Ordering::Equal {
+            return group;
+        }
+
+        match self.reverse {
+            true => (self.comparator)(b, a),
+            false => (self.comparator)(a, b),
+        }
+    }
+
+    // Grouping keys among the sort keys replace the grouping, so they apply at their position.
+    fn grouping(&self) -> Grouping {
+        let groups = |k: &SortKey| matches!(k, SortKey::DirsFirst | SortKey::FilesFirst);
+        match !self.custom_sort && self.sort_keys.iter().any(groups) {
+            true => Grouping::None,
+            false => self.grouping,
+        }
+    }
+
+    fn print_entry(
+        &mut self,
+        entry: &Entry,
+        last: bool,
+        chain: Option<&str>,
+        note: Option<&str>,
+    ) -> io::Result<()> {
+        let mut indent = self.indent.clone();
+        if self.bfs {
+            // Depth annotation is already part of the indentation
+        } else if last {
+            indent.push(self.glyphs.last());
+        } else {
+            indent.push(self.glyphs.item());
+        }
+
+        let depth = match self.bfs {
+            true => self.bfs_depth,
+            false => self.indent.len() + 1,
+        };
+
+        // Counted here, so cancelled runs report only what was listed. Chains end with a slash
+        // for each collapsed directory.
+        match entry.is_dir() {
+            true => self.proc_dirs += 1 + chain.map_or(0, |c| c.matches('/').count() as u64),
+            false => {
+                self.proc_files += 1;
+                self.proc_bytes += entry.size();
+            }
+        }
+
+        let mut columns: Vec<String> = self.depth_column(depth).into_iter().collect();
+        if self.print_size {
+            columns.push(self.size_as_str(entry.size()));
+        }
+
+        if self.print_date {
+            let time = match self.use_ctime {
+                true => entry.changed(),
+                false => entry.modified(),
+            };
+            columns.push(self.date_as_str(time));
+        }
+
+        // Names which aren't valid UTF-8 are printed escaped instead of being skipped
+        let mut file_name = fsutil::display_name(&entry.file_name()).into_owned();
+
+        if self.bfs {
+            // Without indentation entries are identified by their path below the root
+            if let Ok(rel) = entry.path().strip_prefix(&self.path) {
+                file_name = fsutil::display_name(rel.as_os_str()).into_owned();
+            }
+        } else if self.print_fullpath && self.relative_paths {
+            let path = self.cwd.join(entry.path());
+            let relative = fsutil::relative_path(&path, &self.cwd);
+            file_name = fsutil::display_name(relative.as_os_str()).into_owned();
+        } else if self.print_fullpath {
+            match (self.follow_links, entry.path().strip_prefix(&self.path)) {
+                // The path below the root is the one below the canonical root, unless links to
+                // elsewhere are followed
+                (false, Ok(rel)) => file_name = fsutil::display_name(rel.as_os_str()).into_owned(),
+                _ => {
+                    if let Some(base) = self.canonical_path.as_ref() {
+                        file_name = entry.full_rel_path(base);
+                    }
+                }
+            }
+        }
+
+        if let Some(chain) = chain {
+            if !self.print_fullpath {
+                file_name = format!("{}{}", chain, file_name);
+            }
+        }
+
+        if let Cow::Owned(name) = self.normalization.apply(&file_name) {
+            file_name = name;
+        }
+        if let Cow::Owned(name) = escape::transliterate(&file_name, self.charset) {
+            file_name = name;
+        }
+
+        let mut notes = Vec::new();
+        if self.compat == Compat::Tree && entry.stat().is_some_and(|s| s.is_symlink) {
+            if let Ok(target) = self.filesystem().read_link(&entry.path()) {
+                notes.push(format!("-> {}", fsutil::display_name(target.as_os_str())));
+            }
+        }
+        notes.extend(self.annotators.iter().filter_map(|a| a(entry)));
+        if self.show_mounts && entry.is_dir() {
+            if let Some(mount) = self.mount_of(entry) {
+                notes.push(format!("[mount: {} {}]", mount.device, mount.fs_type));
+            }
+        }
+
+        if let Some(note) = note {
+            notes.push(note.to_string());
+        }
+
+        self.emit(|_| Event::EntryDiscovered {
+            node: TreeNode::from_entry(entry),
+            depth,
+        });
+
+        let prefix = indent.concat();
+        let columns = self.join_columns(&columns);
+        let icon = self.icons.as_ref().map(|i| i.icon(entry));
+        if self.max_width > 0 {
+            // Everything but the name keeps its width, separated by single spaces
+            let mut used = prefix.width() + columns.width() + 1;
+            used += icon.map_or(0, |i| i.width() + 1);
+            used += notes.iter().map(|n| n.width() + 1).sum::<usize>();
+
+            let ellipsis = match self.charset {
+                Charset::Utf8 => "…",
+                _ => "...",
+            };
+            let available = self.max_width.saturating_sub(used);
+            file_name = truncate_escaped(&file_name, available, ellipsis, self.escaping);
+        } else if let Cow::Owned(escaped) = escape::escape(&file_name, self.escaping) {
+            file_name = escaped;
+        }
+
+        let link = self.link(&entry.path());
+        let item = Item {
+            entry: Some(entry),
+            name: &file_name,
+            is_dir: entry.is_dir(),
+            depth,
+            prefix: &prefix,
+            columns: &columns,
+            notes: &notes,
+            link: link.as_deref(),
+            icon,
+            highlight: self.highlight(&file_name, entry.is_dir()),
+        };
+        self.formatter.entry(&mut self.output, &item)
+    }
+
+    #[cfg(feature = "regex")]
+    fn normalize_all(&self, patterns: &[String]) -> Vec<String> {
+        patterns
+            .iter()
+            .map(|p| self.normalization.apply(p).into_owned())
+            .collect()
+    }
+
+    // Include patterns only apply to files, so directories have no match to highlight.
+    fn highlight(&self, name: &str, is_dir: bool) -> Option<Range<usize>> {
+        match (self.highlight_matches, is_dir) {
+            (true, false) => self.include_matcher.as_ref()?.find(name),
+            _ => None,
+        }
+    }
+
+    fn link(&self, path: &Path) -> Option<String> {
+        self.hyperlinks
+            .then(|| fsutil::file_url(&self.cwd.join(path)))
+    }
+
+    // Pads the indentation of nested levels to the display width of the entry glyphs, so they
+    // line up with the names of their parent.
+    fn measure_glyphs(&mut self) {
+        let item = self.glyphs.item().width() + 1;
+        self.sz_item = item.saturating_sub(self.glyphs.pipe().width());
+        self.sz_last = self.glyphs.last().width() + 1;
+    }
+
+    fn do_indent(&mut self, is_last: bool) {
+        if is_last {
+            self.indent
+                .push(String::from_utf8(vec![b' '; self.sz_last]).unwrap());
+            return;
+        }
+
+        self.indent.push(
+            self.glyphs.pipe()
+                + String::from_utf8(vec![b' '; self.sz_item])
+                    .unwrap()
+                    .as_ref(),
+        );
+    }
+
+    fn do_unindent(&mut self) {
+        self.indent.pop();
+    }
+
+    fn depth_column(&self, depth: usize) -> Option<String> {
+        self.print_depth.then(|| depth.to_string())
+    }
+
+    // Encloses each column in brackets, tree puts all of them in one bracket.
+    fn join_columns(&self, columns: &[String]) -> String {
+        match self.compat {
+            Compat::Tree if columns.is_empty() => String::new(),
+            Compat::Tree => format!(" [{}] ", columns.join(" ")),
+            Compat::None => columns.iter().map(|c| format!(" [{}]", c)).collect(),
+        }
+    }
+
+    fn date_as_str(&self, time: SystemTime) -> String {
+        match self.compat {
+            Compat::Tree => compat::tree_date(time),
+            Compat::None => DateTime::<Local>::from(time)
+                .format("%b %e %H:%M")
+                .to_string(),
+        }
+    }
+
+    fn size_as_str(&self, sz: u64) -> String {
+        match (self.human_readable, self.compat) {
+            (true, Compat::Tree) => self.units.format_compact(sz),
+            (true, Compat::None) => format!("{:>1$}", self.units.format(sz), self.units.width()),
+            // Sizes wider than the column are printed in scientific notation
+            (false, _) if sz < 100_000_000_000 => match self.digit_separator.as_deref() {
+                Some(separator) => format!("{: >14}", units::group_digits(sz, separator)),
+                None => format!("{: >11}", sz),
+            },
+            (false, _) => format!("{: >11.4E}", sz as f64),
+        }
+    }
+}
+
+// Shortens the name to the given display width, ending with the ellipsis. At least one character
+// is kept to tell names apart.
+fn truncate<'a>(name: &'a str, width: usize, ellipsis: &str) -> Cow<'a, str> {
+    if name.width() <= width {
+        return Cow::Borrowed(name);
+    }
+
+    let budget = width.saturating_sub(ellipsis.width());
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in name.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w > budget && !truncated.is_empty() {
+            break;
+        }
+        truncated.push(c);
+        used += w;
+    }
+    truncated.push_str(ellipsis);
+    Cow::Owned(truncated)
+}
+
+// Shortens the name before escaping it, so quotes and escape sequences stay intact. The name is
+// shortened further until the escaped one fits.
+fn truncate_escaped(name: &str, width: usize, ellipsis: &str, escaping: Escaping) -> String {
+    let mut budget = width;
+    loop {
+        let truncated = truncate(name, budget, ellipsis);
+        let escaped = escape::escape(&truncated, escaping).into_owned();
+        if escaped.width() <= width || budget == 0 {
+            return escaped;
+        }
+        budget -= 1;
+    }
+}
+
+// Formats an error as note printed after an entry, without the os error code. tree only tells
+// that opening the directory failed.
+fn error_note(e: &dyn Error, compat: Compat) -> String {
+    if compat == Compat::Tree {
+        return "[error opening dir]".to_string();
+    }
+
+    let msg = e.to_string();
+    let msg = match msg.find(" (os error") {
+        Some(i) => &msg[..i],
+        None => &msg,
+    };
+    format!("[error opening dir: {}]", msg)
+}
+
+// Returns the current time, or nothing on wasm32-unknown-unknown where asking for it panics.
+// Only progress throttling and --perf-stats rely on it.
+fn now() -> Option<Instant> {
+    match cfg!(all(target_family = "wasm", target_os = "unknown")) {
+        true => None,
+        false => Some(Instant::now()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::PlainColorizer,
+        memfs::MemoryFs,
+        testing::{self, DirEntries, RecordingFs, SharedBuf},
+    };
+    use std::{
+        fs,
+        sync::{
+            atomic::{self, AtomicUsize},
+            Arc, Mutex,
+        },
+        thread,
+    };
+
+    // Creates `width` directories per level down to `depth` and `width` files in the deepest ones.
+    fn create_tree(name: &str, width: usize, depth: usize) -> PathBuf {
+        let root = testing::temp_dir(name);
+
+        let mut dirs = vec![root.clone()];
+        for lvl in 0..=depth {
+            let mut next = vec![];
+            for dir in dirs {
+                fs::create_dir_all(&dir).unwrap();
+                for i in 0..width {
+                    let path = dir.join(format!("{}{}", if lvl < depth { "d" } else { "f" }, i));
+                    match lvl < depth {
+                        true => next.push(path),
+                        false => fs::write(&path, b"").unwrap(),
+                    }
+                }
+            }
+            dirs = next;
+        }
+        root
+    }
+
+    fn run(root: &Path, threads: usize) -> (Aldar, String) {
+        let buf = SharedBuf::default();
+        let mut aldar = Aldar::new();
+        aldar
+            .use_colorizer(Box::new(PlainColorizer))
+            .use_paths(vec![root.to_string_lossy().into_owned()])
+            .use_threads(threads)
+            .use_writer(Box::new(buf.clone()));
+        aldar.run().unwrap();
+
+        let output = String::from_utf8(buf.take()).unwrap();
+        (aldar, output)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_parent_of_symlinked_root() {
+        let root = create_tree("rootlink", 2, 2);
+        std::os::unix::fs::symlink(root.join("d1/d0"), root.join("d0/link")).unwrap();
+        let parent = root.join("d1").canonicalize().unwrap();
+        let (_, output) = run(&root.join("d0/link/.."), 1);
+        fs::remove_dir_all(&root).ok();
+
+        assert!(
+            output.starts_with(&format!("{}\n", parent.display())),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn buffers_only_entries_on_current_path() {
+        let root = create_tree("stream", 6, 3);
+        let (aldar, _) = run(&root, 1);
+        fs::remove_dir_all(&root).ok();
+
+        // 1554 entries in total, at most the siblings on each level are pending.
+        assert_eq!(aldar.proc_dirs + aldar.proc_files, 6 + 36 + 216 + 1296);
+        assert!(aldar.peak_buffered <= 6 * 4, "{}", aldar.peak_buffered);
+    }
+
+    #[test]
+    fn read_ahead_is_bounded_by_one_level() {
+        let root = create_tree("readahead", 6, 3);
+        let (aldar, _) = run(&root, 4);
+        fs::remove_dir_all(&root).ok();
+
+        assert!(
+            aldar.peak_buffered <= 6 * 4 + 6 * 6 * 4,
+            "{}",
+            aldar.peak_buffered
+        );
+    }
+
+    #[test]
+    fn prints_each_directory_sorted_before_the_next() {
+        let root = create_tree("sorted", 2, 1);
+        fs::write(root.join("a.txt"), b"").unwrap();
+        let (_, output) = run(&root, 1);
+        fs::remove_dir_all(&root).ok();
+
+        let lines: Vec<&str> = output.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "├── d0",
+                "│   ├── f0",
+                "│   └── f1",
+                "├── d1",
+                "│   ├── f0",
+                "│   └── f1",
+                "└── a.txt",
+                "",
+                "2 directories, 5 files",
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_tree_in_printed_order() {
+        let root = create_tree("model", 2, 1);
+        let mut aldar = Aldar::new();
+        aldar.use_paths(vec![root.to_string_lossy().into_owned()]);
+        let tree = aldar.build_tree().unwrap();
+        fs::remove_dir_all(&root).ok();
+
+        let names: Vec<&str> = tree.walk().skip(1).map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["d0", "f0", "f1", "d1", "f0", "f1"]);
+        assert!(tree.children[0].is_dir);
+        assert!(tree.children[0].children[0].metadata.is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn reports_which_pattern_is_invalid() {
+        let mut aldar = Aldar::new();
+        match aldar.set_include_patterns(&["ok", "a("]) {
+            Err(AldarError::InvalidPattern { pattern, .. }) => assert_eq!(pattern, "a("),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn aborts_on_write_errors() {
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
         }
 
-        if sz > GB_SIZE {
-            return create_str((sz as f64) / (GB_SIZE as f64), "TB");
+        let root = create_tree("closed", 2, 1);
+        let mut aldar = Aldar::new();
+        aldar
+            .use_paths(vec![root.to_string_lossy().into_owned()])
+            .use_writer(Box::new(Closed));
+        let result = aldar.run();
+        fs::remove_dir_all(&root).ok();
+
+        match result {
+            Err(AldarError::Write(e)) => assert_eq!(e.kind(), io::ErrorKind::BrokenPipe),
+            other => panic!("unexpected result {:?}", other),
         }
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn filters_compose_with_patterns() {
+        let root = create_tree("filter", 2, 1);
+        let mut aldar = Aldar::new();
+        aldar
+            .use_paths(vec![root.to_string_lossy().into_owned()])
+            .filter_with(|e| e.file_name() != "d1")
+            .set_exclude_patterns(&["f1"])
+            .unwrap();
+        let tree = aldar.build_tree().unwrap();
+        fs::remove_dir_all(&root).ok();
+
+        let names: Vec<&str> = tree.walk().skip(1).map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["d0", "f0"]);
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn builder_validates_patterns() {
+        let result = Aldar::builder()
+            .exclude_patterns(&["[a-"])
+            .show_hidden(true)
+            .build();
+        assert!(matches!(result, Err(AldarError::InvalidPattern { .. })));
+    }
+
+    #[test]
+    fn truncates_to_display_width() {
+        assert_eq!(truncate("short", 5, "…"), "short");
+        assert_eq!(truncate("longer_name", 6, "…"), "longe…");
+        assert_eq!(truncate("longer_name", 6, "..."), "lon...");
+        // Wide characters take two columns
+        assert_eq!(truncate("北京市", 4, "…"), "北…");
+        assert_eq!(truncate("name", 0, "…"), "n…");
+    }
+
+    #[test]
+    fn truncates_before_escaping() {
+        let quoted = truncate_escaped("a long name", 8, "…", Escaping::Quote);
+        assert_eq!(quoted, "'a lon…'");
+        let escaped = truncate_escaped("tab\there", 10, "…", Escaping::Quote);
+        assert_eq!(escaped, "$'tab\\th…'");
+        assert_eq!(truncate_escaped("plain", 8, "…", Escaping::Quote), "plain");
+    }
+
+    #[test]
+    fn skips_remote_mounts_when_reading_ahead() {
+        let mut fs = MemoryFs::new();
+        fs.file("srv/local/a", 1)
+            .file("srv/share/b", 1)
+            .file("srv/tmp/c", 1);
+        // Records the directories read, the remote mount must not be among them
+        let reads = Arc::new(Mutex::new(vec![]));
+        let recorded = reads.clone();
+        let fs = RecordingFs(fs, move |path: &Path, read: &dyn Fn() -> DirEntries| {
+            recorded.lock().unwrap().push(path.to_path_buf());
+            read()
+        });
+        let buf = SharedBuf::default();
+        let mut aldar = Aldar::new();
+        aldar
+            .use_path("srv".to_string())
+            .use_filesystem(Box::new(fs))
+            .use_colorizer(Box::new(PlainColorizer))
+            .use_writer(Box::new(buf.clone()))
+            .use_threads(4)
+            .skip_remote_fs(true);
+        aldar.mount_table = Some(MountTable::parse("server:/export srv/share nfs4 rw 0 0\n"));
+        aldar.run().unwrap();
+
+        assert!(!reads.lock().unwrap().contains(&PathBuf::from("srv/share")));
+        let output = String::from_utf8(buf.take()).unwrap();
+        assert!(
+            output.contains("share [remote filesystem, not descended]"),
+            "{}",
+            output
+        );
+    }
 
-        if sz > MB_SIZE {
-            return create_str((sz as f64) / (MB_SIZE as f64), "MB");
+    #[test]
+    fn limits_open_directories() {
+        let mut fs = MemoryFs::new();
+        for i in 0..4 {
+            for j in 0..4 {
+                fs.file(format!("root/d{}/e{}/f", i, j), 1);
+            }
         }
+        // Records how many directories are read at the same time and the most there were
+        let counts = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+        let counted = counts.clone();
+        let fs = RecordingFs(fs, move |_: &Path, read: &dyn Fn() -> DirEntries| {
+            let open = counted[0].fetch_add(1, atomic::Ordering::SeqCst) + 1;
+            counted[1].fetch_max(open, atomic::Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(2));
+            let result = read();
+            counted[0].fetch_sub(1, atomic::Ordering::SeqCst);
+            result
+        });
+        let mut aldar = Aldar::new();
+        aldar
+            .use_path("root".to_string())
+            .use_filesystem(Box::new(fs))
+            .use_writer(Box::new(io::sink()))
+            .use_threads(4)
+            .use_max_open_dirs(1);
+        aldar.run().unwrap();
+
+        assert_eq!(counts[1].load(atomic::Ordering::SeqCst), 1);
+    }
 
-        if sz > KB_SIZE {
-            return create_str((sz as f64) / (KB_SIZE as f64), "KB");
+    #[test]
+    fn sorts_path_lists_like_directories() {
+        let buf = SharedBuf::default();
+        let mut aldar = Aldar::new();
+        aldar
+            .use_colorizer(Box::new(PlainColorizer))
+            .use_writer(Box::new(buf.clone()))
+            .use_sort_keys(&[SortKey::Version])
+            .use_grouping(Grouping::DirsFirst)
+            .reverse_sort(true);
+        aldar
+            .run_with_paths(&PathTree::parse("f9\nf10\ndir/a\ndir/b\nf1\n"))
+            .unwrap();
+
+        let output = String::from_utf8(buf.take()).unwrap();
+        let names: Vec<&str> = output
+            .lines()
+            .map(|l| l.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(
+            names[..6],
+            [".", "dir", "b", "a", "f10", "f9"],
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn sorts_normalized_names() {
+        let mut fs = MemoryFs::new();
+        for name in ["e\u{301}b", "f", "\u{e9}a"] {
+            fs.file(format!("root/{}", name), 1);
         }
+        let buf = SharedBuf::default();
+        let mut aldar = Aldar::new();
+        aldar
+            .use_path("root".to_string())
+            .use_filesystem(Box::new(fs))
+            .use_colorizer(Box::new(PlainColorizer))
+            .use_writer(Box::new(buf.clone()))
+            .use_normalization(Normalization::Nfc);
+        aldar.run().unwrap();
+
+        let output = String::from_utf8(buf.take()).unwrap();
+        let names: Vec<&str> = output
+            .lines()
+            .map(|l| l.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(names[1..4], ["f", "\u{e9}a", "\u{e9}b"], "{}", output);
+    }
+
+    #[test]
+    fn prints_full_paths_below_the_root() {
+        let mut fs = MemoryFs::new();
+        fs.file("root/d/x", 1);
+        let mut aldar = Aldar::new();
+        aldar.use_path("root".to_string()).show_fullpath(true);
+        let (output, result) = testing::render(&mut aldar, fs);
+        result.unwrap();
+
+        let path = Path::new("d").join("x");
+        assert!(
+            output.contains(&format!("└── {}\n", path.display())),
+            "{}",
+            output
+        );
+    }
 
-        create_str(sz as f64, "")
+    #[test]
+    fn is_send_and_sync() {
+        fn check<T: Send + Sync>() {}
+        check::<Aldar>();
+        check::<AldarError>();
     }
 }
@@ -0,0 +1,679 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Archives shown as directories. Their entries are listed by an [`ArchiveProvider`] for the
+//! format, the built-in ones read the index of zip files and the headers of tar files, nothing
+//! is extracted. Other formats are added with [`Aldar::add_archive_provider`](crate::Aldar):
+//!
+//! ```no_run
+//! use aldar::{archive::SevenZipProvider, Aldar};
+//!
+//! let mut aldar = Aldar::new();
+//! aldar
+//!     .use_path("backup.7z".to_string())
+//!     .add_archive_provider(Box::new(SevenZipProvider::default()));
+//! aldar.run().unwrap();
+//! ```
+
+#[cfg(feature = "zip")]
+use chrono::NaiveDate;
+use chrono::{Local, NaiveDateTime};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+#[cfg(any(feature = "zip", feature = "tar"))]
+use std::{fs::File, io::BufReader};
+#[cfg(feature = "tar")]
+use std::{io::Read, time::Duration};
+
+use crate::{
+    fsutil::{self, Entry, FileId, FileSystem},
+    memfs::MemoryFs,
+    stat::{Fields, Stat},
+};
+
+/// Lists the entries of the archives of a format.
+pub trait ArchiveProvider: Send + Sync {
+    /// Returns true if the file at `path` is an archive of the format, usually told by its name.
+    fn matches(&self, path: &Path) -> bool;
+
+    /// Lists the entries of the archive at `path`, each one below `path` in the returned
+    /// filesystem, e.g. `backup.zip/docs/notes.txt`.
+    fn read_index(&self, path: &Path) -> io::Result<MemoryFs>;
+}
+
+/// Reads the central directory of zip files, their entries are not decompressed.
+#[cfg(feature = "zip")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZipProvider;
+
+#[cfg(feature = "zip")]
+impl ArchiveProvider for ZipProvider {
+    fn matches(&self, path: &Path) -> bool {
+        has_extension(path, &[".zip"])
+    }
+
+    fn read_index(&self, path: &Path) -> io::Result<MemoryFs> {
+        let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+        let mut index = MemoryFs::new();
+        index.dir(path);
+        for i in 0..archive.len() {
+            // Raw entries are not decompressed
+            let file = archive.by_index_raw(i)?;
+            let name = match file.enclosed_name() {
+                Some(name) => name,
+                None => {
+                    log::warn!(
+                        "skipped zip entry path={} name={}",
+                        path.display(),
+                        file.name()
+                    );
+                    continue;
+                }
+            };
+
+            let entry = path.join(name);
+            if file.is_dir() {
+                index.dir(&entry);
+            } else if file.unix_mode().is_some_and(|mode| mode & 0o111 != 0) {
+                index.executable(&entry, file.size());
+            } else {
+                index.file(&entry, file.size());
+            }
+            if let Some(mode) = file.unix_mode() {
+                index.set_permissions(&entry, mode);
+            }
+            let modified = file.last_modified().and_then(|time| {
+                local_time(
+                    NaiveDate::from_ymd_opt(
+                        time.year().into(),
+                        time.month().into(),
+                        time.day().into(),
+                    )?
+                    .and_hms_opt(
+                        time.hour().into(),
+                        time.minute().into(),
+                        time.second().into(),
+                    )?,
+                )
+            });
+            if let Some(modified) = modified {
+                index.set_modified(&entry, modified);
+            }
+        }
+        Ok(index)
+    }
+}
+
+/// Reads the headers of tar files, uncompressed or compressed as `.tar.gz` or `.tar.zst`. The
+/// whole archive is decompressed to find them.
+#[cfg(feature = "tar")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TarProvider;
+
+#[cfg(feature = "tar")]
+impl ArchiveProvider for TarProvider {
+    fn matches(&self, path: &Path) -> bool {
+        has_extension(path, &[".tar", ".tar.gz", ".tgz", ".tar.zst", ".tzst"])
+    }
+
+    fn read_index(&self, path: &Path) -> io::Result<MemoryFs> {
+        let file = BufReader::new(File::open(path)?);
+        if has_extension(path, &[".tar.gz", ".tgz"]) {
+            read_tar(path, flate2::bufread::MultiGzDecoder::new(file))
+        } else if has_extension(path, &[".tar.zst", ".tzst"]) {
+            let decoder = ruzstd::StreamingDecoder::new(file).map_err(io::Error::other)?;
+            read_tar(path, decoder)
+        } else {
+            read_tar(path, file)
+        }
+    }
+}
+
+/// Lists 7z, rar and the other formats 7-Zip reads by running `7z l -slt`, which has to be
+/// installed.
+#[derive(Clone, Debug)]
+pub struct SevenZipProvider {
+    program: PathBuf,
+}
+
+impl SevenZipProvider {
+    /// Runs the given 7-Zip executable, e.g. `7zz`, instead of `7z` from the `PATH`.
+    pub fn with_program(program: impl Into<PathBuf>) -> SevenZipProvider {
+        SevenZipProvider {
+            program: program.into(),
+        }
+    }
+}
+
+impl Default for SevenZipProvider {
+    fn default() -> SevenZipProvider {
+        SevenZipProvider::with_program("7z")
+    }
+}
+
+impl ArchiveProvider for SevenZipProvider {
+    fn matches(&self, path: &Path) -> bool {
+        has_extension(path, &[".7z", ".rar"])
+    }
+
+    fn read_index(&self, path: &Path) -> io::Result<MemoryFs> {
+        let output = Command::new(&self.program)
+            .args(["l", "-slt", "--"])
+            .arg(path)
+            .output()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.program.display(), e)))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!(
+                "{} failed: {}",
+                self.program.display(),
+                stderr
+                    .lines()
+                    .find(|line| !line.trim().is_empty())
+                    .unwrap_or_default()
+            )));
+        }
+        Ok(parse_listing(
+            path,
+            &String::from_utf8_lossy(&output.stdout),
+        ))
+    }
+}
+
+// Archives are expanded by the first provider matching them.
+fn default_providers() -> Vec<Box<dyn ArchiveProvider>> {
+    vec![
+        #[cfg(feature = "zip")]
+        Box::new(ZipProvider),
+        #[cfg(feature = "tar")]
+        Box::new(TarProvider),
+    ]
+}
+
+/// Providers and the indexes of the archives read so far, shared by the threads reading
+/// directories. Archives given as roots are always expanded, others only if `nested` is set.
+pub(crate) struct Archives {
+    pub(crate) nested: bool,
+    roots: HashSet<PathBuf>,
+    providers: Vec<Box<dyn ArchiveProvider>>,
+    indexes: Mutex<HashMap<PathBuf, Arc<MemoryFs>>>,
+}
+
+impl Default for Archives {
+    fn default() -> Archives {
+        Archives {
+            nested: false,
+            roots: HashSet::new(),
+            providers: default_providers(),
+            indexes: Mutex::default(),
+        }
+    }
+}
+
+impl Archives {
+    /// Expands the archive at the normalized `path` even if nested archives are not.
+    pub(crate) fn add_root(&mut self, path: &Path) {
+        self.roots.insert(path.to_path_buf());
+    }
+
+    /// Adds a provider, it takes precedence over the ones added before.
+    pub(crate) fn add_provider(&mut self, provider: Box<dyn ArchiveProvider>) {
+        self.providers.insert(0, provider);
+    }
+
+    fn provider(&self, path: &Path) -> Option<&dyn ArchiveProvider> {
+        self.providers
+            .iter()
+            .find(|provider| provider.matches(path))
+            .map(|provider| provider.as_ref())
+    }
+
+    // Returns the index of the archive at `path`, reading it on first use.
+    fn index(&self, path: &Path, provider: &dyn ArchiveProvider) -> io::Result<Arc<MemoryFs>> {
+        if let Some(index) = self.indexes.lock().unwrap().get(path) {
+            return Ok(index.clone());
+        }
+
+        let index = Arc::new(provider.read_index(path)?);
+        self.indexes
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), index.clone());
+        Ok(index)
+    }
+}
+
+/// A filesystem showing the archives of another one as directories, without archives it passes
+/// all calls on.
+pub(crate) struct ArchiveFs<'a> {
+    inner: &'a dyn FileSystem,
+    archives: Option<&'a Archives>,
+}
+
+impl<'a> ArchiveFs<'a> {
+    pub(crate) fn new(inner: &'a dyn FileSystem, archives: Option<&'a Archives>) -> ArchiveFs<'a> {
+        ArchiveFs { inner, archives }
+    }
+
+    // Returns the provider of the normalized `path` if it is an archive to expand.
+    fn provider(&self, path: &Path) -> Option<&'a dyn ArchiveProvider> {
+        let archives = self.archives?;
+        if !archives.nested && !archives.roots.contains(path) {
+            return None;
+        }
+        let provider = archives.provider(path)?;
+        match self.inner.metadata(path) {
+            Ok(stat) if !stat.is_dir => Some(provider),
+            _ => None,
+        }
+    }
+
+    fn is_archive(&self, path: &Path) -> bool {
+        self.provider(&fsutil::normalize_path(path)).is_some()
+    }
+
+    // Returns the index of the archive containing `path`, the path may be the archive itself.
+    fn enclosing(&self, path: &Path) -> Option<io::Result<Arc<MemoryFs>>> {
+        let path = fsutil::normalize_path(path);
+        let ancestors: Vec<&Path> = path.ancestors().collect();
+        let (archive, provider) = ancestors
+            .into_iter()
+            .rev()
+            .find_map(|ancestor| Some((ancestor, self.provider(ancestor)?)))?;
+        Some(self.archives?.index(archive, provider))
+    }
+}
+
+impl FileSystem for ArchiveFs<'_> {
+    fn read_dir(
+        &self,
+        dir: &Path,
+        follow_links: bool,
+        fields: Fields,
+    ) -> io::Result<Vec<io::Result<Entry>>> {
+        if let Some(index) = self.enclosing(dir) {
+            return index?.read_dir(dir, follow_links, fields);
+        }
+
+        let entries = self.inner.read_dir(dir, follow_links, fields)?;
+        let archives = match self.archives {
+            Some(archives) if archives.nested => archives,
+            _ => return Ok(entries),
+        };
+        let entries = entries
+            .into_iter()
+            .map(|entry| {
+                let entry = entry?;
+                match entry.stat().copied() {
+                    Some(stat)
+                        if !stat.is_dir
+                            && !stat.is_symlink
+                            && archives.provider(&entry.path()).is_some() =>
+                    {
+                        Ok(Entry::with_stat(entry.path(), Some(as_dir(stat))))
+                    }
+                    _ => Ok(entry),
+                }
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Stat> {
+        if self.is_archive(path) {
+            return self.inner.metadata(path).map(as_dir);
+        }
+        match self.enclosing(path) {
+            Some(index) => index?.metadata(path),
+            None => self.inner.metadata(path),
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.enclosing(path) {
+            Some(index) => index?.read_link(path),
+            None => self.inner.read_link(path),
+        }
+    }
+
+    fn file_id(&self, path: &Path) -> Option<FileId> {
+        if self.is_archive(path) {
+            return self.inner.file_id(path);
+        }
+        match self.enclosing(path) {
+            Some(index) => index.ok()?.file_id(path),
+            None => self.inner.file_id(path),
+        }
+    }
+}
+
+// Returns true if the name of `path` ends with one of the lowercase extensions.
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().to_lowercase(),
+        None => return false,
+    };
+    extensions
+        .iter()
+        .any(|extension| name.len() > extension.len() && name.ends_with(extension))
+}
+
+// Turns the metadata of an archive into the one of a directory.
+fn as_dir(stat: Stat) -> Stat {
+    Stat {
+        is_dir: true,
+        #[cfg(unix)]
+        mode: 0o40000 | (stat.mode & 0o7777),
+        ..stat
+    }
+}
+
+// Returns the path of an entry below the archive at `path`. Leading slashes are dropped like
+// tar does, entries leaving the archive are skipped.
+fn entry_path(path: &Path, name: &Path) -> Option<PathBuf> {
+    if name.components().any(|c| c == Component::ParentDir) {
+        log::warn!(
+            "skipped archive entry path={} name={}",
+            path.display(),
+            name.display()
+        );
+        return None;
+    }
+    let name: PathBuf = name
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    match name.as_os_str().is_empty() {
+        true => None,
+        false => Some(path.join(name)),
+    }
+}
+
+// Converts a local time of an archive entry.
+fn local_time(time: NaiveDateTime) -> Option<SystemTime> {
+    Some(time.and_local_timezone(Local).earliest()?.into())
+}
+
+// Reads the headers of a tar stream, entries are added below `path`.
+#[cfg(feature = "tar")]
+fn read_tar(path: &Path, input: impl Read) -> io::Result<MemoryFs> {
+    let mut archive = tar::Archive::new(input);
+    let mut index = MemoryFs::new();
+    index.dir(path);
+    // The data of each entry is skipped when moving on to the next one
+    for file in archive.entries()? {
+        let file = file?;
+        let entry = match entry_path(path, &file.path()?) {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        let header = file.header();
+        let mode = header.mode()?;
+        match header.entry_type() {
+            tar::EntryType::Directory => index.dir(&entry),
+            tar::EntryType::Symlink => match file.link_name()? {
+                Some(target) => index.symlink(&entry, target),
+                None => index.file(&entry, 0),
+            },
+            _ if mode & 0o111 != 0 => index.executable(&entry, header.size()?),
+            _ => index.file(&entry, header.size()?),
+        };
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(header.mtime()?);
+        index
+            .set_permissions(&entry, mode)
+            .set_modified(&entry, modified);
+    }
+    Ok(index)
+}
+
+// Parses the technical listing of `7z l -slt`, entries are added below `path`. The entries
+// follow a line of dashes as blocks of `Key = Value` lines separated by empty lines.
+fn parse_listing(path: &Path, listing: &str) -> MemoryFs {
+    let mut index = MemoryFs::new();
+    index.dir(path);
+    let entries = match listing.split_once("\n----------") {
+        Some((_, entries)) => entries,
+        None => return index,
+    };
+
+    for block in entries.split("\n\n") {
+        let fields: HashMap<&str, &str> = block
+            .lines()
+            .filter_map(|line| line.split_once(" = "))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+        let entry = match fields
+            .get("Path")
+            .and_then(|name| entry_path(path, Path::new(name)))
+        {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        // Attributes are like "D_ drwxr-xr-x" or "A", the unix permissions are optional
+        let attributes = fields.get("Attributes").copied().unwrap_or_default();
+        let mode = attributes.split_whitespace().nth(1).and_then(permissions);
+        let is_dir = match fields.get("Folder") {
+            Some(folder) => *folder == "+",
+            None => attributes.starts_with('D'),
+        };
+        let size = fields
+            .get("Size")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(0);
+        if is_dir {
+            index.dir(&entry);
+        } else if mode.is_some_and(|mode| mode & 0o111 != 0) {
+            index.executable(&entry, size);
+        } else {
+            index.file(&entry, size);
+        }
+        if let Some(mode) = mode {
+            index.set_permissions(&entry, mode);
+        }
+
+        // Times may have fractions of seconds, e.g. "2022-03-01 12:30:00.1234567"
+        let modified = fields
+            .get("Modified")
+            .and_then(|time| time.get(..19))
+            .and_then(|time| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").ok())
+            .and_then(local_time);
+        if let Some(modified) = modified {
+            index.set_modified(&entry, modified);
+        }
+    }
+    index
+}
+
+// Parses permissions like "-rwxr-xr-x", special bits are ignored.
+fn permissions(text: &str) -> Option<u32> {
+    let bits = text.as_bytes();
+    if bits.len() != 10 {
+        return None;
+    }
+    let mut mode = 0;
+    for (i, bit) in bits[1..].iter().enumerate() {
+        match bit {
+            b'-' | b'S' | b'T' => {}
+            b'r' | b'w' | b'x' | b's' | b't' => mode |= 1 << (8 - i),
+            _ => return None,
+        }
+    }
+    Some(mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::temp_dir, tree::TreeNode, Aldar};
+    use std::fs;
+
+    // Returns the names, types and sizes of the entries below the first node named `name`.
+    fn entries(tree: &TreeNode, name: &str) -> Vec<(String, bool, Option<u64>)> {
+        let node = tree.walk().find(|node| node.name == name).unwrap();
+        node.walk()
+            .skip(1)
+            .map(|node| {
+                let size = node.metadata.map(|stat| stat.size);
+                (node.name.clone(), node.is_dir, size)
+            })
+            .collect()
+    }
+
+    // Lists every file named *.list as an archive with a single entry.
+    struct ListProvider;
+
+    impl ArchiveProvider for ListProvider {
+        fn matches(&self, path: &Path) -> bool {
+            has_extension(path, &[".list"])
+        }
+
+        fn read_index(&self, path: &Path) -> io::Result<MemoryFs> {
+            let mut index = MemoryFs::new();
+            index.file(path.join("entry"), 42);
+            Ok(index)
+        }
+    }
+
+    #[test]
+    fn uses_added_providers() {
+        let dir = temp_dir("provider");
+        fs::write(dir.join("files.list"), "").unwrap();
+
+        let tree = Aldar::new()
+            .use_path(dir.display().to_string())
+            .show_archives(true)
+            .add_archive_provider(Box::new(ListProvider))
+            .build_tree();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            entries(&tree.unwrap(), "files.list"),
+            [("entry".to_string(), false, Some(42))]
+        );
+    }
+
+    #[test]
+    fn parses_7z_listing() {
+        let listing = "\
+7-Zip [64] 16.02 : Copyright (c) 1999-2016 Igor Pavlov : 2016-05-21
+
+Listing archive: backup.7z
+
+--
+Path = backup.7z
+Type = 7z
+
+----------
+Path = bin/run
+Size = 120
+Modified = 2022-03-01 12:30:00.1234567
+Attributes = A_ -rwxr-x---
+
+Path = bin
+Size = 0
+Modified = 2022-03-01 12:30:00
+Attributes = D_ drwxr-xr-x
+
+Path = ../escape
+Size = 1
+Attributes = A
+";
+        let index = parse_listing(Path::new("backup.7z"), listing);
+        let run = index.metadata(Path::new("backup.7z/bin/run")).unwrap();
+        assert_eq!(run.size, 120);
+        #[cfg(unix)]
+        assert_eq!(run.mode, 0o100750);
+        assert!(index.metadata(Path::new("backup.7z/bin")).unwrap().is_dir);
+        assert!(index.metadata(Path::new("escape")).is_err());
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn lists_zip_entries() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let dir = temp_dir("zip");
+        let mut writer = zip::ZipWriter::new(File::create(dir.join("backup.zip")).unwrap());
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.add_directory("docs/", options).unwrap();
+        writer.start_file("docs/notes.txt", options).unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.start_file("src/main.rs", options).unwrap();
+        writer.write_all(b"fn main() {}").unwrap();
+        writer.finish().unwrap();
+        fs::write(dir.join("plain.txt"), "plain").unwrap();
+
+        let tree = Aldar::new()
+            .use_path(dir.display().to_string())
+            .show_archives(true)
+            .build_tree();
+        // Archives given as root are expanded without show_archives
+        let root = Aldar::new()
+            .use_path(dir.join("backup.zip").display().to_string())
+            .build_tree();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            entries(&tree.unwrap(), "backup.zip"),
+            [
+                ("docs".to_string(), true, Some(0)),
+                ("notes.txt".to_string(), false, Some(5)),
+                ("src".to_string(), true, Some(0)),
+                ("main.rs".to_string(), false, Some(12)),
+            ]
+        );
+        assert_eq!(root.unwrap().children.len(), 2);
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn lists_tar_entries() {
+        let dir = temp_dir("tar");
+        let file = File::create(dir.join("backup.tar.gz")).unwrap();
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o750);
+        header.set_mtime(86400);
+        builder
+            .append_data(&mut header, "./bin/run", &b"hello"[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let tree = Aldar::new()
+            .use_path(dir.display().to_string())
+            .show_archives(true)
+            .build_tree();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let tree = tree.unwrap();
+        assert_eq!(
+            entries(&tree, "backup.tar.gz"),
+            [
+                ("bin".to_string(), true, Some(0)),
+                ("run".to_string(), false, Some(5)),
+            ]
+        );
+        let run = tree.walk().find(|node| node.name == "run").unwrap();
+        let stat = run.metadata.unwrap();
+        assert_eq!(
+            stat.modified,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(86400)
+        );
+        #[cfg(unix)]
+        assert_eq!(stat.mode & 0o777, 0o750);
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{ffi::OsStr, path::PathBuf};
+
+use crate::{fsutil::Entry, stat::Stat};
+
+/// Compact storage for the entries of a single directory while they are buffered. The entries
+/// share the path of their directory and all names are stored in one buffer, instead of
+/// allocating a full path per entry. Entries are restored one at a time when iterated, so a
+/// directory being listed only holds the entry at hand. Overridden sizes are not kept.
+pub struct EntryArena {
+    dir: PathBuf,
+    names: Vec<u8>,
+    ends: Vec<u32>,
+    stats: Vec<Option<Stat>>,
+    // Empty unless the entries were hashed
+    digests: Vec<Option<String>>,
+    // Link targets already fetched, by index
+    targets: Vec<(u32, Option<Stat>)>,
+}
+
+impl EntryArena {
+    /// Packs the given entries, which must all belong to the same directory.
+    pub fn pack(entries: Vec<Entry>) -> EntryArena {
+        let dir = match entries
+            .first()
+            .and_then(|e| e.path().parent().map(|p| p.to_path_buf()))
+        {
+            Some(dir) => dir,
+            _ => PathBuf::new(),
+        };
+
+        let mut arena = EntryArena {
+            dir,
+            names: vec![],
+            ends: Vec::with_capacity(entries.len()),
+            stats: Vec::with_capacity(entries.len()),
+            digests: vec![],
+            targets: vec![],
+        };
+
+        let hashed = entries.iter().any(|e| e.digest().is_some());
+        for (i, entry) in entries.into_iter().enumerate() {
+            if let Some(target) = entry.fetched_target() {
+                arena.targets.push((i as u32, target));
+            }
+            arena
+                .names
+                .extend_from_slice(entry.file_name().as_encoded_bytes());
+            arena.ends.push(arena.names.len() as u32);
+            arena.stats.push(entry.stat().copied());
+            if hashed {
+                arena.digests.push(entry.digest().map(str::to_owned));
+            }
+        }
+        arena.names.shrink_to_fit();
+
+        arena
+    }
+
+    /// Restores the entries in the order they were packed.
+    pub fn unpack(self) -> Vec<Entry> {
+        self.into_iter().collect()
+    }
+}
+
+impl IntoIterator for EntryArena {
+    type Item = Entry;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            arena: self,
+            next: 0,
+            target: 0,
+        }
+    }
+}
+
+/// Restores the entries of an [`EntryArena`] in the order they were packed.
+pub struct IntoIter {
+    arena: EntryArena,
+    next: usize,
+    // Index into the targets of the next entry with one
+    target: usize,
+}
+
+impl Iterator for IntoIter {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        let arena = &mut self.arena;
+        let i = self.next;
+        let end = *arena.ends.get(i)? as usize;
+        let start = match i {
+            0 => 0,
+            _ => arena.ends[i - 1] as usize,
+        };
+        self.next += 1;
+
+        // The bytes were taken from a whole OsStr in pack.
+        let name = unsafe { OsStr::from_encoded_bytes_unchecked(&arena.names[start..end]) };
+        let mut entry = Entry::with_stat(arena.dir.join(name), arena.stats[i]);
+        if let Some(digest) = arena.digests.get_mut(i) {
+            entry.set_digest(digest.take());
+        }
+        match arena.targets.get(self.target) {
+            Some((index, target)) if *index as usize == i => {
+                self.target += 1;
+                Some(entry.with_target(*target))
+            }
+            _ => Some(entry),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.arena.ends.len() - self.next;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for IntoIter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_entries_one_at_a_time() {
+        let stat = |is_dir, is_symlink| {
+            let mut stat = Stat::of_kind(is_dir);
+            stat.is_symlink = is_symlink;
+            Some(stat)
+        };
+        let mut hashed = Entry::with_stat("dir/a".into(), stat(false, false));
+        hashed.set_digest(Some("ff".to_string()));
+        let link = Entry::with_stat("dir/link".into(), stat(false, true));
+        let link = link.with_target(stat(true, false));
+        let entries = vec![hashed, link, Entry::with_stat("dir/ü".into(), None)];
+
+        let mut iter = EntryArena::pack(entries).into_iter();
+        assert_eq!(iter.len(), 3);
+        let a = iter.next().unwrap();
+        assert_eq!((a.path(), a.digest()), ("dir/a".into(), Some("ff")));
+        let link = iter.next().unwrap();
+        assert!(link.target().is_some_and(|t| t.is_dir));
+        assert_eq!(iter.len(), 1);
+        let last = iter.next().unwrap();
+        assert_eq!((last.path(), last.digest()), ("dir/ü".into(), None));
+        assert!(iter.next().is_none());
+    }
+}
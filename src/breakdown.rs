@@ -0,0 +1,221 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Counts and sizes of the entries of a tree grouped by extension, type and depth, telling what
+//! fills a directory.
+//!
+//! ```no_run
+//! use aldar::{breakdown::Breakdown, Aldar};
+//!
+//! let tree = Aldar::new().use_path(".".to_string()).build_tree().unwrap();
+//! for group in Breakdown::of(&tree).extensions {
+//!     println!("{}: {} files, {} bytes", group.key, group.count, group.bytes);
+//! }
+//! ```
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{tree::TreeNode, units::Units};
+
+/// Key of files without extension.
+pub const NO_EXTENSION: &str = "(none)";
+
+/// Entries sharing a key, e.g. an extension.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Group {
+    pub key: String,
+    /// Entries in the group.
+    pub count: u64,
+    /// Total size of the files in the group.
+    pub bytes: u64,
+}
+
+/// Entries below the roots of a tree in groups, each sorted by size, largest first. Depths are
+/// sorted by depth.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Breakdown {
+    /// Files by lowercase extension.
+    pub extensions: Vec<Group>,
+    /// Entries by type: directory, file, executable or symlink.
+    pub types: Vec<Group>,
+    /// Entries by depth below their root, the entries of a root have depth 1.
+    pub depths: Vec<Group>,
+}
+
+impl Breakdown {
+    /// Groups the entries of a tree built with [`Aldar::build_tree`](crate::Aldar::build_tree).
+    pub fn of(tree: &TreeNode) -> Breakdown {
+        let mut extensions = HashMap::new();
+        let mut types = HashMap::new();
+        let mut depths = HashMap::new();
+        let add = |groups: &mut HashMap<String, Group>, key: String, bytes: u64| {
+            let group = groups.entry(key.clone()).or_insert(Group {
+                key,
+                count: 0,
+                bytes: 0,
+            });
+            group.count += 1;
+            group.bytes += bytes;
+        };
+
+        // Several roots are the children of a node without name
+        let roots = match tree.name.is_empty() {
+            true => tree.children.iter().collect(),
+            false => vec![tree],
+        };
+        let mut stack: Vec<(&TreeNode, usize)> = roots
+            .into_iter()
+            .flat_map(|root| root.children.iter().map(|child| (child, 1)))
+            .collect();
+        while let Some((node, depth)) = stack.pop() {
+            stack.extend(node.children.iter().map(|child| (child, depth + 1)));
+
+            let bytes = match node.is_dir {
+                true => 0,
+                false => node.metadata.as_ref().map_or(0, |stat| stat.size),
+            };
+            add(&mut types, entry_type(node).to_string(), bytes);
+            add(&mut depths, depth.to_string(), bytes);
+            if !node.is_dir {
+                add(&mut extensions, extension(&node.name), bytes);
+            }
+        }
+
+        let sorted = |groups: HashMap<String, Group>| {
+            let mut groups: Vec<Group> = groups.into_values().collect();
+            groups.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.key.cmp(&b.key)));
+            groups
+        };
+        let mut depths: Vec<Group> = depths.into_values().collect();
+        depths.sort_by_key(|group| group.key.parse::<usize>().unwrap_or_default());
+        Breakdown {
+            extensions: sorted(extensions),
+            types: sorted(types),
+            depths,
+        }
+    }
+
+    /// Prints a table of each grouping, sizes in `units`.
+    pub fn write_tables(&self, out: &mut dyn Write, units: Units) -> io::Result<()> {
+        let tables = [
+            ("EXTENSION", &self.extensions),
+            ("TYPE", &self.types),
+            ("DEPTH", &self.depths),
+        ];
+        for (i, (title, groups)) in tables.into_iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            let width = groups
+                .iter()
+                .map(|group| group.key.width())
+                .chain([title.width()])
+                .max()
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{:<width$}  {:>10}  {:>10}",
+                title,
+                "ENTRIES",
+                "SIZE",
+                width = width
+            )?;
+            for group in groups.iter() {
+                // Keys are padded by their display width, format pads by chars
+                let padding = " ".repeat(width - group.key.width());
+                writeln!(
+                    out,
+                    "{}{}  {:>10}  {:>10}",
+                    group.key,
+                    padding,
+                    group.count,
+                    units.format(group.bytes)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn entry_type(node: &TreeNode) -> &'static str {
+    let stat = match node.metadata.as_ref() {
+        Some(stat) => stat,
+        None if node.is_dir => return "directory",
+        None => return "file",
+    };
+    if stat.is_symlink {
+        return "symlink";
+    }
+    if node.is_dir {
+        return "directory";
+    }
+    #[cfg(unix)]
+    if stat.mode & 0o111 != 0 {
+        return "executable";
+    }
+    "file"
+}
+
+// Returns the lowercase extension of a name, dotfiles like .bashrc have none.
+fn extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+            extension.to_lowercase()
+        }
+        _ => NO_EXTENSION.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memfs::MemoryFs, Aldar};
+
+    #[test]
+    fn groups_entries() {
+        let mut fs = MemoryFs::new();
+        fs.file("root/src/main.rs", 300)
+            .file("root/src/lib.RS", 100)
+            .file("root/README.md", 50)
+            .file("root/.gitignore", 10)
+            .file("root/Makefile", 20);
+        let tree = Aldar::new()
+            .use_path("root".to_string())
+            .show_hidden(true)
+            .use_filesystem(Box::new(fs))
+            .build_tree()
+            .unwrap();
+        let breakdown = Breakdown::of(&tree);
+
+        let group = |key: &str, count, bytes| Group {
+            key: key.to_string(),
+            count,
+            bytes,
+        };
+        assert_eq!(
+            breakdown.extensions,
+            [
+                group("rs", 2, 400),
+                group("md", 1, 50),
+                group(NO_EXTENSION, 2, 30)
+            ]
+        );
+        assert_eq!(breakdown.depths, [group("1", 4, 80), group("2", 2, 400)]);
+
+        let mut out = Vec::new();
+        breakdown.write_tables(&mut out, Units::Iec).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out
+            .starts_with("EXTENSION     ENTRIES        SIZE\nrs                  2         400\n"));
+    }
+}
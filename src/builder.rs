@@ -0,0 +1,258 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{
+    cmp::Ordering,
+    io::Write,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use crate::{
+    aldar::{Aldar, Progress},
+    archive::ArchiveProvider,
+    color::Colorizer,
+    compat::Compat,
+    error::AldarError,
+    escape::{Charset, Escaping, Normalization},
+    event::Event,
+    format::{Formatter, ReportFormat},
+    fsutil::{Backend, Entry, FileSystem},
+    icons::Icons,
+    sort::{Grouping, SortKey},
+    units::Units,
+    Glyphs,
+};
+
+/// Consuming builder for [`Aldar`], the configuration is validated once it is built.
+///
+/// ```no_run
+/// use aldar::AldarBuilder;
+///
+/// let mut aldar = AldarBuilder::new()
+///     .use_path("src".to_string())
+///     .show_size(true)
+///     .use_max_level(2)
+///     .build()?;
+/// aldar.run()?;
+/// # Ok::<(), aldar::AldarError>(())
+/// ```
+#[derive(Default)]
+pub struct AldarBuilder {
+    aldar: Aldar,
+    #[cfg(feature = "regex")]
+    include_patterns: Option<Vec<String>>,
+    #[cfg(feature = "regex")]
+    exclude_patterns: Option<Vec<String>>,
+}
+
+// Forwards to the setter of the same name on Aldar.
+macro_rules! forward {
+    ($($(#[$doc:meta])* $name:ident($arg:ident: $ty:ty);)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(mut self, $arg: $ty) -> Self {
+                self.aldar.$name($arg);
+                self
+            }
+        )*
+    };
+}
+
+impl AldarBuilder {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> AldarBuilder {
+        AldarBuilder::default()
+    }
+
+    forward! {
+        /// See [`Aldar::use_writer`].
+        use_writer(writer: Box<dyn Write + Send>);
+        /// See [`Aldar::use_formatter`].
+        use_formatter(formatter: Box<dyn Formatter>);
+        /// See [`Aldar::use_colorizer`].
+        use_colorizer(colorizer: Box<dyn Colorizer>);
+        /// See [`Aldar::use_compat`].
+        use_compat(compat: Compat);
+        /// See [`Aldar::use_path`].
+        use_path(path: String);
+        /// See [`Aldar::use_paths`].
+        use_paths(paths: Vec<String>);
+        /// See [`Aldar::show_hidden`].
+        show_hidden(show_hidden: bool);
+        /// See [`Aldar::show_dirs_only`].
+        show_dirs_only(show_dirs_only: bool);
+        /// See [`Aldar::case_sensitive`].
+        #[cfg(feature = "regex")]
+        case_sensitive(ignore_case: bool);
+        /// See [`Aldar::smart_case`].
+        #[cfg(feature = "regex")]
+        smart_case(smart_case: bool);
+        /// See [`Aldar::use_glyphset`].
+        use_glyphset(glyphs: Box<dyn Glyphs>);
+        /// See [`Aldar::use_max_level`].
+        use_max_level(lvl: i32);
+        /// See [`Aldar::follow_links`].
+        follow_links(follow_links: bool);
+        /// See [`Aldar::breadth_first`].
+        breadth_first(bfs: bool);
+        /// See [`Aldar::skip_remote_fs`].
+        skip_remote_fs(skip_remote_fs: bool);
+        /// See [`Aldar::show_mounts`].
+        show_mounts(show_mounts: bool);
+        /// See [`Aldar::compact_dirs`].
+        compact_dirs(compact: bool);
+        /// See [`Aldar::post_order`].
+        post_order(post_order: bool);
+        /// See [`Aldar::use_threads`].
+        use_threads(threads: usize);
+        /// See [`Aldar::show_perf_stats`].
+        show_perf_stats(perf_stats: bool);
+        /// See [`Aldar::show_report`].
+        show_report(report: bool);
+        /// See [`Aldar::use_report_format`].
+        use_report_format(format: Option<ReportFormat>);
+        /// See [`Aldar::use_max_open_dirs`].
+        use_max_open_dirs(max_open_dirs: usize);
+        /// See [`Aldar::use_backend`].
+        use_backend(backend: Backend);
+        /// See [`Aldar::use_filesystem`].
+        use_filesystem(filesystem: Box<dyn FileSystem>);
+        /// See [`Aldar::show_archives`].
+        show_archives(show_archives: bool);
+        /// See [`Aldar::add_archive_provider`].
+        add_archive_provider(provider: Box<dyn ArchiveProvider>);
+        /// See [`Aldar::show_fullpath`].
+        show_fullpath(show_fullpath: bool);
+        /// See [`Aldar::show_relative_paths`].
+        show_relative_paths(relative_paths: bool);
+        /// See [`Aldar::show_absolute_root`].
+        show_absolute_root(absolute_root: bool);
+        /// See [`Aldar::show_size`].
+        show_size(show_size: bool);
+        /// See [`Aldar::show_date`].
+        show_date(show_date: bool);
+        /// See [`Aldar::show_depth`].
+        show_depth(show_depth: bool);
+        /// See [`Aldar::compute_digests`].
+        #[cfg(feature = "hash")]
+        compute_digests(compute_digests: bool);
+        /// See [`Aldar::use_change_time`].
+        use_change_time(use_ctime: bool);
+        /// See [`Aldar::show_human_readable`].
+        show_human_readable(show_human_readable: bool);
+        /// See [`Aldar::use_units`].
+        use_units(units: Units);
+        /// See [`Aldar::use_digit_separator`].
+        use_digit_separator(separator: Option<String>);
+        /// See [`Aldar::do_replace_nonprintable_chars`].
+        do_replace_nonprintable_chars(replace_nonprintables: bool);
+        /// See [`Aldar::show_hyperlinks`].
+        show_hyperlinks(hyperlinks: bool);
+        /// See [`Aldar::show_icons`].
+        show_icons(icons: bool);
+        /// See [`Aldar::use_icons`].
+        use_icons(icons: Icons);
+        /// See [`Aldar::use_max_width`].
+        use_max_width(width: usize);
+        /// See [`Aldar::use_normalization`].
+        use_normalization(normalization: Normalization);
+        /// See [`Aldar::highlight_matches`].
+        #[cfg(feature = "regex")]
+        highlight_matches(highlight: bool);
+        /// See [`Aldar::use_escaping`].
+        use_escaping(escaping: Escaping);
+        /// See [`Aldar::use_charset`].
+        use_charset(charset: Charset);
+        /// See [`Aldar::show_tracked_only`].
+        #[cfg(feature = "git")]
+        show_tracked_only(tracked_only: bool);
+        /// See [`Aldar::use_sort_keys`].
+        use_sort_keys(keys: &[SortKey]);
+        /// See [`Aldar::sort_ignore_case`].
+        sort_ignore_case(ignore_case: bool);
+        /// See [`Aldar::use_grouping`].
+        use_grouping(grouping: Grouping);
+        /// See [`Aldar::reverse_sort`].
+        reverse_sort(reverse: bool);
+        /// See [`Aldar::no_sort`].
+        no_sort(unsorted: bool);
+    }
+
+    /// See [`Aldar::sort_with`].
+    pub fn sort_with(
+        mut self,
+        comparator: impl Fn(&Entry, &Entry) -> Ordering + Send + Sync + 'static,
+    ) -> Self {
+        self.aldar.sort_with(comparator);
+        self
+    }
+
+    /// See [`Aldar::on_event`].
+    pub fn on_event(mut self, callback: impl FnMut(Event) + Send + 'static) -> Self {
+        self.aldar.on_event(callback);
+        self
+    }
+
+    /// See [`Aldar::on_progress`].
+    pub fn on_progress(mut self, callback: impl FnMut(Progress) + Send + 'static) -> Self {
+        self.aldar.on_progress(callback);
+        self
+    }
+
+    /// See [`Aldar::with_cancellation`].
+    pub fn with_cancellation(mut self, token: Arc<AtomicBool>) -> Self {
+        self.aldar.with_cancellation(token);
+        self
+    }
+
+    /// See [`Aldar::filter_with`].
+    pub fn filter_with(mut self, filter: impl Fn(&Entry) -> bool + Send + Sync + 'static) -> Self {
+        self.aldar.filter_with(filter);
+        self
+    }
+
+    /// See [`Aldar::annotate_with`].
+    pub fn annotate_with(
+        mut self,
+        annotator: impl Fn(&Entry) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.aldar.annotate_with(annotator);
+        self
+    }
+
+    /// Lists only files matching one of the patterns, they are validated by [`build`](Self::build).
+    #[cfg(feature = "regex")]
+    pub fn include_patterns(mut self, patterns: &[&str]) -> Self {
+        self.include_patterns = Some(patterns.iter().map(|p| p.to_string()).collect());
+        self
+    }
+
+    /// Skips files and directories matching one of the patterns, they are validated by
+    /// [`build`](Self::build).
+    #[cfg(feature = "regex")]
+    pub fn exclude_patterns(mut self, patterns: &[&str]) -> Self {
+        self.exclude_patterns = Some(patterns.iter().map(|p| p.to_string()).collect());
+        self
+    }
+
+    /// Validates the configuration and returns the configured [`Aldar`].
+    #[cfg_attr(not(feature = "regex"), allow(unused_mut))]
+    pub fn build(mut self) -> Result<Aldar, AldarError> {
+        #[cfg(feature = "regex")]
+        if let Some(patterns) = self.include_patterns.take() {
+            let patterns: Vec<&str> = patterns.iter().map(String::as_str).collect();
+            self.aldar.set_include_patterns(&patterns)?;
+        }
+
+        #[cfg(feature = "regex")]
+        if let Some(patterns) = self.exclude_patterns.take() {
+            let patterns: Vec<&str> = patterns.iter().map(String::as_str).collect();
+            self.aldar.set_exclude_patterns(&patterns)?;
+        }
+
+        self.aldar.validate()?;
+        Ok(self.aldar)
+    }
+}
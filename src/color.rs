@@ -0,0 +1,502 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+#[cfg(feature = "color")]
+use colored::*;
+#[cfg(feature = "color")]
+use std::{collections::HashMap, env, str::FromStr};
+
+use crate::fsutil::Entry;
+#[cfg(feature = "color")]
+use crate::fsutil::{self, AldarExt};
+
+/// Colors the names printed by the [`TextFormatter`](crate::TextFormatter). Colors are left out
+/// anyway if they are turned off.
+pub trait Colorizer: Send + Sync {
+    /// Colors the name of an entry.
+    fn entry(&self, entry: &Entry, name: &str) -> String;
+
+    /// Colors the name of a directory without entry, like roots or paths read from a list.
+    fn dir(&self, name: &str) -> String {
+        #[cfg(feature = "color")]
+        return name.blue().to_string();
+        #[cfg(not(feature = "color"))]
+        name.to_string()
+    }
+}
+
+/// Highlights directories, executables and hidden files.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultColorizer;
+
+#[cfg(feature = "color")]
+impl Colorizer for DefaultColorizer {
+    fn entry(&self, entry: &Entry, name: &str) -> String {
+        if entry.is_dir() && entry.is_hidden() {
+            name.purple().to_string()
+        } else if entry.is_dir() {
+            name.blue().to_string()
+        } else if entry.is_executable() {
+            name.magenta().to_string()
+        } else if entry.is_hidden() {
+            name.cyan().to_string()
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+// The scheme of `dircolors` without a configuration, its longer extension lists shortened.
+#[cfg(feature = "color")]
+const DIRCOLORS: &str = "rs=0:di=01;34:ln=01;36:mh=00:pi=40;33:so=01;35:do=01;35:bd=40;33;01:\
+    cd=40;33;01:or=40;31;01:mi=00:su=37;41:sg=30;43:ca=00:tw=30;42:ow=34;42:st=37;44:ex=01;32:\
+    *.tar=01;31:*.tgz=01;31:*.zip=01;31:*.gz=01;31:*.bz2=01;31:*.xz=01;31:*.zst=01;31:\
+    *.7z=01;31:*.rar=01;31:*.deb=01;31:*.rpm=01;31:*.jar=01;31:*.jpg=01;35:*.jpeg=01;35:\
+    *.png=01;35:*.gif=01;35:*.bmp=01;35:*.svg=01;35:*.webp=01;35:*.tif=01;35:*.tiff=01;35:\
+    *.mp4=01;35:*.mkv=01;35:*.mp3=00;36:*.flac=00;36:*.ogg=00;36:*.wav=00;36:*.opus=00;36";
+
+/// Colors entries like `ls` does, following the `LS_COLORS` scheme set up by `dircolors`.
+/// Entries are colored by type (`di`, `ln`, `or`, `ex`, `fi`) and regular files also by
+/// extension (`*.tar`).
+#[cfg(feature = "color")]
+#[derive(Clone, Debug, Default)]
+pub struct LsColors {
+    types: HashMap<String, String>,
+    // Suffixes lowercased, matched case insensitive
+    extensions: Vec<(String, String)>,
+}
+
+#[cfg(feature = "color")]
+impl LsColors {
+    /// Parses a `LS_COLORS` value, unknown keys and malformed entries are ignored.
+    pub fn parse(value: &str) -> LsColors {
+        let mut colors = LsColors::default();
+        for (key, code) in value.split(':').filter_map(|e| e.split_once('=')) {
+            match key.strip_prefix('*') {
+                Some(suffix) => colors
+                    .extensions
+                    .push((suffix.to_lowercase(), code.to_owned())),
+                None => {
+                    colors.types.insert(key.to_owned(), code.to_owned());
+                }
+            }
+        }
+        colors
+    }
+
+    /// Reads the scheme from the `LS_COLORS` environment variable, if set.
+    pub fn from_env() -> Option<LsColors> {
+        env::var("LS_COLORS").ok().map(|v| LsColors::parse(&v))
+    }
+
+    /// Returns the scheme `dircolors` sets up without a configuration, as `ls` uses it when
+    /// `LS_COLORS` isn't set.
+    pub fn dircolors() -> LsColors {
+        LsColors::parse(DIRCOLORS)
+    }
+
+    // Returns the SGR code of the entry, if its class has one.
+    fn code(&self, entry: &Entry) -> Option<&str> {
+        let stat = entry.stat()?;
+        if stat.is_symlink {
+            return match (self.types.get("ln").map(String::as_str), entry.target()) {
+                (_, None) => self
+                    .types
+                    .get("or")
+                    .or(self.types.get("ln"))
+                    .map(String::as_str),
+                // Links are colored like their target
+                (Some("target"), Some(_)) => self.code_of_target(entry),
+                (code, Some(_)) => code,
+            };
+        }
+        self.code_of_target(entry)
+    }
+
+    fn code_of_target(&self, entry: &Entry) -> Option<&str> {
+        if entry.target().is_some_and(|s| s.is_dir) {
+            return self.types.get("di").map(String::as_str);
+        }
+        if entry.is_executable() {
+            if let Some(code) = self.types.get("ex") {
+                return Some(code);
+            }
+        }
+
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        // The last matching suffix wins, like in ls
+        self.extensions
+            .iter()
+            .rev()
+            .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+            .map(|(_, code)| code.as_str())
+            .or(self.types.get("fi").map(String::as_str))
+    }
+
+    fn paint(&self, code: Option<&str>, name: &str) -> String {
+        match code {
+            Some(code) if enabled() && !code.is_empty() => format!("\x1b[{}m{}\x1b[0m", code, name),
+            _ => name.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "color")]
+impl Colorizer for LsColors {
+    fn entry(&self, entry: &Entry, name: &str) -> String {
+        self.paint(self.code(entry), name)
+    }
+
+    fn dir(&self, name: &str) -> String {
+        self.paint(self.types.get("di").map(String::as_str), name)
+    }
+}
+
+/// Names of the themes built in, see [`Theme::named`].
+#[cfg(feature = "color")]
+pub const THEMES: &[&str] = &["default", "mono", "vivid"];
+
+/// A color and text styles, parsed from words like `bold bright blue` or `italic #ff8800`.
+#[cfg(feature = "color")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(try_from = "String")
+)]
+pub struct Style {
+    color: Option<Color>,
+    styles: Vec<Styles>,
+}
+
+#[cfg(feature = "color")]
+impl Style {
+    /// Applies the style to `name` if colors are turned on.
+    pub fn paint(&self, name: &str) -> String {
+        let mut painted = match self.color {
+            Some(color) => name.color(color),
+            None => name.normal(),
+        };
+        for style in &self.styles {
+            painted = match style {
+                Styles::Bold => painted.bold(),
+                Styles::Dimmed => painted.dimmed(),
+                Styles::Italic => painted.italic(),
+                Styles::Underline => painted.underline(),
+                Styles::Reversed => painted.reversed(),
+                Styles::Blink => painted.blink(),
+                Styles::Strikethrough => painted.strikethrough(),
+                _ => painted,
+            };
+        }
+        painted.to_string()
+    }
+}
+
+#[cfg(feature = "color")]
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Style, String> {
+        let mut style = Style::default();
+        let mut color = vec![];
+        for word in spec.split_whitespace() {
+            match word.to_lowercase().as_str() {
+                "bold" => style.styles.push(Styles::Bold),
+                "dim" | "dimmed" => style.styles.push(Styles::Dimmed),
+                "italic" => style.styles.push(Styles::Italic),
+                "underline" => style.styles.push(Styles::Underline),
+                "reversed" => style.styles.push(Styles::Reversed),
+                "blink" => style.styles.push(Styles::Blink),
+                "strikethrough" => style.styles.push(Styles::Strikethrough),
+                _ => color.push(word),
+            }
+        }
+
+        // Whatever isn't a style names the color, which may take two words like `bright red`
+        let color = color.join(" ");
+        if let Some(hex) = color.strip_prefix('#') {
+            match u32::from_str_radix(hex, 16) {
+                Ok(rgb) if hex.len() == 6 => {
+                    style.color = Some(Color::TrueColor {
+                        r: (rgb >> 16) as u8,
+                        g: (rgb >> 8) as u8,
+                        b: rgb as u8,
+                    })
+                }
+                _ => return Err(format!("invalid color '{}'", color)),
+            }
+        } else if !color.is_empty() {
+            style.color =
+                Some(Color::from_str(&color).map_err(|_| format!("unknown color '{}'", color))?);
+        }
+        Ok(style)
+    }
+}
+
+#[cfg(feature = "color")]
+impl TryFrom<String> for Style {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Style, String> {
+        spec.parse()
+    }
+}
+
+/// Colors entries by class, configurable per class and extension. Classes without a style are
+/// left uncolored.
+#[cfg(feature = "color")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Theme {
+    /// Directories, also used for roots.
+    pub dir: Option<Style>,
+    /// Hidden directories, colored like other directories if not set.
+    pub hidden_dir: Option<Style>,
+    /// Hidden files.
+    pub hidden: Option<Style>,
+    /// Executable files.
+    pub executable: Option<Style>,
+    /// Symbolic links, whatever they point to.
+    pub symlink: Option<Style>,
+    /// Files by extension, given without the leading dot and matched case insensitive. The
+    /// longest matching extension wins, so `tar.gz` can be set apart from `gz`.
+    pub extensions: HashMap<String, Style>,
+}
+
+#[cfg(feature = "color")]
+impl Theme {
+    /// Returns one of the [`THEMES`] built in.
+    pub fn named(name: &str) -> Option<Theme> {
+        let style = |spec: &str| Some(spec.parse::<Style>().unwrap());
+        let theme = match name {
+            // Same colors as the DefaultColorizer
+            "default" => Theme {
+                dir: style("blue"),
+                hidden_dir: style("purple"),
+                hidden: style("cyan"),
+                executable: style("magenta"),
+                ..Theme::default()
+            },
+            "mono" => Theme {
+                dir: style("bold"),
+                hidden_dir: style("bold dimmed"),
+                hidden: style("dimmed"),
+                executable: style("underline"),
+                symlink: style("italic"),
+                ..Theme::default()
+            },
+            "vivid" => {
+                let mut extensions = HashMap::new();
+                for ext in ["tar", "gz", "xz", "zst", "zip", "7z"] {
+                    extensions.insert(ext.to_string(), style("bright red").unwrap());
+                }
+                for ext in ["png", "jpg", "jpeg", "gif", "svg", "webp"] {
+                    extensions.insert(ext.to_string(), style("bright magenta").unwrap());
+                }
+                for ext in ["md", "txt", "rst"] {
+                    extensions.insert(ext.to_string(), style("yellow").unwrap());
+                }
+                Theme {
+                    dir: style("bold bright blue"),
+                    hidden_dir: style("bold magenta"),
+                    hidden: style("bright black"),
+                    executable: style("bold bright green"),
+                    symlink: style("bright cyan"),
+                    extensions,
+                }
+            }
+            _ => return None,
+        };
+        Some(theme)
+    }
+
+    fn style(&self, entry: &Entry) -> Option<&Style> {
+        if entry.stat().is_some_and(|s| s.is_symlink) && self.symlink.is_some() {
+            return self.symlink.as_ref();
+        }
+        if entry.is_dir() {
+            return match entry.is_hidden() {
+                true => self.hidden_dir.as_ref().or(self.dir.as_ref()),
+                false => self.dir.as_ref(),
+            };
+        }
+        if entry.is_executable() && self.executable.is_some() {
+            return self.executable.as_ref();
+        }
+
+        let name = entry.file_name();
+        match fsutil::longest_extension(&name.to_string_lossy(), &self.extensions) {
+            Some(style) => Some(style),
+            None if entry.is_hidden() => self.hidden.as_ref(),
+            None => None,
+        }
+    }
+}
+
+#[cfg(feature = "color")]
+impl Colorizer for Theme {
+    fn entry(&self, entry: &Entry, name: &str) -> String {
+        match self.style(entry) {
+            Some(style) => style.paint(name),
+            None => name.to_string(),
+        }
+    }
+
+    fn dir(&self, name: &str) -> String {
+        match &self.dir {
+            Some(style) => style.paint(name),
+            None => name.to_string(),
+        }
+    }
+}
+
+// Without the color feature there are no colors to turn on.
+#[cfg(not(feature = "color"))]
+impl Colorizer for DefaultColorizer {
+    fn entry(&self, _: &Entry, name: &str) -> String {
+        name.to_string()
+    }
+}
+
+/// Leaves all names uncolored, independent of whether colors are turned on.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlainColorizer;
+
+impl Colorizer for PlainColorizer {
+    fn entry(&self, _: &Entry, name: &str) -> String {
+        name.to_string()
+    }
+
+    fn dir(&self, name: &str) -> String {
+        name.to_string()
+    }
+}
+
+/// Returns true if names are colored, which is never the case without the color feature.
+pub(crate) fn enabled() -> bool {
+    #[cfg(feature = "color")]
+    return colored::control::SHOULD_COLORIZE.should_colorize();
+    #[cfg(not(feature = "color"))]
+    false
+}
+
+#[cfg(all(test, feature = "color"))]
+mod tests {
+    use super::*;
+    use crate::{fsutil::FileSystem, memfs::MemoryFs, stat::Fields};
+
+    fn codes(ls_colors: &str) -> Vec<(String, Option<String>)> {
+        let mut fs = MemoryFs::new();
+        fs.dir("root/src")
+            .file("root/a.TAR", 1)
+            .file("root/b.txt", 1)
+            .executable("root/run.sh", 1)
+            .symlink("root/link", "src")
+            .symlink("root/orphan", "missing");
+
+        let colors = LsColors::parse(ls_colors);
+        let entries = fs.read_dir("root".as_ref(), false, Fields::ALL).unwrap();
+        entries
+            .into_iter()
+            .map(|e| {
+                let e = e.unwrap();
+                let name = e.file_name().to_string_lossy().into_owned();
+                (name, colors.code(&e).map(str::to_owned))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn colors_by_type_and_extension() {
+        let codes = codes("di=01;34:ln=01;36:or=31:ex=01;32:fi=0:*.tar=01;31:*.sh=33");
+        let code = |name: &str| codes.iter().find(|(n, _)| n == name).unwrap().1.clone();
+
+        assert_eq!(code("src").as_deref(), Some("01;34"));
+        assert_eq!(code("a.TAR").as_deref(), Some("01;31"));
+        assert_eq!(code("b.txt").as_deref(), Some("0"));
+        // Executables take precedence over their extension
+        #[cfg(unix)]
+        assert_eq!(code("run.sh").as_deref(), Some("01;32"));
+        assert_eq!(code("link").as_deref(), Some("01;36"));
+        assert_eq!(code("orphan").as_deref(), Some("31"));
+    }
+
+    #[test]
+    fn defaults_like_dircolors() {
+        let codes = codes(DIRCOLORS);
+        let code = |name: &str| codes.iter().find(|(n, _)| n == name).unwrap().1.clone();
+
+        assert_eq!(code("src").as_deref(), Some("01;34"));
+        assert_eq!(code("a.TAR").as_deref(), Some("01;31"));
+        assert_eq!(code("b.txt"), None);
+        assert_eq!(code("orphan").as_deref(), Some("40;31;01"));
+    }
+
+    #[test]
+    fn links_colored_like_their_target() {
+        let codes = codes("di=01;34:ln=target");
+        let link = codes.iter().find(|(n, _)| n == "link").unwrap();
+        assert_eq!(link.1.as_deref(), Some("01;34"));
+    }
+
+    #[test]
+    fn parses_styles() {
+        let style: Style = "bold Bright Blue underline".parse().unwrap();
+        assert_eq!(style.color, Some(Color::BrightBlue));
+        assert_eq!(style.styles, vec![Styles::Bold, Styles::Underline]);
+
+        let style: Style = "#ff8800".parse().unwrap();
+        assert_eq!(
+            style.color,
+            Some(Color::TrueColor {
+                r: 255,
+                g: 136,
+                b: 0
+            })
+        );
+
+        assert!("bold pink".parse::<Style>().is_err());
+        assert!("#ff88".parse::<Style>().is_err());
+    }
+
+    #[test]
+    fn themes_style_by_class_and_extension() {
+        let mut fs = MemoryFs::new();
+        fs.dir("root/src")
+            .dir("root/.git")
+            .file("root/a.tar.gz", 1)
+            .file("root/b.gz", 1)
+            .file("root/.env", 1)
+            .file("root/gz", 1)
+            .symlink("root/link", "src");
+
+        let mut theme = Theme::named("default").unwrap();
+        theme.symlink = Some("italic".parse().unwrap());
+        theme.extensions.insert("gz".into(), "red".parse().unwrap());
+        theme
+            .extensions
+            .insert("TAR.GZ".into(), "green".parse().unwrap());
+
+        let entries = fs.read_dir("root".as_ref(), false, Fields::ALL).unwrap();
+        let style = |name: &str| {
+            let entry = entries
+                .iter()
+                .flatten()
+                .find(|e| e.file_name() == name)
+                .unwrap();
+            theme.style(entry).map(|s| s.to_owned())
+        };
+        let parse = |spec: &str| Some(spec.parse::<Style>().unwrap());
+
+        assert_eq!(style("src"), parse("blue"));
+        assert_eq!(style(".git"), parse("purple"));
+        assert_eq!(style("a.tar.gz"), parse("green"));
+        assert_eq!(style("b.gz"), parse("red"));
+        assert_eq!(style(".env"), parse("cyan"));
+        assert_eq!(style("gz"), None);
+        assert_eq!(style("link"), parse("italic"));
+        assert!(Theme::named("unknown").is_none());
+    }
+}
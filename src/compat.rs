@@ -0,0 +1,690 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Compatibility with the command line and output of other tools, so aldar can be dropped into
+//! scripts written for them.
+
+use chrono::{DateTime, Local};
+#[cfg(feature = "cli")]
+use clap::ArgEnum;
+#[cfg(feature = "cli")]
+use std::ffi::OsString;
+use std::{
+    io::{self, Write},
+    time::{Duration, SystemTime},
+};
+
+use crate::format::{Formatter, Item, Root, Summary};
+
+// Age from which tree prints the year instead of the time of a date.
+const HALF_YEAR: Duration = Duration::from_secs(182 * 24 * 60 * 60);
+
+/// Tool whose output conventions are followed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ArgEnum))]
+pub enum Compat {
+    /// Aldar's own output.
+    #[default]
+    None,
+    /// GNU tree: info columns share one bracket, sizes and dates are formatted like tree does,
+    /// symbolic links show their target and the report reads `1 directory, 2 files`.
+    Tree,
+}
+
+/// Structured output of GNU tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ArgEnum))]
+pub enum Markup {
+    /// JSON like `tree -J` prints it.
+    Json,
+    /// XML like `tree -X` prints it.
+    Xml,
+}
+
+/// Writes the tree as JSON or XML with the element and field names of GNU tree. Entries have to
+/// arrive in depth first order with directories before their contents, as aldar lists them by
+/// default.
+pub struct MarkupFormatter {
+    markup: Markup,
+    size: bool,
+    date: bool,
+    change_time: bool,
+    started: bool,
+    // Directories begun but not closed yet, the root is the first
+    open: usize,
+    // Whether nothing was written into the innermost open directory yet
+    empty: bool,
+}
+
+impl MarkupFormatter {
+    /// Creates a formatter printing names and types only.
+    pub fn new(markup: Markup) -> MarkupFormatter {
+        MarkupFormatter {
+            markup,
+            size: false,
+            date: false,
+            change_time: false,
+            started: false,
+            open: 0,
+            empty: true,
+        }
+    }
+
+    /// Adds the size of entries, as tree does with `-s`.
+    pub fn with_size(mut self, size: bool) -> MarkupFormatter {
+        self.size = size;
+        self
+    }
+
+    /// Adds the modification time of entries, as tree does with `-D`, or the change time with
+    /// `change_time`.
+    pub fn with_date(mut self, date: bool, change_time: bool) -> MarkupFormatter {
+        self.date = date;
+        self.change_time = change_time;
+        self
+    }
+
+    // Starts an element at `depth`, separated from the one before.
+    fn open_element(&mut self, out: &mut dyn Write, depth: usize) -> io::Result<()> {
+        let separator = match (self.markup, self.empty) {
+            (Markup::Json, false) => ",\n",
+            _ => "\n",
+        };
+        self.empty = false;
+        write!(out, "{}{}", separator, "  ".repeat(depth + 1))
+    }
+
+    // Closes directories until `depth` of them are open.
+    fn close(&mut self, out: &mut dyn Write, depth: usize) -> io::Result<()> {
+        while self.open > depth {
+            let indent = "  ".repeat(self.open);
+            match self.markup {
+                Markup::Json => write!(out, "\n{}]}}", indent)?,
+                Markup::Xml => write!(out, "\n{}</directory>", indent)?,
+            }
+            self.open -= 1;
+            self.empty = false;
+        }
+        Ok(())
+    }
+
+    // Writes a directory at `depth` and opens its contents.
+    fn directory(
+        &mut self,
+        out: &mut dyn Write,
+        depth: usize,
+        name: &str,
+        fields: &[(&str, String)],
+        error: Option<&str>,
+    ) -> io::Result<()> {
+        self.open_element(out, depth)?;
+        self.write_start(out, "directory", name, fields)?;
+        match self.markup {
+            Markup::Json => write!(out, ",\"contents\":[")?,
+            Markup::Xml => write!(out, ">")?,
+        }
+        self.open = depth + 1;
+        self.empty = true;
+
+        if let Some(error) = error {
+            self.open_element(out, depth + 1)?;
+            match self.markup {
+                Markup::Json => write!(out, "{{\"error\":{}}}", json_string(error))?,
+                Markup::Xml => write!(out, "<error>{}</error>", xml_text(error))?,
+            }
+        }
+        Ok(())
+    }
+
+    // Writes a file or link at `depth`.
+    fn leaf(
+        &mut self,
+        out: &mut dyn Write,
+        depth: usize,
+        kind: &str,
+        name: &str,
+        fields: &[(&str, String)],
+    ) -> io::Result<()> {
+        self.open_element(out, depth)?;
+        self.write_start(out, kind, name, fields)?;
+        match self.markup {
+            Markup::Json => write!(out, "}}"),
+            Markup::Xml => write!(out, "></{}>", kind),
+        }
+    }
+
+    // Writes the type, name and fields of an element, which is left open for its contents.
+    fn write_start(
+        &self,
+        out: &mut dyn Write,
+        kind: &str,
+        name: &str,
+        fields: &[(&str, String)],
+    ) -> io::Result<()> {
+        match self.markup {
+            Markup::Json => {
+                write!(
+                    out,
+                    "{{\"type\":\"{}\",\"name\":{}",
+                    kind,
+                    json_string(name)
+                )?;
+                for (field, value) in fields {
+                    match *field {
+                        "size" => write!(out, ",\"{}\":{}", field, value)?,
+                        _ => write!(out, ",\"{}\":{}", field, json_string(value))?,
+                    }
+                }
+            }
+            Markup::Xml => {
+                write!(out, "<{} name={}", kind, xml_attribute(name))?;
+                for (field, value) in fields {
+                    write!(out, " {}={}", field, xml_attribute(value))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the header before the first root.
+    fn start(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            match self.markup {
+                Markup::Json => write!(out, "[")?,
+                Markup::Xml => write!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tree>")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Formatter for MarkupFormatter {
+    fn begin(&mut self, out: &mut dyn Write, root: &Root) -> io::Result<()> {
+        self.start(out)?;
+        self.close(out, 0)?;
+        // tree only tells that opening the directory failed
+        let error = root.error.map(|_| "opening dir");
+        self.directory(out, 0, root.name, &[], error)
+    }
+
+    fn entry(&mut self, out: &mut dyn Write, item: &Item) -> io::Result<()> {
+        self.close(out, item.depth)?;
+        let stat = item.entry.and_then(|entry| entry.stat());
+        let mut fields = vec![];
+        if let Some(stat) = stat.filter(|_| self.size) {
+            fields.push(("size", stat.size.to_string()));
+        }
+        if let Some(stat) = stat.filter(|_| self.date) {
+            let time = match self.change_time {
+                true => stat.changed,
+                false => stat.modified,
+            };
+            fields.push(("time", tree_date(time)));
+        }
+
+        let target = item.notes.iter().find_map(|note| note.strip_prefix("-> "));
+        if let Some(target) = target.filter(|_| stat.is_some_and(|s| s.is_symlink)) {
+            fields.insert(0, ("target", target.to_string()));
+            return self.leaf(out, item.depth, "link", item.name, &fields);
+        }
+
+        if item.is_dir {
+            let error = item
+                .notes
+                .iter()
+                .find_map(|note| note.strip_prefix("[error ")?.strip_suffix(']'));
+            return self.directory(out, item.depth, item.name, &fields, error);
+        }
+
+        self.leaf(out, item.depth, "file", item.name, &fields)
+    }
+
+    fn end(&mut self, out: &mut dyn Write, summary: &Summary) -> io::Result<()> {
+        self.start(out)?;
+        self.close(out, 0)?;
+        match self.markup {
+            Markup::Json => {
+                write!(
+                    out,
+                    "\n,\n  {{\"type\":\"report\",\"directories\":{}",
+                    summary.dirs
+                )?;
+                if !summary.dirs_only {
+                    write!(out, ",\"files\":{}", summary.files)?;
+                }
+                write!(out, "}}")
+            }
+            Markup::Xml => {
+                write!(
+                    out,
+                    "\n  <report>\n    <directories>{}</directories>",
+                    summary.dirs
+                )?;
+                if !summary.dirs_only {
+                    write!(out, "\n    <files>{}</files>", summary.files)?;
+                }
+                write!(out, "\n  </report>")
+            }
+        }
+    }
+
+    // A free text line would break the markup, so the report keeps its structure.
+    fn summary(&mut self, out: &mut dyn Write, summary: &Summary, _line: &str) -> io::Result<()> {
+        self.end(out, summary)
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.start(out)?;
+        self.close(out, 0)?;
+        self.started = false;
+        match self.markup {
+            Markup::Json => writeln!(out, "\n]"),
+            Markup::Xml => writeln!(out, "\n</tree>"),
+        }
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn xml_text(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn xml_attribute(value: &str) -> String {
+    format!("\"{}\"", xml_text(value).replace('"', "&quot;"))
+}
+
+/// Formats a date like tree does, with the year instead of the time for dates older than half a
+/// year or in the future.
+pub(crate) fn tree_date(time: SystemTime) -> String {
+    let date = DateTime::<Local>::from(time);
+    let old = SystemTime::now()
+        .duration_since(time)
+        .map_or(true, |age| age > HALF_YEAR);
+    match old {
+        true => date.format("%b %e  %Y").to_string(),
+        false => date.format("%b %e %H:%M").to_string(),
+    }
+}
+
+// GNU tree flags taking a value, given either attached or as the next argument.
+#[cfg(feature = "cli")]
+const TREE_SHORT_VALUES: &str = "LPIoHT";
+
+/// Maps the flags of GNU tree to the ones of aldar if `--compat tree` is given, the arguments
+/// are returned as they are otherwise. Wildcard patterns of `-P` and `-I` are turned into
+/// regular expressions, flags aldar has no equivalent for are an error.
+#[cfg(feature = "cli")]
+pub fn translate_args(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    let requested = args
+        .windows(2)
+        .any(|w| w[0] == "--compat" && w[1] == "tree")
+        || args.iter().any(|a| a == "--compat=tree");
+    if !requested {
+        return Ok(args);
+    }
+
+    let mut args = args.into_iter().peekable();
+    let mut mapped: Vec<OsString> = args.next().into_iter().collect();
+    // The subcommand stays in front, everything else is a path after the flags
+    if let Some(command) = args.next_if(|a| a == "tree") {
+        mapped.push(command);
+    }
+    let mut flags: Vec<String> = vec!["--compat".into(), "tree".into()];
+    let mut dirs_first = false;
+    let mut files_first = false;
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let arg = match arg.into_string() {
+            Ok(arg) if arg.starts_with('-') && arg.len() > 1 => arg,
+            // Neither a flag nor valid UTF-8, only paths remain
+            Ok(arg) => {
+                paths.push(arg.into());
+                continue;
+            }
+            Err(arg) => {
+                paths.push(arg);
+                continue;
+            }
+        };
+
+        if arg == "--" {
+            paths.extend(args.by_ref());
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let mut value = || match value.clone() {
+                Some(value) => Ok(value),
+                None => next_value(&mut args, &arg),
+            };
+            match name {
+                "compat" => {
+                    value()?;
+                }
+                "help" | "version" | "noreport" => flags.push(arg.clone()),
+                "si" => flags.extend(["-s".into(), "--si".into()]),
+                "dirsfirst" => dirs_first = true,
+                "filesfirst" => {
+                    files_first = true;
+                    flags.push("--files-first".into())
+                }
+                "ignore-case" => flags.push("-i".into()),
+                "charset" => {
+                    let charset = value()?.to_lowercase();
+                    let charset = match charset.as_str() {
+                        "utf8" => "utf-8",
+                        "iso-8859-1" | "iso8859-1" | "latin-1" => "latin1",
+                        "us-ascii" | "ansi_x3.4-1968" => "ascii",
+                        charset => charset,
+                    };
+                    flags.extend(["--charset".into(), charset.into()]);
+                }
+                "sort" => {
+                    let key = match value()?.as_str() {
+                        "name" => "name",
+                        "version" => "version",
+                        "size" => "size",
+                        "mtime" => "mtime",
+                        "ctime" => "ctime",
+                        "none" => {
+                            flags.push("-U".into());
+                            continue;
+                        }
+                        key => return Err(format!("unknown sort key {} of GNU tree", key)),
+                    };
+                    flags.extend(["--sort".into(), key.into()]);
+                }
+                _ => return Err(unsupported(&format!("--{}", name))),
+            }
+            continue;
+        }
+
+        let shorts: Vec<char> = arg[1..].chars().collect();
+        for (i, flag) in shorts.iter().enumerate() {
+            if TREE_SHORT_VALUES.contains(*flag) {
+                let rest: String = shorts[i + 1..].iter().collect();
+                let value = match rest.is_empty() {
+                    true => next_value(&mut args, &format!("-{}", flag))?,
+                    false => rest,
+                };
+                match flag {
+                    // Levels of tree count from one, the ones of aldar from zero
+                    'L' => match value.parse::<i32>() {
+                        Ok(level) if level > 0 => {
+                            flags.extend(["-L".into(), (level - 1).to_string()])
+                        }
+                        _ => {
+                            return Err(format!("invalid level {}, must be greater than 0", value))
+                        }
+                    },
+                    'P' => flags.extend(["-I".into(), wildcard_to_regex(&value)]),
+                    'I' => flags.extend(["-E".into(), wildcard_to_regex(&value)]),
+                    'o' => flags.extend(["-o".into(), value]),
+                    _ => return Err(unsupported(&format!("-{}", flag))),
+                }
+                break;
+            }
+
+            match flag {
+                'a' | 'd' | 'l' | 'f' | 'q' | 'Q' | 's' | 'D' | 't' | 'c' | 'U' | 'r' | 'V' => {
+                    flags.push(format!("-{}", flag))
+                }
+                'h' => flags.extend(["-s".into(), "-H".into()]),
+                'v' => flags.extend(["--sort".into(), "version".into()]),
+                'n' => flags.extend(["--color".into(), "never".into()]),
+                'J' => flags.extend(["--markup".into(), "json".into()]),
+                'X' => flags.extend(["--markup".into(), "xml".into()]),
+                'C' => flags.extend(["--color".into(), "always".into()]),
+                // Printing names as they are and line drawing characters are the default
+                'N' | 'A' => {}
+                _ => return Err(unsupported(&format!("-{}", flag))),
+            }
+        }
+    }
+
+    // tree lists files and directories mixed unless told otherwise
+    if !dirs_first && !files_first {
+        flags.push("--dirs-first=false".into());
+    }
+    mapped.extend(flags.into_iter().map(OsString::from));
+    mapped.extend(paths);
+    Ok(mapped)
+}
+
+#[cfg(feature = "cli")]
+fn next_value(args: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<String, String> {
+    match args.next().map(OsString::into_string) {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(_)) => Err(format!("value of {} is not valid UTF-8", flag)),
+        None => Err(format!("{} requires a value", flag)),
+    }
+}
+
+#[cfg(feature = "cli")]
+fn unsupported(flag: &str) -> String {
+    format!("{} of GNU tree is not supported", flag)
+}
+
+/// Turns a wildcard pattern of GNU tree into a regular expression matching a whole name, also
+/// as last component of a path. Besides `*`, `?` and `[...]` alternatives are separated by `|`.
+pub fn wildcard_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("(?:^|/)(?:");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '|' => regex.push('|'),
+            '[' => {
+                regex.push('[');
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == '\\' {
+                        regex.push_str("\\\\");
+                        continue;
+                    }
+                    regex.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => push_literal(&mut regex, c),
+                None => regex.push_str("\\\\"),
+            },
+            c => push_literal(&mut regex, c),
+        }
+    }
+    regex.push_str(")$");
+    regex
+}
+
+fn push_literal(regex: &mut String, c: char) {
+    if "\\.+*?()|[]{}^$#&-~".contains(c) {
+        regex.push('\\');
+    }
+    regex.push(c);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_wildcards() {
+        assert_eq!(
+            wildcard_to_regex("*.rs|*.toml"),
+            r"(?:^|/)(?:[^/]*\.rs|[^/]*\.toml)$"
+        );
+        assert_eq!(
+            wildcard_to_regex("file?[!0-9]"),
+            r"(?:^|/)(?:file[^/][^0-9])$"
+        );
+        assert_eq!(wildcard_to_regex(r"a\*b"), r"(?:^|/)(?:a\*b)$");
+    }
+
+    #[test]
+    fn writes_json_and_xml_like_tree() {
+        let render = |markup| {
+            let mut out = Vec::new();
+            let mut formatter = MarkupFormatter::new(markup);
+            let item = |name, is_dir, depth| Item {
+                entry: None,
+                name,
+                is_dir,
+                depth,
+                prefix: "",
+                columns: "",
+                notes: &[],
+                link: None,
+                icon: None,
+                highlight: None,
+            };
+            let root = Root {
+                name: ".",
+                error: None,
+                link: None,
+            };
+            formatter.begin(&mut out, &root).unwrap();
+            for item in [
+                item("src", true, 1),
+                item("a \"b\".rs", false, 2),
+                item("empty", true, 2),
+                item("Cargo.toml", false, 1),
+            ] {
+                formatter.entry(&mut out, &item).unwrap();
+            }
+            let summary = Summary {
+                dirs: 2,
+                files: 2,
+                ..Summary::default()
+            };
+            formatter.end(&mut out, &summary).unwrap();
+            formatter.finish(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            render(Markup::Json),
+            r#"[
+  {"type":"directory","name":".","contents":[
+    {"type":"directory","name":"src","contents":[
+      {"type":"file","name":"a \"b\".rs"},
+      {"type":"directory","name":"empty","contents":[
+      ]}
+    ]},
+    {"type":"file","name":"Cargo.toml"}
+  ]}
+,
+  {"type":"report","directories":2,"files":2}
+]
+"#
+        );
+        assert_eq!(
+            render(Markup::Xml),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<tree>
+  <directory name=".">
+    <directory name="src">
+      <file name="a &quot;b&quot;.rs"></file>
+      <directory name="empty">
+      </directory>
+    </directory>
+    <file name="Cargo.toml"></file>
+  </directory>
+  <report>
+    <directories>2</directories>
+    <files>2</files>
+  </report>
+</tree>
+"#
+        );
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn maps_tree_flags() {
+        let args = |args: &[&str]| {
+            let args = args.iter().map(OsString::from).collect();
+            translate_args(args).map(|a| a.into_iter().map(|a| a.into_string().unwrap()))
+        };
+
+        let mapped: Vec<String> = args(&[
+            "tree",
+            "--compat",
+            "tree",
+            "-ahL",
+            "2",
+            "-I",
+            "*.o",
+            "--dirsfirst",
+            "src",
+        ])
+        .unwrap()
+        .collect();
+        assert_eq!(
+            mapped,
+            [
+                "tree",
+                "--compat",
+                "tree",
+                "-a",
+                "-s",
+                "-H",
+                "-L",
+                "1",
+                "-E",
+                r"(?:^|/)(?:[^/]*\.o)$",
+                "src"
+            ]
+        );
+
+        let unchanged: Vec<String> = args(&["aldar", "-I", "x"]).unwrap().collect();
+        assert_eq!(unchanged, ["aldar", "-I", "x"]);
+        assert!(args(&["tree", "--compat=tree", "-Jx"]).is_err());
+
+        let mapped: Vec<String> = args(&["aldar", "--compat", "tree", "--filesfirst", "-J"])
+            .unwrap()
+            .collect();
+        assert_eq!(
+            mapped,
+            [
+                "aldar",
+                "--compat",
+                "tree",
+                "--files-first",
+                "--markup",
+                "json"
+            ]
+        );
+    }
+}
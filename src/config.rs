@@ -0,0 +1,276 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Defaults read from a TOML file, shared by a team e.g. through a dotfiles repository.
+//!
+//! ```toml
+//! glyphs = "ascii"
+//! exclude = ["^target$", "^node_modules$"]
+//! sort = ["dirs-first", "version"]
+//! reverse = false
+//! size = "human"
+//!
+//! [colors]
+//! enabled = true
+//! theme = "vivid"
+//! dir = "bold blue"
+//! executable = "green"
+//!
+//! [colors.extensions]
+//! rs = "bright red"
+//! "tar.gz" = "italic #ff8800"
+//!
+//! [icons]
+//! enabled = true
+//! dir = "📁"
+//!
+//! [icons.names]
+//! "justfile" = "🤖"
+//! ```
+
+use serde::Deserialize;
+use std::{collections::HashMap, fs, io, path::Path};
+
+use crate::{
+    color::{Style, Theme},
+    error::AldarError,
+    fsutil,
+    icons::Icons,
+    sort::SortKey,
+};
+
+/// Location of the config file read if no other one is given.
+pub const DEFAULT_PATH: &str = "~/.config/aldar/config.toml";
+
+/// Settings of a config file, those left out keep their default.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether and how names are colored.
+    #[serde(default)]
+    pub colors: ColorsConfig,
+    /// Whether and which icons are printed before names.
+    #[serde(default)]
+    pub icons: IconsConfig,
+    /// Glyphs used for the indentation lines.
+    pub glyphs: Option<GlyphStyle>,
+    /// Patterns of files and directories which are not listed.
+    pub exclude: Option<Vec<String>>,
+    /// Keys the entries of a directory are sorted by.
+    pub sort: Option<Vec<SortKey>>,
+    /// Whether directories are listed before files.
+    pub dirs_first: Option<bool>,
+    /// Whether the sort order is reversed.
+    pub reverse: Option<bool>,
+    /// How sizes are printed.
+    pub size: Option<SizeUnits>,
+}
+
+/// The `[colors]` section, styles given here override those of the theme.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ColorsConfig {
+    /// Whether names are colored.
+    pub enabled: Option<bool>,
+    /// One of the [`THEMES`](crate::color::THEMES) built in.
+    pub theme: Option<String>,
+    /// Style of directories.
+    pub dir: Option<Style>,
+    /// Style of hidden directories.
+    pub hidden_dir: Option<Style>,
+    /// Style of hidden files.
+    pub hidden: Option<Style>,
+    /// Style of executable files.
+    pub executable: Option<Style>,
+    /// Style of symbolic links.
+    pub symlink: Option<Style>,
+    /// Styles of files by extension.
+    #[serde(default)]
+    pub extensions: HashMap<String, Style>,
+}
+
+impl ColorsConfig {
+    /// Returns true if any style is set.
+    pub fn has_styles(&self) -> bool {
+        self.dir.is_some()
+            || self.hidden_dir.is_some()
+            || self.hidden.is_some()
+            || self.executable.is_some()
+            || self.symlink.is_some()
+            || !self.extensions.is_empty()
+    }
+
+    /// Overrides the styles of `theme` with the ones set.
+    pub fn apply(&self, theme: &mut Theme) {
+        let classes = [
+            (&mut theme.dir, &self.dir),
+            (&mut theme.hidden_dir, &self.hidden_dir),
+            (&mut theme.hidden, &self.hidden),
+            (&mut theme.executable, &self.executable),
+            (&mut theme.symlink, &self.symlink),
+        ];
+        for (style, configured) in classes {
+            if configured.is_some() {
+                style.clone_from(configured);
+            }
+        }
+        theme.extensions.extend(self.extensions.clone());
+    }
+}
+
+/// The `[icons]` section, icons given here override the default ones.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct IconsConfig {
+    /// Whether icons are printed.
+    pub enabled: Option<bool>,
+    /// Icon of directories.
+    pub dir: Option<String>,
+    /// Icon of files.
+    pub file: Option<String>,
+    /// Icon of symbolic links.
+    pub symlink: Option<String>,
+    /// Icon of executable files.
+    pub executable: Option<String>,
+    /// Icons of directories and files by name.
+    #[serde(default)]
+    pub names: HashMap<String, String>,
+    /// Icons of files by extension.
+    #[serde(default)]
+    pub extensions: HashMap<String, String>,
+}
+
+impl IconsConfig {
+    /// Overrides the icons of `icons` with the ones set.
+    pub fn apply(&self, icons: &mut Icons) {
+        let classes = [
+            (&mut icons.dir, &self.dir),
+            (&mut icons.file, &self.file),
+            (&mut icons.symlink, &self.symlink),
+            (&mut icons.executable, &self.executable),
+        ];
+        for (icon, configured) in classes {
+            if let Some(configured) = configured {
+                icon.clone_from(configured);
+            }
+        }
+        icons.names.extend(self.names.clone());
+        icons.extensions.extend(self.extensions.clone());
+    }
+}
+
+/// Glyphs used for the indentation lines.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GlyphStyle {
+    Unicode,
+    Ascii,
+}
+
+/// How sizes are printed.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizeUnits {
+    /// Plain number of bytes
+    Bytes,
+    /// Scaled to KiB, MiB, GiB and so on
+    Human,
+    /// Scaled to kB, MB, GB and so on
+    Si,
+}
+
+impl Config {
+    /// Reads the config file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, AldarError> {
+        let path = fsutil::expand_home(path.as_ref());
+        let content = fs::read_to_string(&path).map_err(|source| AldarError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        Config::parse(&content).map_err(|source| AldarError::Config {
+            path,
+            source: Box::new(source),
+        })
+    }
+
+    /// Reads the config file at [`DEFAULT_PATH`], an empty config is returned if there is none.
+    pub fn load_default() -> Result<Config, AldarError> {
+        match Config::load(DEFAULT_PATH) {
+            Err(AldarError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+                Ok(Config::default())
+            }
+            result => result,
+        }
+    }
+
+    /// Parses the contents of a config file.
+    pub fn parse(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_all_settings() {
+        let config = Config::parse(
+            r#"
+            glyphs = "ascii"
+            exclude = ["^target$"]
+            sort = ["dirs-first", "version"]
+            dirs-first = true
+            reverse = true
+            size = "human"
+
+            [colors]
+            enabled = false
+            theme = "mono"
+            dir = "bold blue"
+
+            [colors.extensions]
+            rs = "red"
+
+            [icons]
+            enabled = true
+            dir = "D"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                colors: ColorsConfig {
+                    enabled: Some(false),
+                    theme: Some("mono".to_string()),
+                    dir: Some("bold blue".parse().unwrap()),
+                    extensions: HashMap::from([("rs".to_string(), "red".parse().unwrap())]),
+                    ..ColorsConfig::default()
+                },
+                icons: IconsConfig {
+                    enabled: Some(true),
+                    dir: Some("D".to_string()),
+                    ..IconsConfig::default()
+                },
+                glyphs: Some(GlyphStyle::Ascii),
+                exclude: Some(vec!["^target$".to_string()]),
+                sort: Some(vec![SortKey::DirsFirst, SortKey::Version]),
+                dirs_first: Some(true),
+                reverse: Some(true),
+                size: Some(SizeUnits::Human),
+            }
+        );
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(Config::parse("colour = true").is_err());
+        assert!(Config::parse("glyphs = \"fancy\"").is_err());
+        assert!(Config::parse("[colors]\ndir = \"sky blue\"").is_err());
+    }
+}
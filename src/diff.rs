@@ -0,0 +1,443 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Comparison of two directory trees, rendered as one tree marking what was added, removed or
+//! modified.
+//!
+//! ```no_run
+//! use aldar::{diff::{Criterion, DiffNode}, Aldar, UNICODE_GLYPHSET};
+//!
+//! let before = Aldar::new().use_path("v1".to_string()).build_tree().unwrap();
+//! let after = Aldar::new().use_path("v2".to_string()).build_tree().unwrap();
+//! let diff = DiffNode::compare(&before, &after, &[Criterion::Size]);
+//! diff.write_tree(&mut std::io::stdout(), &UNICODE_GLYPHSET, false).unwrap();
+//! ```
+
+#[cfg(feature = "cli")]
+use clap::ArgEnum;
+#[cfg(feature = "color")]
+use colored::Colorize;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{aldar::Glyphs, tree::TreeNode};
+
+/// What files are compared by, files equal in all criteria are unchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ArgEnum))]
+pub enum Criterion {
+    Size,
+    /// Modification time, which differs between copies unless they preserve it.
+    Mtime,
+    /// SHA-256 digest of the content, files are read only if their sizes are equal.
+    #[cfg(feature = "hash")]
+    Hash,
+}
+
+/// How an entry differs between the trees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Status {
+    Unchanged,
+    /// Only in the second tree.
+    Added,
+    /// Only in the first tree.
+    Removed,
+    /// A file differing in one of the criteria.
+    Modified,
+}
+
+/// An entry of either tree, directories hold the entries of both.
+#[derive(Clone, Debug)]
+pub struct DiffNode {
+    pub name: String,
+    /// Path relative to the roots, empty for the roots.
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub status: Status,
+    /// Size in the first tree, if the entry is a file there.
+    pub size_before: Option<u64>,
+    /// Size in the second tree, if the entry is a file there.
+    pub size_after: Option<u64>,
+    pub children: Vec<DiffNode>,
+}
+
+/// A changed entry, as listed for automation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Difference {
+    pub path: PathBuf,
+    pub status: Status,
+    pub is_dir: bool,
+    pub size_before: Option<u64>,
+    pub size_after: Option<u64>,
+}
+
+/// Counts of changed entries, entries below added or removed directories included.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DiffSummary {
+    pub added: u64,
+    pub removed: u64,
+    pub modified: u64,
+}
+
+impl DiffNode {
+    /// Compares the tree `after` to the tree `before`, entries are matched by name. The root is
+    /// named after both roots, its entries follow the order of `after` with removed entries
+    /// after the ones they followed in `before`. An entry which changed between file and
+    /// directory is removed and added.
+    pub fn compare(before: &TreeNode, after: &TreeNode, criteria: &[Criterion]) -> DiffNode {
+        let mut root = DiffNode::new(
+            format!("{} → {}", before.name, after.name),
+            PathBuf::new(),
+            true,
+            Status::Unchanged,
+        );
+        root.children = compare_children(before, after, Path::new(""), criteria);
+        root
+    }
+
+    fn new(name: String, path: PathBuf, is_dir: bool, status: Status) -> DiffNode {
+        DiffNode {
+            name,
+            path,
+            is_dir,
+            status,
+            size_before: None,
+            size_after: None,
+            children: vec![],
+        }
+    }
+
+    // Creates the node of an entry only in one tree, with all its descendants.
+    fn one_sided(node: &TreeNode, parent: &Path, status: Status) -> DiffNode {
+        let path = parent.join(&node.name);
+        let mut diff = DiffNode::new(node.name.clone(), path.clone(), node.is_dir, status);
+        let size = file_size(node);
+        match status {
+            Status::Removed => diff.size_before = size,
+            _ => diff.size_after = size,
+        }
+        diff.children = node
+            .children
+            .iter()
+            .map(|child| DiffNode::one_sided(child, &path, status))
+            .collect();
+        diff
+    }
+
+    /// Iterates over this node and all its descendants in depth first order.
+    pub fn walk(&self) -> impl Iterator<Item = &DiffNode> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+
+    /// Returns true if this entry or one of its descendants changed.
+    pub fn has_changes(&self) -> bool {
+        self.walk().any(|node| node.status != Status::Unchanged)
+    }
+
+    /// Lists the changed entries in depth first order.
+    pub fn differences(&self) -> Vec<Difference> {
+        self.walk()
+            .filter(|node| node.status != Status::Unchanged)
+            .map(|node| Difference {
+                path: node.path.clone(),
+                status: node.status,
+                is_dir: node.is_dir,
+                size_before: node.size_before,
+                size_after: node.size_after,
+            })
+            .collect()
+    }
+
+    /// Counts the changed entries.
+    pub fn summary(&self) -> DiffSummary {
+        let mut summary = DiffSummary::default();
+        for node in self.walk() {
+            match node.status {
+                Status::Unchanged => {}
+                Status::Added => summary.added += 1,
+                Status::Removed => summary.removed += 1,
+                Status::Modified => summary.modified += 1,
+            }
+        }
+        summary
+    }
+
+    /// Prints the tree with markers after changed entries and the counts below it. With
+    /// `changes_only` unchanged entries are left out unless they contain changes.
+    pub fn write_tree(
+        &self,
+        out: &mut dyn Write,
+        glyphs: &dyn Glyphs,
+        changes_only: bool,
+    ) -> io::Result<()> {
+        writeln!(out, "{}", self.name)?;
+        self.write_children(out, glyphs, changes_only, "")?;
+
+        let summary = self.summary();
+        writeln!(
+            out,
+            "\n{} added, {} removed, {} modified",
+            summary.added, summary.removed, summary.modified
+        )
+    }
+
+    fn write_children(
+        &self,
+        out: &mut dyn Write,
+        glyphs: &dyn Glyphs,
+        changes_only: bool,
+        indent: &str,
+    ) -> io::Result<()> {
+        let children: Vec<&DiffNode> = self
+            .children
+            .iter()
+            .filter(|child| !changes_only || child.has_changes())
+            .collect();
+
+        let (pipe, item, last) = (glyphs.pipe(), glyphs.item(), glyphs.last());
+        for (i, child) in children.iter().enumerate() {
+            let is_last = i + 1 == children.len();
+            write!(
+                out,
+                "{}{} {}",
+                indent,
+                if is_last { &last } else { &item },
+                child.name
+            )?;
+            match (child.status, child.size_before, child.size_after) {
+                (Status::Unchanged, _, _) => writeln!(out)?,
+                (Status::Modified, Some(before), Some(after)) if before != after => {
+                    writeln!(out, " {} {} → {}", marker(child.status), before, after)?
+                }
+                (status, _, _) => writeln!(out, " {}", marker(status))?,
+            }
+
+            let indent = match is_last {
+                true => format!("{}{}", indent, " ".repeat(last.width() + 1)),
+                false => {
+                    let padding = (item.width() + 1).saturating_sub(pipe.width());
+                    format!("{}{}{}", indent, pipe, " ".repeat(padding))
+                }
+            };
+            child.write_children(out, glyphs, changes_only, &indent)?;
+        }
+        Ok(())
+    }
+}
+
+fn compare_children(
+    before: &TreeNode,
+    after: &TreeNode,
+    parent: &Path,
+    criteria: &[Criterion],
+) -> Vec<DiffNode> {
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    for (i, child) in after.children.iter().enumerate() {
+        positions.insert(&child.name, i);
+    }
+
+    // Removed entries are sorted after the last entry before them which is in both trees
+    let mut nodes: Vec<((usize, usize), DiffNode)> = vec![];
+    let mut previous = 0;
+    for (i, old) in before.children.iter().enumerate() {
+        match positions.get(old.name.as_str()) {
+            Some(&pos) if after.children[pos].is_dir == old.is_dir => previous = pos + 1,
+            _ => nodes.push((
+                (previous, i + 1),
+                DiffNode::one_sided(old, parent, Status::Removed),
+            )),
+        }
+    }
+
+    let by_name: HashMap<&str, &TreeNode> = before
+        .children
+        .iter()
+        .map(|c| (c.name.as_str(), c))
+        .collect();
+    for (i, new) in after.children.iter().enumerate() {
+        let node = match by_name.get(new.name.as_str()) {
+            Some(old) if old.is_dir == new.is_dir => compare_entry(old, new, parent, criteria),
+            _ => DiffNode::one_sided(new, parent, Status::Added),
+        };
+        nodes.push(((i + 1, 0), node));
+    }
+
+    nodes.sort_by_key(|(key, _)| *key);
+    nodes.into_iter().map(|(_, node)| node).collect()
+}
+
+// Compares an entry found in both trees.
+fn compare_entry(
+    before: &TreeNode,
+    after: &TreeNode,
+    parent: &Path,
+    criteria: &[Criterion],
+) -> DiffNode {
+    let path = parent.join(&after.name);
+    let mut node = DiffNode::new(
+        after.name.clone(),
+        path.clone(),
+        after.is_dir,
+        Status::Unchanged,
+    );
+    if after.is_dir {
+        node.children = compare_children(before, after, &path, criteria);
+        return node;
+    }
+
+    node.size_before = file_size(before);
+    node.size_after = file_size(after);
+    if criteria.iter().any(|c| differs(before, after, *c)) {
+        node.status = Status::Modified;
+    }
+    node
+}
+
+fn differs(before: &TreeNode, after: &TreeNode, criterion: Criterion) -> bool {
+    let (old, new) = match (before.metadata.as_ref(), after.metadata.as_ref()) {
+        (Some(old), Some(new)) => (old, new),
+        // Entries which couldn't be read are compared by name only
+        _ => return false,
+    };
+
+    match criterion {
+        Criterion::Size => old.size != new.size,
+        Criterion::Mtime => old.modified != new.modified,
+        #[cfg(feature = "hash")]
+        Criterion::Hash if old.size != new.size => true,
+        #[cfg(feature = "hash")]
+        Criterion::Hash => {
+            // Digests of the tree are used, e.g. of a snapshot, files are read otherwise
+            let digest = |node: &TreeNode| match node.digest.clone() {
+                Some(digest) => Some(digest),
+                None => match crate::hash::sha256_file(&node.path) {
+                    Ok(digest) => Some(digest),
+                    Err(e) => {
+                        log::warn!("not hashed path={} error={}", node.path.display(), e);
+                        None
+                    }
+                },
+            };
+            digest(before) != digest(after)
+        }
+    }
+}
+
+fn file_size(node: &TreeNode) -> Option<u64> {
+    match node.is_dir {
+        true => None,
+        false => node.metadata.as_ref().map(|stat| stat.size),
+    }
+}
+
+// Marks a change like the watch subcommand does.
+fn marker(status: Status) -> String {
+    let marker = match status {
+        Status::Unchanged => "",
+        Status::Added => "[+]",
+        Status::Removed => "[-]",
+        Status::Modified => "[~]",
+    };
+    #[cfg(feature = "color")]
+    return match status {
+        Status::Unchanged => String::new(),
+        Status::Added => marker.green().bold().to_string(),
+        Status::Removed => marker.red().bold().to_string(),
+        Status::Modified => marker.yellow().bold().to_string(),
+    };
+    #[cfg(not(feature = "color"))]
+    marker.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memfs::MemoryFs, Aldar, ASCII_GLYPHSET};
+
+    fn tree(fs: &MemoryFs, root: &str) -> TreeNode {
+        Aldar::new()
+            .use_path(root.to_string())
+            .use_filesystem(Box::new(fs.clone()))
+            .build_tree()
+            .unwrap()
+    }
+
+    fn render(diff: &DiffNode, changes_only: bool) -> String {
+        #[cfg(feature = "color")]
+        colored::control::set_override(false);
+        let mut out = Vec::new();
+        diff.write_tree(&mut out, &ASCII_GLYPHSET, changes_only)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn marks_changes() {
+        let mut fs = MemoryFs::new();
+        fs.file("a/gone", 5)
+            .file("a/old/x", 1)
+            .file("a/same", 3)
+            .file("a/size", 4)
+            .file("b/new", 1)
+            .file("b/same", 3)
+            .file("b/size", 6);
+
+        let diff = DiffNode::compare(&tree(&fs, "a"), &tree(&fs, "b"), &[Criterion::Size]);
+        assert_eq!(
+            render(&diff, false),
+            "a → b\n\
+             |-- old [-]\n\
+             |   `-- x [-]\n\
+             |-- gone [-]\n\
+             |-- new [+]\n\
+             |-- same\n\
+             `-- size [~] 4 → 6\n\
+             \n\
+             1 added, 3 removed, 1 modified\n"
+        );
+        assert_eq!(
+            diff.summary(),
+            DiffSummary {
+                added: 1,
+                removed: 3,
+                modified: 1
+            }
+        );
+        assert!(!render(&diff, true).contains("same"));
+        let paths: Vec<PathBuf> = diff.differences().into_iter().map(|d| d.path).collect();
+        assert_eq!(
+            paths,
+            ["old", "old/x", "gone", "new", "size"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn changed_type_is_removed_and_added() {
+        let mut fs = MemoryFs::new();
+        fs.file("a/x", 1).dir("b/x");
+
+        let diff = DiffNode::compare(&tree(&fs, "a"), &tree(&fs, "b"), &[Criterion::Size]);
+        let statuses: Vec<Status> = diff.children.iter().map(|c| c.status).collect();
+        assert_eq!(statuses, [Status::Removed, Status::Added]);
+    }
+}
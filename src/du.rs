@@ -0,0 +1,263 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Disk usage of a tree: sizes of directories are summed up from their files and the heaviest
+//! entries are printed with bars of their share, like `dust` does.
+//!
+//! ```no_run
+//! use aldar::{du::DiskUsage, units::Units, Aldar, UNICODE_GLYPHSET};
+//!
+//! let tree = Aldar::new().use_path("target".to_string()).build_tree().unwrap();
+//! let usage = DiskUsage::of(&tree);
+//! usage
+//!     .write_top(&mut std::io::stdout(), 10, false, &UNICODE_GLYPHSET, Units::Iec)
+//!     .unwrap();
+//! ```
+
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{aldar::Glyphs, tree::TreeNode, units::Units};
+
+// Characters wide the bars of the share are.
+const BAR_WIDTH: usize = 20;
+
+/// An entry with the size of its files, directories with the sum of their entries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Apparent size in bytes, symbolic links count with their own size.
+    pub size: u64,
+    /// Files in it, 1 for files.
+    pub files: u64,
+    /// Entries sorted heaviest first.
+    pub children: Vec<DiskUsage>,
+}
+
+impl DiskUsage {
+    /// Sums up the sizes of a tree built with [`Aldar::build_tree`](crate::Aldar::build_tree).
+    pub fn of(tree: &TreeNode) -> DiskUsage {
+        let mut children: Vec<DiskUsage> = tree.children.iter().map(DiskUsage::of).collect();
+        children.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+
+        // The node holding several roots is no directory either
+        let (size, files) = match (tree.is_dir, tree.metadata.as_ref()) {
+            (false, Some(stat)) => (stat.size, 1),
+            _ => children
+                .iter()
+                .fold((0, 0), |(size, files), c| (size + c.size, files + c.files)),
+        };
+        DiskUsage {
+            name: tree.name.clone(),
+            path: tree.path.clone(),
+            is_dir: tree.is_dir,
+            size,
+            files,
+            children,
+        }
+    }
+
+    /// Iterates over this entry and all its descendants in depth first order.
+    pub fn walk(&self) -> impl Iterator<Item = &DiskUsage> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let usage = stack.pop()?;
+            stack.extend(usage.children.iter().rev());
+            Some(usage)
+        })
+    }
+
+    /// Drops the entries more than `depth` levels below this one, their sizes stay counted.
+    pub fn truncate(&mut self, depth: usize) {
+        match depth {
+            0 => self.children.clear(),
+            depth => {
+                for child in &mut self.children {
+                    child.truncate(depth - 1);
+                }
+            }
+        }
+    }
+
+    /// Returns the paths of the `n` heaviest entries below this one, only directories with
+    /// `dirs_only`.
+    pub fn heaviest(&self, n: usize, dirs_only: bool) -> HashSet<&Path> {
+        let mut entries: Vec<&DiskUsage> = self
+            .walk()
+            .skip(1)
+            .filter(|usage| usage.is_dir || !dirs_only)
+            .collect();
+        entries.sort_by_key(|usage| std::cmp::Reverse(usage.size));
+        entries
+            .into_iter()
+            .take(n)
+            .map(|usage| usage.path.as_path())
+            .collect()
+    }
+
+    /// Prints the `n` heaviest entries as a tree, with the directories containing them, their
+    /// size and a bar of their share of this entry. Sizes are printed in `units`, the total
+    /// follows the tree. Several roots are printed below a line named total.
+    pub fn write_top(
+        &self,
+        out: &mut dyn Write,
+        n: usize,
+        dirs_only: bool,
+        glyphs: &dyn Glyphs,
+        units: Units,
+    ) -> io::Result<()> {
+        let heaviest = self.heaviest(n, dirs_only);
+        let mut shown = HashSet::new();
+        self.collect_shown(&heaviest, &mut shown);
+        let name = match self.name.is_empty() {
+            true => "total",
+            false => &self.name,
+        };
+        self.write_line(out, self.size, units, glyphs.item().is_ascii(), name)?;
+        self.write_children(out, &shown, glyphs, units, "", self.size)?;
+        writeln!(out, "\n{} in {} files", units.format(self.size), self.files)
+    }
+
+    // Collects the paths of the entries that are among the heaviest or contain one of them,
+    // returns true if this one is.
+    fn collect_shown<'a>(
+        &'a self,
+        heaviest: &HashSet<&Path>,
+        shown: &mut HashSet<&'a Path>,
+    ) -> bool {
+        let mut show = heaviest.contains(self.path.as_path());
+        for child in &self.children {
+            show |= child.collect_shown(heaviest, shown);
+        }
+        if show {
+            shown.insert(self.path.as_path());
+        }
+        show
+    }
+
+    fn write_children(
+        &self,
+        out: &mut dyn Write,
+        shown: &HashSet<&Path>,
+        glyphs: &dyn Glyphs,
+        units: Units,
+        indent: &str,
+        total: u64,
+    ) -> io::Result<()> {
+        let children: Vec<&DiskUsage> = self
+            .children
+            .iter()
+            .filter(|child| shown.contains(child.path.as_path()))
+            .collect();
+
+        let (pipe, item, last) = (glyphs.pipe(), glyphs.item(), glyphs.last());
+        for (i, child) in children.iter().enumerate() {
+            let is_last = i + 1 == children.len();
+            let glyph = if is_last { &last } else { &item };
+            let name = format!("{}{} {}", indent, glyph, child.name);
+            child.write_line(out, total, units, item.is_ascii(), &name)?;
+
+            let indent = match is_last {
+                true => format!("{}{}", indent, " ".repeat(last.width() + 1)),
+                false => {
+                    let padding = (item.width() + 1).saturating_sub(pipe.width());
+                    format!("{}{}{}", indent, pipe, " ".repeat(padding))
+                }
+            };
+            child.write_children(out, shown, glyphs, units, &indent, total)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(
+        &self,
+        out: &mut dyn Write,
+        total: u64,
+        units: Units,
+        ascii: bool,
+        name: &str,
+    ) -> io::Result<()> {
+        let share = match total {
+            0 => 0.0,
+            total => self.size as f64 / total as f64,
+        };
+        writeln!(
+            out,
+            "{:>width$} {} {:>3.0}% {}",
+            units.format(self.size),
+            bar(share, ascii),
+            share * 100.0,
+            name,
+            width = units.width()
+        )
+    }
+}
+
+// Draws a bar filled to `share` of its width.
+fn bar(share: f64, ascii: bool) -> String {
+    let filled = ((share * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+    let (full, empty) = match ascii {
+        true => ("#", "."),
+        false => ("█", "░"),
+    };
+    format!(
+        "{}{}",
+        full.repeat(filled),
+        empty.repeat(BAR_WIDTH - filled)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memfs::MemoryFs, Aldar, ASCII_GLYPHSET};
+
+    fn usage() -> DiskUsage {
+        let mut fs = MemoryFs::new();
+        fs.file("root/big/a", 600)
+            .file("root/big/b", 200)
+            .file("root/small/c", 100)
+            .file("root/d", 100);
+        let tree = Aldar::new()
+            .use_path("root".to_string())
+            .use_filesystem(Box::new(fs))
+            .build_tree()
+            .unwrap();
+        DiskUsage::of(&tree)
+    }
+
+    #[test]
+    fn sums_sizes_bottom_up() {
+        let usage = usage();
+        assert_eq!(usage.size, 1000);
+        let sizes: Vec<(&str, u64)> = usage
+            .children
+            .iter()
+            .map(|child| (child.name.as_str(), child.size))
+            .collect();
+        assert_eq!(sizes, [("big", 800), ("d", 100), ("small", 100)]);
+    }
+
+    #[test]
+    fn prints_heaviest_entries() {
+        let mut out = Vec::new();
+        usage()
+            .write_top(&mut out, 2, false, &ASCII_GLYPHSET, Units::Iec)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "      1000 #################### 100% root\n       \
+                800 ################....  80% `-- big\n       \
+                600 ############........  60%     `-- a\n\
+             \n1000 in 4 files\n"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Detection of files with the same content. Files are grouped by size first, only files
+//! sharing their size with another one are read to compare their digests.
+//!
+//! ```no_run
+//! use aldar::{dupes, Aldar};
+//!
+//! let tree = Aldar::new().use_path("photos".to_string()).build_tree().unwrap();
+//! for group in dupes::find(&tree) {
+//!     println!("{} bytes reclaimable: {:?}", group.reclaimable(), group.paths);
+//! }
+//! ```
+
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use crate::{fsutil, hash, tree::TreeNode};
+
+/// Files with the same content.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// Size of each file.
+    pub size: u64,
+    /// Hex encoded SHA-256 digest of the content.
+    pub digest: String,
+    /// Paths of the files in the order they are listed in the tree.
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// Returns the bytes freed by keeping a single copy.
+    pub fn reclaimable(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// Finds the files of a tree having the same content, in groups of at least two files sorted by
+/// the space they waste. Empty files, symbolic links, further hard links to a listed file and
+/// files which can't be read are skipped. Digests of the tree are used, files without one are read on the rayon thread pool.
+pub fn find(tree: &TreeNode) -> Vec<DuplicateGroup> {
+    let mut by_size: HashMap<u64, Vec<&TreeNode>> = HashMap::new();
+    for node in tree.walk().filter(|node| !node.is_dir) {
+        match node.metadata.as_ref() {
+            Some(stat) if stat.size > 0 && !stat.is_symlink => {
+                by_size.entry(stat.size).or_default().push(node)
+            }
+            _ => {}
+        }
+    }
+
+    // Hard links share their space, only the first one listed counts
+    let mut seen = HashSet::new();
+    let candidates: Vec<(u64, &TreeNode)> = by_size
+        .into_iter()
+        .filter(|(_, nodes)| nodes.len() > 1)
+        .flat_map(|(size, nodes)| nodes.into_iter().map(move |node| (size, node)))
+        .filter(|(_, node)| fsutil::file_id(&node.path).is_none_or(|id| seen.insert(id)))
+        .collect();
+    let digests: Vec<(u64, String, &TreeNode)> = candidates
+        .into_par_iter()
+        .filter_map(|(size, node)| {
+            let digest = match node.digest.clone() {
+                Some(digest) => digest,
+                None => match hash::sha256_file(&node.path) {
+                    Ok(digest) => digest,
+                    Err(e) => {
+                        log::warn!("not hashed path={} error={}", node.path.display(), e);
+                        return None;
+                    }
+                },
+            };
+            Some((size, digest, node))
+        })
+        .collect();
+
+    // Paths keep the order of the tree
+    let order: HashMap<&PathBuf, usize> = tree
+        .walk()
+        .enumerate()
+        .map(|(i, node)| (&node.path, i))
+        .collect();
+    let mut groups: HashMap<(u64, String), Vec<&PathBuf>> = HashMap::new();
+    for (size, digest, node) in digests {
+        groups.entry((size, digest)).or_default().push(&node.path);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = groups
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((size, digest), mut paths)| {
+            paths.sort_by_key(|path| order.get(path));
+            DuplicateGroup {
+                size,
+                digest,
+                paths: paths.into_iter().cloned().collect(),
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.reclaimable()
+            .cmp(&a.reclaimable())
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::fs;
+
+    #[test]
+    fn groups_equal_files() {
+        let dir = temp_dir("dupes");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a"), "same").unwrap();
+        fs::write(dir.join("sub/b"), "same").unwrap();
+        fs::write(dir.join("c"), "diff").unwrap();
+        fs::write(dir.join("d"), "longer").unwrap();
+        fs::write(dir.join("empty"), "").unwrap();
+        fs::write(dir.join("sub/empty"), "").unwrap();
+        fs::hard_link(dir.join("d"), dir.join("e")).unwrap();
+
+        let tree = crate::Aldar::new()
+            .use_path(dir.display().to_string())
+            .build_tree()
+            .unwrap();
+        let groups = find(&tree);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].paths, [dir.join("sub/b"), dir.join("a")]);
+        assert_eq!(groups[0].size, 4);
+        assert_eq!(groups[0].reclaimable(), 4);
+    }
+}
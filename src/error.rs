@@ -0,0 +1,98 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{error::Error, fmt, io, path::PathBuf};
+
+/// Errors returned by [`Aldar`](crate::Aldar).
+#[derive(Debug)]
+pub enum AldarError {
+    /// An include or exclude pattern is not a valid regular expression.
+    #[cfg(feature = "regex")]
+    InvalidPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    /// A configured root does not exist.
+    RootNotFound(PathBuf),
+    /// Accessing a path failed.
+    Io { path: PathBuf, source: io::Error },
+    /// Writing the output failed.
+    Write(io::Error),
+    /// The git index of the repository containing the root or a revision could not be read.
+    Git {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// A config file is not valid TOML or contains unknown settings.
+    #[cfg(feature = "config")]
+    Config {
+        path: PathBuf,
+        source: Box<toml::de::Error>,
+    },
+    /// A snapshot file is not valid JSON or of an unsupported version.
+    #[cfg(feature = "snapshot")]
+    Snapshot {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The selected backend is not part of this build.
+    UnsupportedBackend,
+    /// The thread pool could not be created.
+    ThreadPool(rayon::ThreadPoolBuildError),
+    /// The scan was aborted through its cancellation token.
+    Cancelled,
+    /// Entries or directories could not be read, they are annotated in the output.
+    Traversal { errors: Vec<AldarError> },
+}
+
+impl fmt::Display for AldarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "regex")]
+            AldarError::InvalidPattern { pattern, source } => {
+                write!(f, "invalid pattern '{}': {}", pattern, source)
+            }
+            AldarError::RootNotFound(path) => write!(f, "{} does not exist", path.display()),
+            AldarError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            AldarError::Write(e) => write!(f, "failed to write output: {}", e),
+            AldarError::Git { source, .. } => write!(f, "{}", source),
+            #[cfg(feature = "config")]
+            AldarError::Config { path, source } => {
+                write!(f, "invalid config {}: {}", path.display(), source)
+            }
+            #[cfg(feature = "snapshot")]
+            AldarError::Snapshot { path, source } => {
+                write!(f, "invalid snapshot {}: {}", path.display(), source)
+            }
+            AldarError::UnsupportedBackend => {
+                write!(f, "the selected backend is not supported by this build")
+            }
+            AldarError::ThreadPool(e) => write!(f, "failed to create thread pool: {}", e),
+            AldarError::Cancelled => write!(f, "the scan was cancelled"),
+            AldarError::Traversal { errors } => {
+                write!(f, "{} errors occurred while traversing", errors.len())
+            }
+        }
+    }
+}
+
+impl Error for AldarError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "regex")]
+            AldarError::InvalidPattern { source, .. } => Some(source),
+            AldarError::Io { source, .. } => Some(source),
+            AldarError::Write(e) => Some(e),
+            AldarError::Git { source, .. } => Some(source.as_ref()),
+            #[cfg(feature = "config")]
+            AldarError::Config { source, .. } => Some(source.as_ref()),
+            #[cfg(feature = "snapshot")]
+            AldarError::Snapshot { source, .. } => Some(source.as_ref()),
+            AldarError::ThreadPool(e) => Some(e),
+            AldarError::Traversal { errors } => errors.first().map(|e| e as _),
+            _ => None,
+        }
+    }
+}
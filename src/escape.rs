@@ -0,0 +1,246 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Makes names safe to print, from replacing control characters to quoting them for a shell,
+//! fits them into the character set of the output and normalizes them.
+
+#[cfg(feature = "cli")]
+use clap::ArgEnum;
+use std::{borrow::Cow, char::REPLACEMENT_CHARACTER, fmt::Write};
+use unicode_normalization::{is_nfc_quick, is_nfd_quick, IsNormalized, UnicodeNormalization};
+
+/// How names are escaped before they are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Escaping {
+    /// Names are printed as they are.
+    #[default]
+    None,
+    /// Control characters are replaced with [`REPLACEMENT_CHARACTER`].
+    Replace,
+    /// Names with spaces, quotes, shell or control characters are quoted, so they can be pasted
+    /// into a POSIX shell. Control characters are written as `$'...'` escapes.
+    Quote,
+}
+
+/// Unicode normalization form names are brought into, so names written on macOS (mostly NFD)
+/// and Linux (mostly NFC) print, sort and match alike.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ArgEnum))]
+pub enum Normalization {
+    /// Names are kept as they are.
+    #[default]
+    None,
+    /// Canonical composition, `é` is one character.
+    Nfc,
+    /// Canonical decomposition, `é` is an `e` followed by a combining accent.
+    Nfd,
+}
+
+impl Normalization {
+    /// Normalizes `name`, it is borrowed if it already is.
+    pub fn apply(self, name: &str) -> Cow<'_, str> {
+        match self {
+            Normalization::None => Cow::Borrowed(name),
+            Normalization::Nfc if is_nfc_quick(name.chars()) == IsNormalized::Yes => {
+                Cow::Borrowed(name)
+            }
+            Normalization::Nfc => Cow::Owned(name.nfc().collect()),
+            Normalization::Nfd if is_nfd_quick(name.chars()) == IsNormalized::Yes => {
+                Cow::Borrowed(name)
+            }
+            Normalization::Nfd => Cow::Owned(name.nfd().collect()),
+        }
+    }
+}
+
+/// Character set of the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ArgEnum))]
+pub enum Charset {
+    /// Names are printed as they are.
+    #[default]
+    #[cfg_attr(feature = "cli", clap(name = "utf-8"))]
+    Utf8,
+    /// Names are transliterated to ASCII, e.g. `Größe` to `Grosse`.
+    Ascii,
+    /// The output is encoded as ISO-8859-1, names are transliterated where Latin-1 has no
+    /// character.
+    Latin1,
+}
+
+impl Charset {
+    fn contains(self, c: char) -> bool {
+        match self {
+            Charset::Utf8 => true,
+            Charset::Ascii => c.is_ascii(),
+            Charset::Latin1 => (c as u32) < 0x100,
+        }
+    }
+}
+
+/// Transliterates the characters of `name` which aren't in `charset`, those without ASCII
+/// equivalent become `?`.
+pub fn transliterate(name: &str, charset: Charset) -> Cow<'_, str> {
+    if name.chars().all(|c| charset.contains(c)) {
+        return Cow::Borrowed(name);
+    }
+
+    let mut transliterated = String::with_capacity(name.len());
+    for c in name.chars() {
+        match charset.contains(c) {
+            true => transliterated.push(c),
+            false => transliterated.push_str(deunicode::deunicode_char(c).unwrap_or("?")),
+        }
+    }
+    // Words like 北京 are transliterated with a trailing space to separate them
+    if !name.ends_with(char::is_whitespace) {
+        transliterated.truncate(transliterated.trim_end().len());
+    }
+    Cow::Owned(transliterated)
+}
+
+/// Encodes UTF-8 written in chunks as ISO-8859-1, characters Latin-1 has not become `?`.
+#[derive(Debug, Default)]
+pub(crate) struct Latin1Encoder {
+    // Lead byte of a character split between two chunks
+    pending: Option<u8>,
+}
+
+impl Latin1Encoder {
+    pub(crate) fn encode(&mut self, buf: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(buf.len());
+        let mut bytes = self.pending.take().into_iter().chain(buf.iter().copied());
+        while let Some(b) = bytes.next() {
+            match b {
+                0x00..=0x7f => encoded.push(b),
+                // Continuation bytes of a character already replaced
+                0x80..=0xbf => {}
+                0xc2 | 0xc3 => match bytes.next() {
+                    Some(next) => encoded.push(((b & 0x03) << 6) | (next & 0x3f)),
+                    None => self.pending = Some(b),
+                },
+                _ => encoded.push(b'?'),
+            }
+        }
+        encoded
+    }
+}
+
+/// Escapes `name`, it is borrowed if nothing had to change.
+pub fn escape(name: &str, escaping: Escaping) -> Cow<'_, str> {
+    match escaping {
+        Escaping::None => Cow::Borrowed(name),
+        Escaping::Replace if name.chars().any(char::is_control) => Cow::Owned(
+            name.chars()
+                .map(|c| match c.is_control() {
+                    true => REPLACEMENT_CHARACTER,
+                    false => c,
+                })
+                .collect(),
+        ),
+        Escaping::Replace => Cow::Borrowed(name),
+        Escaping::Quote => quote(name),
+    }
+}
+
+fn quote(name: &str) -> Cow<'_, str> {
+    if !name.is_empty() && name.chars().all(is_safe) && !name.starts_with('~') {
+        return Cow::Borrowed(name);
+    }
+
+    if !name.chars().any(char::is_control) {
+        return Cow::Owned(format!("'{}'", name.replace('\'', r"'\''")));
+    }
+
+    let mut quoted = String::from("$'");
+    for c in name.chars() {
+        match c {
+            '\x07' => quoted.push_str(r"\a"),
+            '\x08' => quoted.push_str(r"\b"),
+            '\t' => quoted.push_str(r"\t"),
+            '\n' => quoted.push_str(r"\n"),
+            '\x0b' => quoted.push_str(r"\v"),
+            '\x0c' => quoted.push_str(r"\f"),
+            '\r' => quoted.push_str(r"\r"),
+            '\x1b' => quoted.push_str(r"\e"),
+            '\\' => quoted.push_str(r"\\"),
+            '\'' => quoted.push_str(r"\'"),
+            c if c.is_ascii_control() => write!(quoted, r"\x{:02x}", c as u32).unwrap(),
+            c if c.is_control() => write!(quoted, r"\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    Cow::Owned(quoted)
+}
+
+// Characters a shell takes literally anywhere in a word.
+fn is_safe(c: char) -> bool {
+    match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' => true,
+        '_' | '-' | '.' | '/' | ',' | '+' | ':' | '@' | '%' | '=' => true,
+        c => !c.is_ascii() && !c.is_control() && !c.is_whitespace(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_control_characters() {
+        assert_eq!(escape("a\tb\n", Escaping::Replace), "a\u{fffd}b\u{fffd}");
+        assert!(matches!(escape("a b", Escaping::Replace), Cow::Borrowed(_)));
+        assert_eq!(escape("a\tb", Escaping::None), "a\tb");
+    }
+
+    #[test]
+    fn transliterates_to_charset() {
+        assert_eq!(
+            transliterate("Größe 北京", Charset::Ascii),
+            "Grosse Bei Jing"
+        );
+        assert_eq!(transliterate("Größe ł", Charset::Latin1), "Größe l");
+        assert!(matches!(
+            transliterate("Größe", Charset::Latin1),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(transliterate("Größe", Charset::Utf8), "Größe");
+    }
+
+    #[test]
+    fn normalizes_names() {
+        let nfd = "Cafe\u{301}";
+        assert_eq!(Normalization::Nfc.apply(nfd), "Caf\u{e9}");
+        assert_eq!(Normalization::Nfd.apply("Caf\u{e9}"), nfd);
+        assert!(matches!(Normalization::Nfd.apply(nfd), Cow::Borrowed(_)));
+        assert_eq!(Normalization::None.apply(nfd), nfd);
+    }
+
+    #[test]
+    fn encodes_latin1_across_chunks() {
+        let mut encoder = Latin1Encoder::default();
+        let utf8 = "aäö€".as_bytes();
+        let mut encoded = encoder.encode(&utf8[..2]);
+        encoded.extend(encoder.encode(&utf8[2..]));
+        assert_eq!(encoded, b"a\xe4\xf6?");
+    }
+
+    #[test]
+    fn quotes_for_shells() {
+        assert!(matches!(
+            escape("src/main.rs", Escaping::Quote),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(escape("größe.txt", Escaping::Quote), "größe.txt");
+        assert_eq!(escape("my file", Escaping::Quote), "'my file'");
+        assert_eq!(escape("it's", Escaping::Quote), r"'it'\''s'");
+        assert_eq!(escape("$HOME*", Escaping::Quote), "'$HOME*'");
+        assert_eq!(escape("~user", Escaping::Quote), "'~user'");
+        assert_eq!(escape("", Escaping::Quote), "''");
+        assert_eq!(escape("a\nb's\\\x01", Escaping::Quote), r"$'a\nb\'s\\\x01'");
+        assert_eq!(escape("a\u{85}", Escaping::Quote), r"$'a\u0085'");
+    }
+}
@@ -0,0 +1,27 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{io, path::PathBuf};
+
+use crate::tree::TreeNode;
+
+/// Receives the events of a scan.
+pub type EventFn = Box<dyn FnMut(Event) + Send>;
+
+/// Emitted while [`Aldar::run`](crate::Aldar::run) lists a tree, in the order entries are
+/// printed, so a tree can be rendered while the scan is still running.
+#[derive(Debug)]
+pub enum Event {
+    /// An entry is listed at the given depth, entries of a root have depth 1. The node has no
+    /// children.
+    EntryDiscovered { node: TreeNode, depth: usize },
+    /// The contents of a directory are listed next, roots have depth 0. With breadth first
+    /// listing only roots are entered.
+    DirEntered { path: PathBuf, depth: usize },
+    /// All contents of a directory have been listed.
+    DirLeft { path: PathBuf, depth: usize },
+    /// A directory or entry could not be read, the scan goes on.
+    Error { path: PathBuf, error: io::Error },
+}
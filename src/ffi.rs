@@ -0,0 +1,372 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! C API to embed the tree rendering in non-Rust applications, declared in `include/aldar.h`.
+//!
+//! A handle is created with [`aldar_new`], configured, run into a buffer or a callback as often
+//! as needed and released with [`aldar_free`]. Output is never colored.
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    io::{self, Write},
+    ptr,
+};
+
+use crate::{
+    color::PlainColorizer, testing::SharedBuf, Aldar, AldarError, ASCII_GLYPHSET, UNICODE_GLYPHSET,
+};
+
+/// The call succeeded.
+pub const ALDAR_OK: c_int = 0;
+/// The run failed or entries couldn't be read, see [`aldar_last_error`].
+pub const ALDAR_ERROR: c_int = 1;
+/// A required pointer was null or a string not valid UTF-8.
+pub const ALDAR_INVALID_ARGUMENT: c_int = -1;
+
+/// Receives `len` bytes of output, a return value other than 0 aborts the run.
+pub type AldarWriteFn =
+    unsafe extern "C" fn(data: *const u8, len: usize, user_data: *mut c_void) -> c_int;
+
+/// Opaque handle owning a configured [`Aldar`].
+pub struct AldarHandle {
+    aldar: Aldar,
+    paths: Vec<String>,
+    error: Option<CString>,
+}
+
+impl AldarHandle {
+    // Runs into the writer and remembers the error of the run, if any.
+    fn run(&mut self, writer: Box<dyn Write + Send>) -> c_int {
+        if !self.paths.is_empty() {
+            self.aldar.use_paths(self.paths.clone());
+        }
+
+        let result = self.aldar.use_writer(writer).run();
+        // Drops the writer, a callback must not be called after the run returned.
+        self.aldar.use_writer(Box::new(io::sink()));
+
+        match result {
+            Ok(_) => {
+                self.error = None;
+                ALDAR_OK
+            }
+            // C callers can't walk the error, the collected ones are listed line by line.
+            Err(AldarError::Traversal { errors }) => {
+                let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                self.fail(&lines.join("\n"))
+            }
+            Err(e) => self.fail(&e.to_string()),
+        }
+    }
+
+    fn fail(&mut self, message: &str) -> c_int {
+        self.error = CString::new(message.replace('\0', "?")).ok();
+        ALDAR_ERROR
+    }
+}
+
+struct CallbackWriter {
+    callback: AldarWriteFn,
+    user_data: *mut c_void,
+}
+
+// The callback is only called during a run, on the thread which started it.
+unsafe impl Send for CallbackWriter {}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match unsafe { (self.callback)(buf.as_ptr(), buf.len(), self.user_data) } {
+            0 => Ok(buf.len()),
+            code => Err(io::Error::other(format!(
+                "write callback returned {}",
+                code
+            ))),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Applies `f` to the Aldar of the handle, ignoring null handles.
+unsafe fn configure(handle: *mut AldarHandle, f: impl FnOnce(&mut Aldar)) {
+    if let Some(handle) = handle.as_mut() {
+        f(&mut handle.aldar);
+    }
+}
+
+/// Creates a handle listing the current directory, release it with [`aldar_free`].
+#[no_mangle]
+pub extern "C" fn aldar_new() -> *mut AldarHandle {
+    let mut aldar = Aldar::new();
+    aldar.use_colorizer(Box::new(PlainColorizer));
+
+    Box::into_raw(Box::new(AldarHandle {
+        aldar,
+        paths: vec![],
+        error: None,
+    }))
+}
+
+/// Releases a handle created by [`aldar_new`].
+///
+/// # Safety
+///
+/// `handle` must be null or returned by [`aldar_new`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_free(handle: *mut AldarHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Adds a directory to list, each one is printed as separate tree.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle and `path` a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_add_path(handle: *mut AldarHandle, path: *const c_char) -> c_int {
+    let (Some(handle), false) = (handle.as_mut(), path.is_null()) else {
+        return ALDAR_INVALID_ARGUMENT;
+    };
+
+    match CStr::from_ptr(path).to_str() {
+        Ok(path) => {
+            handle.paths.push(path.to_owned());
+            ALDAR_OK
+        }
+        Err(_) => ALDAR_INVALID_ARGUMENT,
+    }
+}
+
+/// Configures whether hidden files are listed.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_show_hidden(handle: *mut AldarHandle, show: bool) {
+    configure(handle, |a| {
+        a.show_hidden(show);
+    });
+}
+
+/// Configures whether only directories are listed.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_show_dirs_only(handle: *mut AldarHandle, dirs_only: bool) {
+    configure(handle, |a| {
+        a.show_dirs_only(dirs_only);
+    });
+}
+
+/// Configures how many levels deep directories are listed, negative for no limit.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_set_max_level(handle: *mut AldarHandle, level: c_int) {
+    configure(handle, |a| {
+        a.use_max_level(level);
+    });
+}
+
+/// Configures whether the size of each entry is printed.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_show_size(handle: *mut AldarHandle, show: bool) {
+    configure(handle, |a| {
+        a.show_size(show);
+    });
+}
+
+/// Configures whether the date of the last modification of each entry is printed.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_show_date(handle: *mut AldarHandle, show: bool) {
+    configure(handle, |a| {
+        a.show_date(show);
+    });
+}
+
+/// Configures whether entries are printed with their full path.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_show_fullpath(handle: *mut AldarHandle, show: bool) {
+    configure(handle, |a| {
+        a.show_fullpath(show);
+    });
+}
+
+/// Configures whether the indentation lines are ASCII only.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_use_ascii(handle: *mut AldarHandle, ascii: bool) {
+    configure(handle, |a| {
+        match ascii {
+            true => a.use_glyphset(Box::new(ASCII_GLYPHSET)),
+            false => a.use_glyphset(Box::new(UNICODE_GLYPHSET)),
+        };
+    });
+}
+
+/// Lists the configured paths into a NUL terminated buffer stored in `out`, release it with
+/// [`aldar_string_free`]. The buffer is also set if entries couldn't be read.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle and `out` point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_run_to_buffer(
+    handle: *mut AldarHandle,
+    out: *mut *mut c_char,
+) -> c_int {
+    let (Some(handle), false) = (handle.as_mut(), out.is_null()) else {
+        return ALDAR_INVALID_ARGUMENT;
+    };
+    *out = ptr::null_mut();
+
+    let buf = SharedBuf::default();
+    let code = handle.run(Box::new(buf.clone()));
+
+    let output = buf.take();
+    match CString::new(output) {
+        Ok(output) => *out = output.into_raw(),
+        Err(_) => return handle.fail("output contains a NUL byte"),
+    }
+    code
+}
+
+/// Lists the configured paths and passes the output to `callback` in chunks as it is written.
+///
+/// # Safety
+///
+/// `handle` must be a valid handle, `callback` is called with `user_data` until this returns.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_run_with_callback(
+    handle: *mut AldarHandle,
+    callback: Option<AldarWriteFn>,
+    user_data: *mut c_void,
+) -> c_int {
+    let (Some(handle), Some(callback)) = (handle.as_mut(), callback) else {
+        return ALDAR_INVALID_ARGUMENT;
+    };
+
+    handle.run(Box::new(CallbackWriter {
+        callback,
+        user_data,
+    }))
+}
+
+/// Returns the error of the last failed run or null, valid until the next run or
+/// [`aldar_free`].
+///
+/// # Safety
+///
+/// `handle` must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_last_error(handle: *const AldarHandle) -> *const c_char {
+    match handle.as_ref().and_then(|h| h.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Releases a buffer returned by [`aldar_run_to_buffer`].
+///
+/// # Safety
+///
+/// `s` must be null or returned by [`aldar_run_to_buffer`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn aldar_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+    use std::fs;
+
+    unsafe extern "C" fn collect(data: *const u8, len: usize, user_data: *mut c_void) -> c_int {
+        let out = &mut *(user_data as *mut Vec<u8>);
+        out.extend_from_slice(std::slice::from_raw_parts(data, len));
+        0
+    }
+
+    #[test]
+    fn runs_into_buffer_and_callback() {
+        let root = temp_dir("ffi");
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("dir").join("file"), b"").unwrap();
+        let path = CString::new(root.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let handle = aldar_new();
+            assert_eq!(aldar_add_path(handle, path.as_ptr()), ALDAR_OK);
+            aldar_use_ascii(handle, true);
+
+            let mut out = ptr::null_mut();
+            assert_eq!(aldar_run_to_buffer(handle, &mut out), ALDAR_OK);
+            let buffered = CStr::from_ptr(out).to_str().unwrap().to_owned();
+            aldar_string_free(out);
+            assert!(buffered.ends_with("`-- dir\n    `-- file\n\n1 directories, 1 files\n"));
+
+            let mut collected: Vec<u8> = vec![];
+            let user_data = &mut collected as *mut Vec<u8> as *mut c_void;
+            assert_eq!(
+                aldar_run_with_callback(handle, Some(collect), user_data),
+                ALDAR_OK
+            );
+            assert_eq!(String::from_utf8(collected).unwrap(), buffered);
+            assert!(aldar_last_error(handle).is_null());
+
+            aldar_free(handle);
+        }
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn reports_errors_of_the_last_run() {
+        let path = CString::new("/nonexistent/aldar-ffi").unwrap();
+
+        unsafe {
+            let handle = aldar_new();
+            aldar_add_path(handle, path.as_ptr());
+
+            let mut out = ptr::null_mut();
+            assert_eq!(aldar_run_to_buffer(handle, &mut out), ALDAR_ERROR);
+            aldar_string_free(out);
+            let error = CStr::from_ptr(aldar_last_error(handle)).to_str().unwrap();
+            assert!(error.contains("/nonexistent/aldar-ffi"), "{}", error);
+
+            assert_eq!(
+                aldar_run_to_buffer(handle, ptr::null_mut()),
+                ALDAR_INVALID_ARGUMENT
+            );
+            aldar_free(handle);
+        }
+    }
+}
@@ -0,0 +1,391 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+#[cfg(feature = "color")]
+use colored::Colorize;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Write},
+    mem,
+    ops::Range,
+    str::FromStr,
+    sync::Arc,
+};
+
+use crate::{
+    aldar::Stats,
+    color::{Colorizer, DefaultColorizer},
+    compat::Compat,
+    fsutil::Entry,
+};
+
+/// Placeholders available in a [`ReportFormat`].
+pub const REPORT_FIELDS: &[&str] = &["dirs", "files", "errors", "size", "bytes", "duration"];
+
+/// A root about to be listed.
+pub struct Root<'a> {
+    /// Path of the root as configured for printing.
+    pub name: &'a str,
+    /// Why the root could not be read, its entries are skipped then.
+    pub error: Option<&'a str>,
+    /// URL the name links to, if hyperlinks are turned on.
+    pub link: Option<&'a str>,
+}
+
+/// An entry in the order it is listed.
+pub struct Item<'a> {
+    /// The entry, None for paths which don't come from the filesystem.
+    pub entry: Option<&'a Entry>,
+    /// Name as configured for printing, e.g. the full path or a collapsed chain.
+    pub name: &'a str,
+    pub is_dir: bool,
+    /// Depth below the root, the entries of the root have depth 1.
+    pub depth: usize,
+    /// Tree lines and glyph leading the entry, the depth in breadth first order.
+    pub prefix: &'a str,
+    /// Enabled columns like size and date, formatted for text output.
+    pub columns: &'a str,
+    /// Annotations like mount points, loops or read errors.
+    pub notes: &'a [String],
+    /// URL the name links to, if hyperlinks are turned on.
+    pub link: Option<&'a str>,
+    /// Printed before the name, if icons are turned on.
+    pub icon: Option<&'a str>,
+    /// Part of the name matched by an include pattern, if matches are highlighted.
+    pub highlight: Option<Range<usize>>,
+}
+
+/// Counts of a finished run.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Summary {
+    pub dirs: u64,
+    pub files: u64,
+    pub errors: u64,
+    /// Whether only directories were listed.
+    pub dirs_only: bool,
+}
+
+/// Template of the report line, placeholders like `{dirs}` are replaced with the counts of the
+/// run and `{{` or `}}` print a brace.
+///
+/// ```
+/// use aldar::format::ReportFormat;
+///
+/// let format: ReportFormat = "{dirs} dirs, {files} files, {size}".parse().unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportFormat {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field(ReportField),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportField {
+    Dirs,
+    Files,
+    Errors,
+    Size,
+    Bytes,
+    Duration,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '{' | '}' if rest.starts_with(c) => {
+                    rest = &rest[1..];
+                    text.push(c);
+                }
+                '{' => {
+                    let end = rest.find('}').ok_or("unclosed { in report format")?;
+                    let field = match &rest[..end] {
+                        "dirs" => ReportField::Dirs,
+                        "files" => ReportField::Files,
+                        "errors" => ReportField::Errors,
+                        "size" => ReportField::Size,
+                        "bytes" => ReportField::Bytes,
+                        "duration" => ReportField::Duration,
+                        name => {
+                            let available = REPORT_FIELDS.join(", ");
+                            return Err(format!(
+                                "unknown field {{{}}}, available: {}",
+                                name, available
+                            ));
+                        }
+                    };
+                    rest = &rest[end + 1..];
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Field(field));
+                }
+                '}' => return Err("unmatched } in report format, use }} for a brace".to_string()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(ReportFormat { pieces })
+    }
+}
+
+impl ReportFormat {
+    /// Returns true if the template prints the total size, which has to be read then.
+    pub fn needs_size(&self) -> bool {
+        self.pieces
+            .iter()
+            .any(|p| matches!(p, Piece::Field(ReportField::Size | ReportField::Bytes)))
+    }
+
+    /// Fills in the counts of `stats`, `size` is the total size as configured for printing.
+    pub fn render(&self, stats: &Stats, size: &str) -> String {
+        let mut line = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => line.push_str(text),
+                Piece::Field(ReportField::Dirs) => line.push_str(&stats.dirs.to_string()),
+                Piece::Field(ReportField::Files) => line.push_str(&stats.files.to_string()),
+                Piece::Field(ReportField::Errors) => line.push_str(&stats.errors.to_string()),
+                Piece::Field(ReportField::Size) => line.push_str(size),
+                Piece::Field(ReportField::Bytes) => line.push_str(&stats.bytes.to_string()),
+                Piece::Field(ReportField::Duration) => {
+                    line.push_str(&format!("{:.2}s", stats.duration.as_secs_f64()))
+                }
+            }
+        }
+        line
+    }
+}
+
+/// Writes the events of a run in an output format.
+pub trait Formatter: Send + Sync {
+    /// Called for every root before its entries.
+    fn begin(&mut self, out: &mut dyn Write, root: &Root) -> io::Result<()>;
+    /// Called for every entry.
+    fn entry(&mut self, out: &mut dyn Write, item: &Item) -> io::Result<()>;
+    /// Called once all roots are done, unless the report is turned off.
+    fn end(&mut self, out: &mut dyn Write, summary: &Summary) -> io::Result<()>;
+
+    /// Called instead of [`Formatter::end`] if the report is printed from a [`ReportFormat`],
+    /// with `line` rendered from it. Formatters whose output has no place for free text may
+    /// write their own report from the summary instead.
+    fn summary(&mut self, out: &mut dyn Write, _summary: &Summary, line: &str) -> io::Result<()> {
+        writeln!(out, "\n{}", line)
+    }
+
+    /// Called last, after the report if there is one, e.g. to close what [`Formatter::begin`]
+    /// opened.
+    fn finish(&mut self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Receives the conventions configured with [`Aldar::use_compat`](crate::Aldar::use_compat),
+    /// formatters following none ignore them.
+    fn use_compat(&mut self, _compat: Compat) {}
+
+    /// Receives the colorizer configured with
+    /// [`Aldar::use_colorizer`](crate::Aldar::use_colorizer), formatters without colors ignore
+    /// it.
+    fn use_colorizer(&mut self, _colorizer: Arc<dyn Colorizer>) {}
+}
+
+/// Writes the tree as indented text, colored unless colors are turned off.
+pub struct TextFormatter {
+    colorizer: Arc<dyn Colorizer>,
+    compat: Compat,
+}
+
+impl TextFormatter {
+    /// Creates a text formatter coloring names with the given colorizer.
+    pub fn new(colorizer: Box<dyn Colorizer>) -> TextFormatter {
+        TextFormatter {
+            colorizer: Arc::from(colorizer),
+            compat: Compat::None,
+        }
+    }
+
+    /// Follows the output conventions of another tool.
+    pub fn with_compat(mut self, compat: Compat) -> TextFormatter {
+        self.compat = compat;
+        self
+    }
+}
+
+impl Default for TextFormatter {
+    fn default() -> Self {
+        TextFormatter::new(Box::new(DefaultColorizer))
+    }
+}
+
+impl Formatter for TextFormatter {
+    fn use_compat(&mut self, compat: Compat) {
+        self.compat = compat;
+    }
+
+    fn use_colorizer(&mut self, colorizer: Arc<dyn Colorizer>) {
+        self.colorizer = colorizer;
+    }
+
+    fn begin(&mut self, out: &mut dyn Write, root: &Root) -> io::Result<()> {
+        let name = hyperlink(self.colorizer.dir(root.name), root.link);
+        match root.error {
+            Some(error) => writeln!(out, "{} {}", name, error),
+            None => writeln!(out, "{}", name),
+        }
+    }
+
+    fn entry(&mut self, out: &mut dyn Write, item: &Item) -> io::Result<()> {
+        let color = |name: &str| match item.entry {
+            _ if name.is_empty() => String::new(),
+            Some(entry) => self.colorizer.entry(entry, name),
+            None if item.is_dir => self.colorizer.dir(name),
+            None => name.to_string(),
+        };
+        let name = match item.highlight.clone() {
+            Some(range) => format!(
+                "{}{}{}",
+                color(&item.name[..range.start]),
+                highlight(&item.name[range.clone()]),
+                color(&item.name[range.end..])
+            ),
+            None => color(item.name),
+        };
+        let name = hyperlink(name, item.link);
+
+        write!(out, "{}{} ", item.prefix, item.columns)?;
+        if let Some(icon) = item.icon {
+            write!(out, "{} ", icon)?;
+        }
+        write!(out, "{}", name)?;
+        for note in item.notes {
+            write!(out, " {}", note)?;
+        }
+        writeln!(out)
+    }
+
+    fn end(&mut self, out: &mut dyn Write, summary: &Summary) -> io::Result<()> {
+        if self.compat == Compat::Tree {
+            // Errors are only printed next to the entries by tree
+            let plural = |n: u64, one: &str, many: &str| match n {
+                1 => format!("{} {}", n, one),
+                n => format!("{} {}", n, many),
+            };
+            write!(
+                out,
+                "\n{}",
+                plural(summary.dirs, "directory", "directories")
+            )?;
+            if !summary.dirs_only {
+                write!(out, ", {}", plural(summary.files, "file", "files"))?;
+            }
+            return writeln!(out);
+        }
+
+        write!(
+            out,
+            "\n{} directories, {} files",
+            summary.dirs, summary.files
+        )?;
+        if summary.errors > 0 {
+            write!(out, ", {} errors", summary.errors)?;
+        }
+        writeln!(out)
+    }
+}
+
+// Colors the part of a name matched by a pattern like grep does.
+fn highlight(matched: &str) -> String {
+    #[cfg(feature = "color")]
+    return matched.red().bold().to_string();
+    #[cfg(not(feature = "color"))]
+    matched.to_string()
+}
+
+// Wraps the name in an OSC 8 escape sequence, terminals supporting it make it clickable.
+fn hyperlink(name: String, link: Option<&str>) -> String {
+    match link {
+        Some(link) => format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", link, name),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_report_format() {
+        let stats = Stats {
+            dirs: 2,
+            files: 5,
+            bytes: 2048,
+            ..Stats::default()
+        };
+        let format: ReportFormat = "{dirs} dirs, {files} files, {size} {{{bytes}}}"
+            .parse()
+            .unwrap();
+        assert!(format.needs_size());
+        assert_eq!(
+            format.render(&stats, "2KiB"),
+            "2 dirs, 5 files, 2KiB {2048}"
+        );
+    }
+
+    #[test]
+    fn links_names_with_osc8() {
+        let mut formatter = TextFormatter::new(Box::new(crate::color::PlainColorizer));
+        let mut out = vec![];
+        let root = Root {
+            name: "a dir",
+            error: None,
+            link: Some("file:///a%20dir"),
+        };
+        formatter.begin(&mut out, &root).unwrap();
+        let item = Item {
+            entry: None,
+            name: "b",
+            is_dir: false,
+            depth: 1,
+            prefix: "└──",
+            columns: "",
+            notes: &[],
+            link: Some("file:///a%20dir/b"),
+            icon: None,
+            highlight: None,
+        };
+        formatter.entry(&mut out, &item).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b]8;;file:///a%20dir\x1b\\a dir\x1b]8;;\x1b\\\n\
+             └── \x1b]8;;file:///a%20dir/b\x1b\\b\x1b]8;;\x1b\\\n"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_report_formats() {
+        assert!("{dirs".parse::<ReportFormat>().is_err());
+        assert!("dirs}".parse::<ReportFormat>().is_err());
+        assert!("{folders}"
+            .parse::<ReportFormat>()
+            .unwrap_err()
+            .contains("available: dirs"));
+    }
+}
@@ -3,9 +3,22 @@
 // Use of this source code is governed by an BSD-style
 // license that can be found in the LICENSE file.
 
-use std::fs::DirEntry;
+#[cfg(feature = "cli")]
+use clap::ArgEnum;
 #[cfg(unix)]
-use std::os::unix::prelude::{MetadataExt, PermissionsExt};
+use std::os::unix::prelude::MetadataExt;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs::{self, DirEntry},
+    io,
+    path::{Component, Path, PathBuf},
+    sync::{Condvar, Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use crate::stat::{self, Fields, Stat};
 
 pub trait AldarExt {
     fn is_hidden(&self) -> bool;
@@ -15,181 +28,584 @@ pub trait AldarExt {
     fn full_rel_path(&self, base: &str) -> String;
 }
 
-#[cfg(unix)]
-impl AldarExt for DirEntry {
+/// A directory entry whose metadata is fetched once and reused for sorting and printing.
+pub struct Entry {
+    path: PathBuf,
+    stat: Option<Stat>,
+    size: Option<u64>,
+    digest: Option<String>,
+    // Metadata of the target of a symbolic link, fetched on first use
+    target: OnceLock<Option<Stat>>,
+}
+
+impl Entry {
+    /// Creates a new entry, with `follow_links` the metadata of a symbolic link's target is used.
+    /// Only the given fields are guaranteed to be fetched besides the file type.
+    pub fn new(dir_entry: DirEntry, follow_links: bool, fields: Fields) -> Entry {
+        let stat = match Stat::of(&dir_entry, follows(&dir_entry, follow_links), fields) {
+            Ok(stat) => Some(stat),
+            Err(e) => {
+                log::debug!(
+                    "no metadata path={} error={}",
+                    dir_entry.path().display(),
+                    e
+                );
+                None
+            }
+        };
+        Entry::with_stat(dir_entry.path(), stat)
+    }
+
+    /// Creates a new entry from already fetched metadata.
+    pub fn with_stat(path: PathBuf, stat: Option<Stat>) -> Entry {
+        Entry {
+            path,
+            stat,
+            size: None,
+            digest: None,
+            target: OnceLock::new(),
+        }
+    }
+
+    /// Sets the metadata of a symbolic link's target, otherwise it is queried from the standard
+    /// filesystem when needed. Entries of other filesystems must set it for their links.
+    pub fn with_target(self, target: Option<Stat>) -> Entry {
+        self.target.set(target).ok();
+        self
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn file_name(&self) -> OsString {
+        match self.path.file_name() {
+            Some(name) => name.to_os_string(),
+            _ => self.path.clone().into_os_string(),
+        }
+    }
+
+    /// Returns the cached metadata of the entry.
+    pub fn stat(&self) -> Option<&Stat> {
+        self.stat.as_ref()
+    }
+
+    /// Overrides the size of the entry, e.g. with the cumulative size of a directory.
+    pub fn set_size(&mut self, size: u64) {
+        self.size = Some(size);
+    }
+
+    /// Returns the metadata of the entry, for symbolic links the one of their target.
+    pub fn target(&self) -> Option<&Stat> {
+        match self.stat.as_ref() {
+            Some(s) if s.is_symlink => self
+                .target
+                .get_or_init(|| {
+                    stat::metadata(&self.path)
+                        .ok()
+                        .map(|m| Stat::from_metadata(&m))
+                })
+                .as_ref(),
+            stat => stat,
+        }
+    }
+
+    /// Returns a copy of the entry with another file name, e.g. to sort by a normalized name. The
+    /// metadata of a link target is taken over as the renamed path may not exist.
+    pub(crate) fn renamed(&self, file_name: &str) -> Entry {
+        Entry {
+            path: self.path.with_file_name(file_name),
+            stat: self.stat,
+            size: self.size,
+            digest: None,
+            target: OnceLock::from(self.target().copied()),
+        }
+    }
+
+    /// Returns the metadata of a link target if it was already fetched or set, without fetching.
+    pub(crate) fn fetched_target(&self) -> Option<Option<Stat>> {
+        self.target.get().copied()
+    }
+
+    /// Returns the digest of the file contents, if computed.
+    pub fn digest(&self) -> Option<&str> {
+        self.digest.as_deref()
+    }
+
+    pub fn set_digest(&mut self, digest: Option<String>) {
+        self.digest = digest;
+    }
+
+    /// Returns the last modification time or the unix epoch if unavailable.
+    pub fn modified(&self) -> SystemTime {
+        match self.stat.as_ref() {
+            Some(s) => s.modified,
+            _ => SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    /// Returns the last status change time (creation time on windows) or the unix epoch if unavailable.
+    pub fn changed(&self) -> SystemTime {
+        match self.stat.as_ref() {
+            Some(s) => s.changed,
+            _ => SystemTime::UNIX_EPOCH,
+        }
+    }
+}
+
+impl AldarExt for Entry {
+    #[cfg(not(windows))]
+    fn is_hidden(&self) -> bool {
+        match self.path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.starts_with('.'),
+            _ => false,
+        }
+    }
+
+    #[cfg(windows)]
     fn is_hidden(&self) -> bool {
-        match self.file_name().to_str() {
-            Some(n) => n.starts_with("."),
+        match self.stat.as_ref() {
+            Some(s) => s.hidden,
             _ => false,
         }
     }
 
+    #[cfg(unix)]
     fn is_executable(&self) -> bool {
-        match self.file_type() {
-            Ok(f) if f.is_dir() => false,
-            Ok(_) => {
-                if let Ok(meta) = self.path().metadata() {
-                    return meta.permissions().mode() & 0o111 != 0;
-                }
-                false
-            }
+        if self.is_dir() {
+            return false;
+        }
+
+        match self.target() {
+            Some(s) => s.mode & 0o111 != 0,
             _ => false,
         }
     }
 
-    fn size(&self) -> u64 {
-        let metadata = match self.metadata() {
-            Ok(m) => m,
-            Err(_) => return 0,
-        };
+    // Matching the extension avoids opening every file, which is slow on network shares.
+    #[cfg(windows)]
+    fn is_executable(&self) -> bool {
+        if self.is_dir() {
+            return false;
+        }
 
-        return metadata.size();
+        match self.path.extension() {
+            Some(ext) => {
+                let ext = ext.to_string_lossy().to_uppercase();
+                executable_extensions().contains(&ext)
+            }
+            _ => false,
+        }
     }
 
-    fn full_rel_path(&self, base: &str) -> String {
-       get_full_rel_path(self, base)
+    // There are no permissions on a virtual filesystem, e.g. on wasm.
+    #[cfg(not(any(unix, windows)))]
+    fn is_executable(&self) -> bool {
+        false
     }
 
     fn is_dir(&self) -> bool {
-        match self.file_type() {
-            Ok(f) => f.is_dir(),
+        match self.stat.as_ref() {
+            Some(s) => s.is_dir,
             _ => false,
         }
     }
+
+    fn size(&self) -> u64 {
+        match (self.size, self.stat.as_ref()) {
+            (Some(size), _) => size,
+            (_, Some(s)) => s.size,
+            _ => 0,
+        }
+    }
+
+    fn full_rel_path(&self, base: &str) -> String {
+        get_full_rel_path(&self.path, base)
+    }
 }
 
+/// Returns true if the metadata of the link target is used for the entry.
+pub fn follows(dir_entry: &DirEntry, follow_links: bool) -> bool {
+    match dir_entry.file_type() {
+        Ok(t) => follow_links && t.is_symlink(),
+        _ => false,
+    }
+}
 
-fn get_full_rel_path(entry: &DirEntry, base: &str) -> String {
-    let fp = match entry.path().canonicalize() {
-        Ok(p) => p,
-        _ => match entry.file_name().to_str() {
-            Some(s) => return s.to_string(),
-            _ => return "?".to_owned(),
-        },
-    };
+/// Available implementations to read directories with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ArgEnum))]
+pub enum Backend {
+    /// Read directories with the standard library
+    Std,
+    /// Batch metadata queries through io_uring (Linux only)
+    IoUring,
+}
 
-    let rel_str = match fp.to_str() {
-        Some(p) => match p.strip_prefix(base) {
-            Some(s) => s.to_string(),
-            _ => return "?".to_owned(),
-        },
-        _ => return "?".to_owned(),
-    };
+impl Backend {
+    /// Returns true if the backend is available in this build.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Backend::Std => true,
+            Backend::IoUring => cfg!(all(target_os = "linux", feature = "io-uring")),
+        }
+    }
 
-    match rel_str.strip_prefix(std::path::MAIN_SEPARATOR) {
-        Some(s) => s.to_string(),
-        _ => rel_str,
+    /// Reads all entries of a directory including their metadata, failed entries are kept as errors.
+    pub fn read_entries(
+        &self,
+        dir: &Path,
+        follow_links: bool,
+        fields: Fields,
+    ) -> io::Result<Vec<io::Result<Entry>>> {
+        match self {
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Backend::IoUring => crate::uring::read_entries(dir, follow_links, fields),
+            _ => read_entries(dir, follow_links, fields),
+        }
     }
 }
 
+/// A filesystem to read the tree from, the local one is read through a [`Backend`].
+pub trait FileSystem: Send + Sync {
+    /// Reads all entries of a directory, failed entries are kept as errors. With `follow_links`
+    /// entries of symbolic links get the metadata of their target. Only the given fields are
+    /// required besides the file type.
+    fn read_dir(
+        &self,
+        dir: &Path,
+        follow_links: bool,
+        fields: Fields,
+    ) -> io::Result<Vec<io::Result<Entry>>>;
+
+    /// Returns the metadata of a path, following symbolic links.
+    fn metadata(&self, path: &Path) -> io::Result<Stat>;
+
+    /// Returns the path a symbolic link points to.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Identifies the file at `path` following symbolic links, used to detect link loops.
+    fn file_id(&self, path: &Path) -> Option<FileId>;
+}
 
-#[cfg(windows)]
-use std::os::windows::prelude::*;
+impl FileSystem for Backend {
+    fn read_dir(
+        &self,
+        dir: &Path,
+        follow_links: bool,
+        fields: Fields,
+    ) -> io::Result<Vec<io::Result<Entry>>> {
+        self.read_entries(dir, follow_links, fields)
+    }
 
-#[cfg(windows)]
-impl AldarExt for DirEntry {
-    fn is_hidden(&self) -> bool {
-        if let Ok(m) = self.metadata() {
-            let attrs = m.file_attributes();
+    fn metadata(&self, path: &Path) -> io::Result<Stat> {
+        stat::metadata(path).map(|m| Stat::from_metadata(&m))
+    }
 
-            
-            return attrs&win32::FILE_ATTRIBUTE_HIDDEN == win32::FILE_ATTRIBUTE_HIDDEN;
-        }
-        false
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
     }
 
-    fn is_executable(&self) -> bool {    
-        let path_result = self.path().canonicalize();
+    fn file_id(&self, path: &Path) -> Option<FileId> {
+        file_id(path)
+    }
+}
 
-        if path_result.is_err() {
-            return false;
+/// Reads all entries of a directory including their metadata, failed entries are kept as errors.
+pub fn read_entries(
+    dir: &Path,
+    follow_links: bool,
+    fields: Fields,
+) -> io::Result<Vec<io::Result<Entry>>> {
+    Ok(fs::read_dir(dir)?
+        .map(|r| r.map(|e| Entry::new(e, follow_links, fields)))
+        .collect())
+}
+
+/// Limits the number of directories read at the same time, readers wait for a free slot.
+pub struct DirLimit {
+    max: usize,
+    open: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A slot to read a directory, freed when dropped.
+pub struct DirPermit<'a>(&'a DirLimit);
+
+impl DirLimit {
+    pub fn new(max: usize) -> DirLimit {
+        DirLimit {
+            max: max.max(1),
+            open: Mutex::new(0),
+            freed: Condvar::new(),
         }
+    }
 
-        let lp_application_name: Vec<u16> = match path_result.unwrap().to_str() {
-            Some(s) => s.encode_utf16().chain(Some(0)).collect(),
-            _ => return false
-        };
-        
-        let success: win32::BOOL;
-        let mut lp_binary_type: win32::DWORD = 0;
-        unsafe {
-            success = win32::GetBinaryTypeW(lp_application_name.as_ptr(), &mut lp_binary_type);
+    /// Waits until another directory may be opened.
+    pub fn acquire(&self) -> DirPermit<'_> {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.max {
+            open = self.freed.wait(open).unwrap();
         }
+        *open += 1;
+        DirPermit(self)
+    }
+}
 
-        success == win32::TRUE
+impl Drop for DirPermit<'_> {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
     }
+}
 
-    fn size(&self) -> u64 {
-        let metadata = match self.metadata() {
-            Ok(m) => m,
-            Err(_) => return 0,
-        };
+/// Returns how many directories may be open at once, half of the file descriptor soft limit
+/// so files (e.g. for hashing) can still be opened.
+#[cfg(target_os = "linux")]
+pub fn default_max_open_dirs() -> usize {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    match unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } {
+        0 => (limit.rlim_cur / 2).clamp(1, 4096) as usize,
+        _ => 64,
+    }
+}
+
+/// Returns how many directories may be open at once.
+#[cfg(not(target_os = "linux"))]
+pub fn default_max_open_dirs() -> usize {
+    64
+}
+
+/// Identifies a file independent of the path used to reach it.
+#[cfg(unix)]
+pub type FileId = (u64, u64);
+
+/// Identifies a file independent of the path used to reach it.
+#[cfg(not(unix))]
+pub type FileId = PathBuf;
 
-        return metadata.file_size();
+/// Returns the device and inode of the file at `path`, following symbolic links.
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> Option<FileId> {
+    stat::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+/// Returns the canonical path of the file at `path`, following symbolic links.
+#[cfg(not(unix))]
+pub fn file_id(path: &Path) -> Option<FileId> {
+    stat::record_calls(1);
+    path.canonicalize().ok()
+}
+
+/// Lexically resolves `.` and `..` components of the given path without touching the filesystem.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            c => normalized.push(c),
+        }
     }
 
-    fn full_rel_path(&self, base: &str) -> String {
-        get_full_rel_path(self, base)
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
     }
+    normalized
+}
 
-    fn is_dir(&self) -> bool {
-        match self.file_type() {
-            Ok(f) => f.is_dir(),
-            _ => false,
+/// Resolves `.` and `..` components of a root like [`normalize_path`], but a `..` that follows a
+/// symbolic link goes to the parent of the link target, as it does when the path is opened.
+pub fn resolve_root(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        let is_link = || fs::symlink_metadata(&resolved).is_ok_and(|m| m.file_type().is_symlink());
+        match component {
+            Component::ParentDir if is_link() => match resolved.canonicalize() {
+                Ok(target) => {
+                    resolved = target;
+                    resolved.pop();
+                }
+                Err(_) => {
+                    resolved.pop();
+                }
+            },
+            c => resolved = normalize_path(&resolved.join(c)),
         }
     }
+
+    if resolved.as_os_str().is_empty() {
+        resolved.push(".");
+    }
+    resolved
 }
 
+/// Returns the value of the longest extension in `map` that `name` ends with, ignoring case. A
+/// name has to be more than its extension, so `.gz` has none.
+pub(crate) fn longest_extension<'a, V>(name: &str, map: &'a HashMap<String, V>) -> Option<&'a V> {
+    let name = name.to_lowercase();
+    map.iter()
+        .filter(|(ext, _)| {
+            name.strip_suffix(ext.to_lowercase().as_str())
+                .is_some_and(|stem| stem.len() > 1 && stem.ends_with('.'))
+        })
+        .max_by_key(|(ext, _)| ext.len())
+        .map(|(_, value)| value)
+}
 
-#[cfg(windows)]
-#[allow(dead_code)]
-mod win32 {
+/// Replaces a leading `~` with the home directory of the current user.
+pub fn expand_home(path: &Path) -> PathBuf {
+    let home = match std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" }) {
+        Some(home) => PathBuf::from(home),
+        _ => return path.to_path_buf(),
+    };
+
+    match path.strip_prefix("~") {
+        Ok(rest) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
 
-    pub const FILE_ATTRIBUTE_HIDDEN: u32 = 0x00000002;
-    pub const TRUE: BOOL = 1;
-    pub const FALSE: BOOL = 0;
+/// Returns the path to reach `path` starting from the directory `base`, both must be absolute.
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path = normalize_path(path);
+    let base = normalize_path(base);
 
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in base.components().skip(common) {
+        relative.push("..");
+    }
+    for component in path.components().skip(common) {
+        relative.push(component);
+    }
+
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+/// Returns the `file://` URL of an absolute path, characters not allowed in URLs are percent
+/// encoded.
+pub fn file_url(path: &Path) -> String {
+    use std::fmt::Write;
+
+    let path = normalize_path(path);
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().replace('\\', "/").into_bytes();
+
+    let mut url = String::from("file://");
+    // Windows paths start with the drive letter
+    if !bytes.starts_with(b"/") {
+        url.push('/');
+    }
+    for b in bytes {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => url.push(b as char),
+            b'-' | b'.' | b'_' | b'~' | b'/' | b':' => url.push(b as char),
+            b => write!(url, "%{:02X}", b).unwrap(),
+        }
+    }
+    url
+}
 
-    #[allow(non_camel_case_types)]
-    pub type c_uint = u32;
-    #[allow(non_camel_case_types)]
-    pub type c_int = i32;
-    pub type DWORD = c_uint;
-    pub type LPDWORD = *mut DWORD;
-    pub type BOOL = c_int;
+/// Returns a printable form of a name or path, bytes which aren't valid UTF-8 are escaped as
+/// `\xNN` so different names stay distinguishable.
+#[cfg(unix)]
+pub fn display_name(name: &OsStr) -> Cow<'_, str> {
+    use std::{fmt::Write, os::unix::ffi::OsStrExt};
 
-    pub type LPCWSTR = *const WCHAR;
-    pub type WCHAR = wchar_t;
-    #[allow(non_camel_case_types)]
-    pub type wchar_t = u16;
+    if let Some(s) = name.to_str() {
+        return Cow::Borrowed(s);
+    }
 
-    #[allow(non_camel_case_types)]
-    pub enum LpBinaryType {
-        // A 32-bit Windows-based application
-        SCS_32BIT_BINARY = 0,
+    let mut escaped = String::new();
+    for chunk in name.as_bytes().utf8_chunks() {
+        escaped.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            write!(escaped, "\\x{:02X}", b).ok();
+        }
+    }
+    Cow::Owned(escaped)
+}
 
-        // 	A 64-bit Windows-based application.
-        SCS_64BIT_BINARY = 6,
+/// Returns a printable form of a name or path, unpaired surrogates are replaced.
+#[cfg(not(unix))]
+pub fn display_name(name: &OsStr) -> Cow<'_, str> {
+    name.to_string_lossy()
+}
 
-        // 	An MS-DOS – based application
-        SCS_DOS_BINARY = 1,
+fn get_full_rel_path(path: &Path, base: &str) -> String {
+    let fp = match path.canonicalize() {
+        Ok(p) => p,
+        _ => match path.file_name() {
+            Some(s) => return display_name(s).into_owned(),
+            _ => return "?".to_owned(),
+        },
+    };
 
-        // 	A 16-bit OS/2-based application
-        SCS_OS216_BINARY = 5,
+    let rel_str = match display_name(fp.as_os_str()).strip_prefix(base) {
+        Some(s) => s.to_string(),
+        _ => return "?".to_owned(),
+    };
 
-        // A PIF file that executes an MS-DOS – based application
-        SCS_PIF_BINARY = 3,
+    match rel_str.strip_prefix(std::path::MAIN_SEPARATOR) {
+        Some(s) => s.to_string(),
+        _ => rel_str,
+    }
+}
 
-        // 	A POSIX – based application
-        SCS_POSIX_BINARY = 4,
+// Returns the extensions of executable files listed in PATHEXT, without the leading dot.
+#[cfg(windows)]
+fn executable_extensions() -> &'static [String] {
+    static EXTENSIONS: OnceLock<Vec<String>> = OnceLock::new();
+    EXTENSIONS.get_or_init(|| {
+        std::env::var("PATHEXT")
+            .unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_owned())
+            .split(';')
+            .filter_map(|e| e.strip_prefix('.'))
+            .map(str::to_uppercase)
+            .collect()
+    })
+}
 
-        // A 16-bit Windows-based application
-        SCS_WOW_BINARY = 2,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn percent_encodes_file_urls() {
+        assert_eq!(
+            file_url(Path::new("/srv/my docs/#1/./ü.txt")),
+            "file:///srv/my%20docs/%231/%C3%BC.txt"
+        );
+        assert_eq!(file_url(Path::new("/a-b_c~d:e")), "file:///a-b_c~d:e");
     }
 
-    #[link(name = "Kernel32")]
-    extern "system" {
-        /// [`GetBinaryTypeW`](https://docs.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-getbinarytypew)
-        pub fn GetBinaryTypeW(lpApplicationName: LPCWSTR, lpBinaryType: LPDWORD) -> BOOL;
+    #[cfg(windows)]
+    #[test]
+    fn percent_encodes_file_urls() {
+        assert_eq!(
+            file_url(Path::new(r"C:\my docs\#1\ü.txt")),
+            "file:///C:/my%20docs/%231/%C3%BC.txt"
+        );
     }
 }
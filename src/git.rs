@@ -0,0 +1,324 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use simple_error::SimpleError;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+#[cfg(unix)]
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+// Size of the fixed part of an index entry (stat data, object id and flags).
+const ENTRY_HEADER_SIZE: usize = 62;
+const FLAG_EXTENDED: u16 = 0x4000;
+
+/// Set of paths tracked in the index of a git repository.
+#[derive(Debug)]
+pub struct GitIndex {
+    root: PathBuf,
+    files: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl GitIndex {
+    /// Searches the repository containing `path` and reads its index.
+    pub fn discover(path: &Path) -> Result<GitIndex, Box<dyn Error + Send + Sync>> {
+        let start = path.canonicalize()?;
+
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            if dot_git.is_dir() {
+                return GitIndex::open(dir, &dot_git.join("index"));
+            }
+
+            // Worktrees and submodules use a file pointing to the real git directory.
+            if dot_git.is_file() {
+                let content = fs::read_to_string(&dot_git)?;
+                if let Some(git_dir) = content.trim().strip_prefix("gitdir:") {
+                    let git_dir = dir.join(git_dir.trim());
+                    return GitIndex::open(dir, &git_dir.join("index"));
+                }
+            }
+        }
+
+        Err(Box::new(SimpleError::new(format!(
+            "{} is not inside a git repository",
+            path.display()
+        ))))
+    }
+
+    fn open(root: &Path, index: &Path) -> Result<GitIndex, Box<dyn Error + Send + Sync>> {
+        let mut git_index = GitIndex {
+            root: root.to_path_buf(),
+            files: HashSet::new(),
+            dirs: HashSet::new(),
+        };
+
+        // A freshly initialized repository has no index yet.
+        if !index.exists() {
+            return Ok(git_index);
+        }
+
+        for file in parse_index(&fs::read(index)?)? {
+            for dir in file.ancestors().skip(1) {
+                if !git_index.dirs.insert(dir.to_path_buf()) {
+                    break;
+                }
+            }
+            git_index.files.insert(file);
+        }
+
+        Ok(git_index)
+    }
+
+    /// Returns true if the canonical `path` is tracked or is a directory containing tracked files.
+    pub fn is_tracked(&self, path: &Path) -> bool {
+        match path.strip_prefix(&self.root) {
+            Ok(rel) => self.files.contains(rel) || self.dirs.contains(rel),
+            _ => false,
+        }
+    }
+}
+
+fn invalid_index() -> Box<dyn Error + Send + Sync> {
+    Box::new(SimpleError::new("invalid git index"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    match data.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        _ => Err(invalid_index()),
+    }
+}
+
+// Decodes the offset encoded integer used by index version 4 for path prefix compression.
+fn read_varint(data: &[u8], offset: &mut usize) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let mut c = *data.get(*offset).ok_or_else(invalid_index)?;
+    *offset += 1;
+    let mut val = (c & 0x7f) as usize;
+    while c & 0x80 != 0 {
+        c = *data.get(*offset).ok_or_else(invalid_index)?;
+        *offset += 1;
+        val = ((val + 1) << 7) | (c & 0x7f) as usize;
+    }
+    Ok(val)
+}
+
+// Names are kept as they are on unix, git writes them as UTF-8 elsewhere.
+#[cfg(unix)]
+fn to_path(name: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(name))
+}
+
+#[cfg(not(unix))]
+fn to_path(name: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(name).into_owned())
+}
+
+/// Parses a git index file (version 2, 3 and 4) and returns the paths of all entries.
+fn parse_index(data: &[u8]) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    if data.len() < 12 || &data[0..4] != b"DIRC" {
+        return Err(invalid_index());
+    }
+
+    let version = read_u32(data, 4)?;
+    if !(2..=4).contains(&version) {
+        return Err(Box::new(SimpleError::new(format!(
+            "unsupported git index version {}",
+            version
+        ))));
+    }
+
+    let count = read_u32(data, 8)? as usize;
+    // The count isn't trusted, no entry is shorter than its fixed part
+    let mut names = Vec::with_capacity(count.min(data.len() / ENTRY_HEADER_SIZE));
+    let mut offset = 12;
+    let mut previous: Vec<u8> = vec![];
+
+    for _ in 0..count {
+        let start = offset;
+        let flags = match data.get(offset + 60..offset + ENTRY_HEADER_SIZE) {
+            Some(b) => u16::from_be_bytes([b[0], b[1]]),
+            _ => return Err(invalid_index()),
+        };
+        offset += ENTRY_HEADER_SIZE;
+        if version >= 3 && flags & FLAG_EXTENDED != 0 {
+            offset += 2;
+        }
+
+        let name = if version == 4 {
+            let strip = read_varint(data, &mut offset)?;
+            if strip > previous.len() {
+                return Err(invalid_index());
+            }
+            let suffix_len = match data
+                .get(offset..)
+                .and_then(|d| d.iter().position(|b| *b == 0))
+            {
+                Some(n) => n,
+                _ => return Err(invalid_index()),
+            };
+            let mut name = previous[..previous.len() - strip].to_vec();
+            name.extend_from_slice(&data[offset..offset + suffix_len]);
+            offset += suffix_len + 1;
+            name
+        } else {
+            let name_len = match data
+                .get(offset..)
+                .and_then(|d| d.iter().position(|b| *b == 0))
+            {
+                Some(n) => n,
+                _ => return Err(invalid_index()),
+            };
+            let name = data[offset..offset + name_len].to_vec();
+            // Entries are padded with 1-8 NUL bytes to a multiple of eight.
+            offset = start + ((offset - start + name_len + 8) & !7);
+            name
+        };
+
+        names.push(to_path(&name));
+        previous = name;
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds an index entry with zeroed stat data, versions 2 and 3 pad the name with NUL bytes.
+    fn entry(version: u32, name: &[u8], strip: u8, extended: bool) -> Vec<u8> {
+        let mut data = vec![0; ENTRY_HEADER_SIZE - 2];
+        let mut flags = name.len().min(0xfff) as u16;
+        if extended {
+            flags |= FLAG_EXTENDED;
+        }
+        data.extend(flags.to_be_bytes());
+        if extended {
+            data.extend([0, 0]);
+        }
+
+        match version {
+            4 => {
+                data.push(strip);
+                data.extend(name);
+                data.push(0);
+            }
+            _ => {
+                data.extend(name);
+                let padded = (data.len() + 8) & !7;
+                data.resize(padded, 0);
+            }
+        }
+        data
+    }
+
+    fn index(version: u32, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend(version.to_be_bytes());
+        data.extend((entries.len() as u32).to_be_bytes());
+        entries.iter().for_each(|e| data.extend(e));
+        data
+    }
+
+    #[test]
+    fn parses_version_2() {
+        let data = index(
+            2,
+            &[
+                entry(2, b"a.txt", 0, false),
+                entry(2, b"src/main.rs", 0, false),
+            ],
+        );
+        let names = parse_index(&data).unwrap();
+        assert_eq!(
+            names,
+            [PathBuf::from("a.txt"), PathBuf::from("src/main.rs")]
+        );
+    }
+
+    #[test]
+    fn parses_extended_flags_of_version_3() {
+        let data = index(
+            3,
+            &[entry(3, b"ab", 0, true), entry(3, b"abcdef", 0, false)],
+        );
+        let names = parse_index(&data).unwrap();
+        assert_eq!(names, [PathBuf::from("ab"), PathBuf::from("abcdef")]);
+    }
+
+    #[test]
+    fn parses_prefix_compression_of_version_4() {
+        let data = index(
+            4,
+            &[
+                entry(4, b"src/lib.rs", 0, false),
+                entry(4, b"main.rs", 6, true),
+                entry(4, b"tests/a", 11, false),
+            ],
+        );
+        let names = parse_index(&data).unwrap();
+        assert_eq!(
+            names,
+            [
+                PathBuf::from("src/lib.rs"),
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("tests/a")
+            ]
+        );
+
+        let data = index(4, &[entry(4, b"a", 2, false)]);
+        assert_eq!(
+            parse_index(&data).unwrap_err().to_string(),
+            "invalid git index"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_names_that_are_no_utf8() {
+        let data = index(2, &[entry(2, b"caf\xe9", 0, false)]);
+        let names = parse_index(&data).unwrap();
+        assert_eq!(names, [PathBuf::from(OsStr::from_bytes(b"caf\xe9"))]);
+    }
+
+    #[test]
+    fn rejects_truncated_index() {
+        let data = index(2, &[entry(2, b"a.txt", 0, false)]);
+        for len in [8, 40, data.len() - 8] {
+            let err = parse_index(&data[..len]).unwrap_err();
+            assert_eq!(err.to_string(), "invalid git index", "length {}", len);
+        }
+
+        // An entry count far beyond the data fails without allocating for it
+        let mut data = index(2, &[]);
+        data[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            parse_index(&data).unwrap_err().to_string(),
+            "invalid git index"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let mut data = index(2, &[entry(2, b"a.txt", 0, false)]);
+        data[0..4].copy_from_slice(b"CRID");
+        assert_eq!(
+            parse_index(&data).unwrap_err().to_string(),
+            "invalid git index"
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let data = index(5, &[]);
+        let err = parse_index(&data).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported git index version 5");
+    }
+}
@@ -0,0 +1,255 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Trees of git revisions read from the object database of a repository, no checkout needed.
+//! Objects are listed by the `git` executable, which has to be installed.
+//!
+//! ```no_run
+//! use aldar::{gittree, Aldar};
+//! use std::path::Path;
+//!
+//! let fs = gittree::read_revision(Path::new("."), "v1.0").unwrap();
+//! let mut aldar = Aldar::new();
+//! aldar
+//!     .use_path("v1.0/src".to_string())
+//!     .use_filesystem(Box::new(fs))
+//!     .show_size(true);
+//! aldar.run().unwrap();
+//! ```
+
+use simple_error::SimpleError;
+use std::{
+    error::Error,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{error::AldarError, memfs::MemoryFs};
+
+/// Reads the tree of the revision `rev` of the repository at `repo`, a commit, branch, tag or
+/// tree. Entries are placed below a directory named like the revision, e.g. `main/src/lib.rs`.
+/// Files have the size of their blob and the time of the commit, submodules are empty
+/// directories.
+pub fn read_revision(repo: &Path, rev: &str) -> Result<MemoryFs, AldarError> {
+    read(repo, rev).map_err(|source| AldarError::Git {
+        path: repo.to_path_buf(),
+        source,
+    })
+}
+
+fn read(repo: &Path, rev: &str) -> Result<MemoryFs, Box<dyn Error + Send + Sync>> {
+    // Revisions are resolved first, so one looking like an option is never passed on
+    let tree = resolve(repo, rev, "tree")?;
+    let listing = git(
+        repo,
+        &["ls-tree", "-r", "-t", "-l", "-z", "--full-tree", &tree],
+    )?;
+    // Trees have no commit time
+    let modified = resolve(repo, rev, "commit")
+        .and_then(|commit| git(repo, &["log", "-1", "--format=%ct", &commit, "--"]))
+        .ok()
+        .and_then(|time| String::from_utf8_lossy(&time).trim().parse().ok())
+        .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+
+    let root = PathBuf::from(rev);
+    let mut fs = MemoryFs::new();
+    fs.dir(&root);
+    let mut links = vec![];
+    // Records are "<mode> <type> <object> <size>\t<path>", sizes are padded and - for trees
+    for record in listing.split(|b| *b == 0).filter(|r| !r.is_empty()) {
+        let invalid = || SimpleError::new(format!("invalid ls-tree output of {}", rev));
+        let tab = record
+            .iter()
+            .position(|b| *b == b'\t')
+            .ok_or_else(invalid)?;
+        let info = String::from_utf8_lossy(&record[..tab]);
+        let fields: Vec<&str> = info.split_whitespace().collect();
+        let (mode, object, size) = match fields.as_slice() {
+            [mode, _, object, size] => (*mode, *object, size.parse().unwrap_or(0)),
+            _ => return Err(invalid().into()),
+        };
+
+        let path = root.join(path_of(&record[tab + 1..]));
+        match mode {
+            "040000" | "160000" => fs.dir(&path),
+            "100755" => fs.executable(&path, size),
+            "120000" => {
+                links.push((path.clone(), object.to_string()));
+                fs.file(&path, size)
+            }
+            _ => fs.file(&path, size),
+        };
+        if let Some(modified) = modified {
+            fs.set_modified(&path, modified);
+        }
+    }
+
+    // Targets of symbolic links are the contents of their blobs
+    for ((path, _), target) in links.iter().zip(read_blobs(repo, &links)?) {
+        fs.symlink(path, path_of(&target));
+        if let Some(modified) = modified {
+            fs.set_modified(path, modified);
+        }
+    }
+    Ok(fs)
+}
+
+// Runs git in the repository and returns its output, failures with the first line it printed.
+fn git(repo: &Path, args: &[&str]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| SimpleError::new(format!("failed to run git: {}", e)))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.lines().next().unwrap_or("git failed").to_string();
+        return Err(Box::new(SimpleError::new(message)));
+    }
+    Ok(output.stdout)
+}
+
+// Returns the id of the object of type `kind` that `rev` points to.
+fn resolve(repo: &Path, rev: &str, kind: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let object = format!("{}^{{{}}}", rev, kind);
+    let id = git(
+        repo,
+        &["rev-parse", "--verify", "-q", "--end-of-options", &object],
+    )
+    // Quietly failing means the revision is unknown, other failures say why
+    .map_err(|e| match e.to_string().as_str() {
+        "git failed" => SimpleError::new(format!("unknown revision: {}", rev)).into(),
+        _ => e,
+    })?;
+    Ok(String::from_utf8_lossy(&id).trim().to_string())
+}
+
+// Reads the contents of the blobs of the links with a single git cat-file.
+fn read_blobs(
+    repo: &Path,
+    links: &[(PathBuf, String)],
+) -> Result<Vec<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+    if links.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| SimpleError::new(format!("failed to run git: {}", e)))?;
+    let mut stdin = child.stdin.take().ok_or("git has no input")?;
+    let stdout = child.stdout.take().ok_or("git has no output")?;
+
+    // Objects are requested while reading, so neither pipe fills up
+    let blobs = thread::scope(|scope| {
+        scope.spawn(move || {
+            for (_, object) in links {
+                writeln!(stdin, "{}", object)?;
+            }
+            Ok::<(), std::io::Error>(())
+        });
+
+        // Each blob is "<object> blob <size>\n<content>\n"
+        let mut stdout = BufReader::new(stdout);
+        let mut blobs = vec![];
+        for _ in links {
+            let mut header = String::new();
+            stdout.read_line(&mut header)?;
+            let size = header
+                .split_whitespace()
+                .nth(2)
+                .and_then(|size| size.parse::<usize>().ok())
+                .ok_or_else(|| SimpleError::new(format!("no such blob: {}", header.trim())))?;
+            let mut blob = vec![0; size + 1];
+            stdout.read_exact(&mut blob)?;
+            blob.pop();
+            blobs.push(blob);
+        }
+        Ok::<_, Box<dyn Error + Send + Sync>>(blobs)
+    });
+    child.wait()?;
+    blobs
+}
+
+#[cfg(unix)]
+fn path_of(bytes: &[u8]) -> PathBuf {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_of(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::temp_dir, Aldar, FileSystem};
+    use std::fs;
+
+    #[test]
+    fn reads_committed_tree() {
+        let repo = temp_dir("gittree");
+        fs::create_dir_all(repo.join("src")).unwrap();
+        fs::write(repo.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(repo.join("README.md"), "# readme").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("README.md", repo.join("link")).unwrap();
+        let run = |args: &[&str]| git(&repo, args).map(|_| ());
+        let committed = run(&["init", "-q"])
+            .and_then(|_| run(&["add", "."]))
+            .and_then(|_| {
+                run(&[
+                    "-c",
+                    "user.name=Aldar",
+                    "-c",
+                    "user.email=aldar@example.com",
+                    "commit",
+                    "-qm",
+                    "initial",
+                ])
+            });
+        // Files changed after the commit are not part of it
+        fs::write(repo.join("src/main.rs"), "changed").unwrap();
+
+        let result = committed.map(|_| {
+            let option = read_revision(&repo, "--output=out.txt");
+            (read_revision(&repo, "HEAD"), option)
+        });
+        let written = repo.join("out.txt").exists();
+        fs::remove_dir_all(&repo).unwrap();
+        let (fs, option) = result.unwrap();
+        let fs = fs.unwrap();
+
+        assert!(option.is_err() && !written);
+        assert_eq!(fs.metadata(Path::new("HEAD/src/main.rs")).unwrap().size, 12);
+        #[cfg(unix)]
+        assert_eq!(
+            fs.read_link(Path::new("HEAD/link")).unwrap(),
+            Path::new("README.md")
+        );
+        let tree = Aldar::new()
+            .use_path("HEAD".to_string())
+            .use_filesystem(Box::new(fs))
+            .build_tree()
+            .unwrap();
+        let names: Vec<&str> = tree.walk().map(|node| node.name.as_str()).collect();
+        #[cfg(unix)]
+        assert_eq!(names, ["HEAD", "src", "main.rs", "README.md", "link"]);
+        #[cfg(not(unix))]
+        assert_eq!(names, ["HEAD", "src", "main.rs", "README.md"]);
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use rayon::{prelude::*, ThreadPool};
+use sha2::{Digest, Sha256};
+use std::{fmt::Write, fs::File, io, path::Path};
+
+use crate::fsutil::{AldarExt, Entry};
+
+// Length of a hex encoded digest.
+const DIGEST_LEN: usize = 64;
+
+/// Returns the hex encoded SHA-256 digest of the file at `path`.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    let mut hex = String::with_capacity(DIGEST_LEN);
+    for b in hasher.finalize() {
+        write!(hex, "{:02x}", b).ok();
+    }
+    Ok(hex)
+}
+
+/// Computes the digests of all files among the entries on the pool, or on this thread without
+/// one. Files which can't be read get no digest.
+pub fn hash_entries(entries: &mut [Entry], pool: Option<&ThreadPool>) {
+    let hash = |e: &mut Entry| {
+        if !e.is_dir() {
+            let digest = match sha256_file(&e.path()) {
+                Ok(digest) => Some(digest),
+                Err(err) => {
+                    log::warn!("not hashed path={} error={}", e.path().display(), err);
+                    None
+                }
+            };
+            e.set_digest(digest);
+        }
+    };
+
+    match pool {
+        Some(pool) => pool.install(|| entries.par_iter_mut().for_each(hash)),
+        None => entries.iter_mut().for_each(hash),
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Icons printed before names, the default ones need a [Nerd Font](https://www.nerdfonts.com).
+
+use std::collections::HashMap;
+
+use crate::fsutil::{self, AldarExt, Entry};
+
+/// Maps entries to icons by type, name and extension.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Icons {
+    /// Directories without an icon of their own.
+    pub dir: String,
+    /// Files without an icon of their own.
+    pub file: String,
+    /// Symbolic links, whatever they point to.
+    pub symlink: String,
+    /// Executable files without an icon for their name or extension.
+    pub executable: String,
+    /// Directories and files by exact name, like `.git` or `Cargo.toml`.
+    pub names: HashMap<String, String>,
+    /// Files by extension, given without the leading dot and matched case insensitive. The
+    /// longest matching extension wins.
+    pub extensions: HashMap<String, String>,
+}
+
+impl Default for Icons {
+    fn default() -> Self {
+        let names = [
+            (".git", "\u{e5fb}"),
+            (".github", "\u{e5fd}"),
+            (".gitignore", "\u{f1d3}"),
+            ("Cargo.toml", "\u{e7a8}"),
+            ("Cargo.lock", "\u{e7a8}"),
+            ("Dockerfile", "\u{f308}"),
+            ("LICENSE", "\u{f02d}"),
+            ("node_modules", "\u{e5fa}"),
+        ];
+
+        let mut extensions = HashMap::new();
+        let groups: &[(&[&str], &str)] = &[
+            (&["rs"], "\u{e7a8}"),
+            (&["py"], "\u{e606}"),
+            (&["js", "mjs"], "\u{e74e}"),
+            (&["ts"], "\u{e628}"),
+            (&["go"], "\u{e626}"),
+            (&["c", "h"], "\u{e61e}"),
+            (&["cpp", "cc", "hpp"], "\u{e61d}"),
+            (&["java"], "\u{e738}"),
+            (&["html", "htm"], "\u{e736}"),
+            (&["css"], "\u{e749}"),
+            (&["sh", "bash", "zsh"], "\u{f489}"),
+            (&["md"], "\u{f48a}"),
+            (&["json"], "\u{e60b}"),
+            (&["toml", "yaml", "yml", "ini"], "\u{e615}"),
+            (&["lock"], "\u{f023}"),
+            (&["txt"], "\u{f15c}"),
+            (&["pdf"], "\u{f1c1}"),
+            (&["png", "jpg", "jpeg", "gif", "svg", "webp"], "\u{f1c5}"),
+            (&["zip", "tar", "gz", "xz", "zst", "7z"], "\u{f410}"),
+            (&["mp3", "flac", "ogg", "wav"], "\u{f001}"),
+            (&["mp4", "mkv", "webm", "mov"], "\u{f03d}"),
+        ];
+        for (exts, icon) in groups {
+            for ext in exts.iter() {
+                extensions.insert(ext.to_string(), icon.to_string());
+            }
+        }
+
+        Icons {
+            dir: "\u{f07b}".to_string(),
+            file: "\u{f15b}".to_string(),
+            symlink: "\u{f481}".to_string(),
+            executable: "\u{f489}".to_string(),
+            names: names
+                .iter()
+                .map(|(name, icon)| (name.to_string(), icon.to_string()))
+                .collect(),
+            extensions,
+        }
+    }
+}
+
+impl Icons {
+    /// Returns the icon of an entry.
+    pub fn icon(&self, entry: &Entry) -> &str {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.stat().is_some_and(|s| s.is_symlink) {
+            return &self.symlink;
+        }
+        if let Some(icon) = self.names.get(&name) {
+            return icon;
+        }
+        if entry.is_dir() {
+            return &self.dir;
+        }
+
+        match fsutil::longest_extension(&name, &self.extensions) {
+            Some(icon) => icon,
+            None if entry.is_executable() => &self.executable,
+            None => &self.file,
+        }
+    }
+
+    /// Returns the icon of a path without entry, like one read from a list.
+    pub fn icon_of_path(&self, name: &str, is_dir: bool) -> &str {
+        match (self.names.get(name), is_dir) {
+            (Some(icon), _) => icon,
+            (None, true) => &self.dir,
+            (None, false) => &self.file,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fsutil::FileSystem, memfs::MemoryFs, stat::Fields};
+
+    #[test]
+    fn icons_by_name_type_and_extension() {
+        let mut fs = MemoryFs::new();
+        fs.dir("root/src")
+            .dir("root/.git")
+            .file("root/main.RS", 1)
+            .file("root/a.tar.gz", 1)
+            .file("root/notes", 1)
+            .symlink("root/link", "src");
+
+        let mut icons = Icons::default();
+        icons.extensions.insert("tar.gz".into(), "T".into());
+
+        let entries = fs.read_dir("root".as_ref(), false, Fields::ALL).unwrap();
+        let icon = |name: &str| {
+            let entry = entries.iter().flatten().find(|e| e.file_name() == name);
+            icons.icon(entry.unwrap()).to_owned()
+        };
+
+        assert_eq!(icon("src"), icons.dir);
+        assert_eq!(icon(".git"), "\u{e5fb}");
+        assert_eq!(icon("main.RS"), "\u{e7a8}");
+        assert_eq!(icon("a.tar.gz"), "T");
+        assert_eq!(icon("notes"), icons.file);
+        assert_eq!(icon("link"), icons.symlink);
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Aldar lists the contents of directories as a tree, like the `tree` command.
+//!
+//! ```no_run
+//! use aldar::Aldar;
+//!
+//! let mut aldar = Aldar::new();
+//! aldar.use_paths(vec![".".to_string()]).show_hidden(true);
+//! aldar.run().unwrap();
+//! ```
+
+mod aldar;
+pub mod archive;
+mod arena;
+pub mod breakdown;
+mod builder;
+pub mod color;
+pub mod compat;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod diff;
+pub mod du;
+#[cfg(feature = "hash")]
+pub mod dupes;
+mod error;
+pub mod escape;
+mod event;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+pub mod fsutil;
+#[cfg(feature = "git")]
+mod git;
+#[cfg(feature = "git")]
+pub mod gittree;
+#[cfg(feature = "hash")]
+mod hash;
+pub mod icons;
+pub mod memfs;
+mod mounts;
+#[cfg(feature = "tokio")]
+mod nonblocking;
+pub mod pathtree;
+mod pattern;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+pub mod sort;
+pub mod stat;
+pub mod testing;
+pub mod tree;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod units;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+pub use crate::{
+    aldar::{
+        Aldar, Annotator, Filter, GlyphSet, Glyphs, Progress, ProgressFn, Stats, ASCII_GLYPHSET,
+        UNICODE_GLYPHSET,
+    },
+    builder::AldarBuilder,
+    color::{Colorizer, DefaultColorizer, PlainColorizer},
+    error::AldarError,
+    event::{Event, EventFn},
+    format::{Formatter, TextFormatter},
+    fsutil::{AldarExt, Backend, Entry, FileSystem},
+    pathtree::PathTree,
+    pattern::Matcher,
+    sort::{Comparator, Grouping, SortKey},
+    tree::TreeNode,
+};
@@ -3,19 +3,218 @@
 // Use of this source code is governed by an BSD-style
 // license that can be found in the LICENSE file.
 
-mod aldar;
-mod fsutil;
-
-use clap::Parser;
+use clap::{ArgEnum, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueSource};
+use clap_complete::Shell;
 use colored::*;
-use std::fs::File;
-use std::process;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::process::{self, Child, Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+use aldar::{
+    archive::SevenZipProvider,
+    breakdown::Breakdown,
+    color::{LsColors, Theme, THEMES},
+    compat::{self, Compat, Markup, MarkupFormatter},
+    config::{ColorsConfig, Config, GlyphStyle, IconsConfig, SizeUnits},
+    diff::{Criterion, DiffNode},
+    du::DiskUsage,
+    dupes,
+    escape::{Charset, Escaping, Normalization},
+    format::ReportFormat,
+    gittree,
+    icons::Icons,
+    sftp::SftpFs,
+    snapshot::Snapshot,
+    tui::Browser,
+    units::{self, Units},
+    watch::Watch,
+    Aldar, AldarError, Backend, Colorizer, DefaultColorizer, Entry, Glyphs, Grouping, Matcher,
+    PathTree, SortKey,
+};
+
+// Prints an error to where diagnostics go.
+macro_rules! print_error {
+    ($($arg:tt)*) => {
+        diagnose(format_args!("{} {}", "Error:".red(), format_args!($($arg)*)))
+    };
+}
+
+// Where errors and log messages are written, stderr unless --output-errors-to says otherwise.
+static DIAGNOSTICS: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
 
-use crate::aldar::Aldar;
+// Exit status of --fail-if-empty if no file was listed, errors exit with 1 and usage errors with 2.
+const EXIT_EMPTY: i32 = 3;
 
 #[derive(Parser, Debug)]
-#[clap(about, version, author)]
-struct Args {
+#[clap(about, version, author, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Commands>,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Print the tree of directories, the default without command
+    Tree(Box<TreeArgs>),
+    /// Keep the tree on screen and update it as files are created, modified or removed
+    Watch(Box<WatchArgs>),
+    /// Compare two directories and print one tree marking added, removed and modified entries
+    Diff(Box<DiffArgs>),
+    /// Sum up the sizes of directories and print the heaviest entries with their share
+    Du(Box<DuArgs>),
+    /// Find files with the same content and print them in groups with the space they waste
+    Dupes(Box<DupesArgs>),
+    /// Print the paths of the entries whose name matches a pattern, one per line
+    Find(Box<FindArgs>),
+    /// Print the tree of a git revision from the object database, without checking it out
+    GitTree(Box<GitTreeArgs>),
+    /// Count entries and their sizes by extension, type and depth
+    Stats(Box<StatsArgs>),
+    /// Save the tree with sizes, modification times and with --hash digests as JSON, to compare
+    /// it later with diff --against
+    Snapshot(Box<SnapshotArgs>),
+    /// Print the tree of a directory on a remote host, read over SFTP
+    Ssh(Box<SshArgs>),
+    /// Print the completion script of a shell
+    Completions {
+        #[clap(arg_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct SnapshotArgs {
+    /// Save the SHA-256 digest of each file, computed in parallel with --threads
+    #[clap(long)]
+    hash: bool,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
+    /// Wait MS milliseconds for more changes before redrawing the tree
+    #[clap(long, value_name = "MS", default_value = "200")]
+    debounce: u64,
+
+    /// Mark changed entries for SECS seconds
+    #[clap(long, value_name = "SECS", default_value = "10")]
+    highlight: u64,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Compare files by CRITERIA, e.g. size,hash
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "CRITERIA",
+        use_value_delimiter = true,
+        default_value = "size"
+    )]
+    compare: Vec<Criterion>,
+
+    /// List only changed entries and the directories containing them
+    #[clap(long)]
+    changes_only: bool,
+
+    /// Print the changed entries and their counts as JSON
+    #[clap(long)]
+    json: bool,
+
+    /// Compare the paths to a snapshot FILE taken with aldar snapshot
+    #[clap(long, value_name = "FILE")]
+    against: Option<String>,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct DuArgs {
+    /// Print the N heaviest entries and the directories containing them
+    #[clap(long, value_name = "N", default_value = "20")]
+    top: usize,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct DupesArgs {
+    /// Print the whole tree, marking duplicates with the number of their group
+    #[clap(long)]
+    inline: bool,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct FindArgs {
+    /// Regular expression the names have to match, besides the include patterns
+    #[clap(value_name = "PATTERN")]
+    pattern: String,
+
+    /// End paths with a NUL character instead of a newline, for xargs -0
+    #[clap(short = '0', long)]
+    print0: bool,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct GitTreeArgs {
+    /// Commit, branch, tag or tree to list, the PATHs are paths in it
+    #[clap(value_name = "REV", default_value = "HEAD")]
+    rev: String,
+
+    /// Read the revision from the repository in DIR, also a bare one
+    #[clap(long, value_name = "DIR", default_value = ".")]
+    repo: String,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct SshArgs {
+    /// Host and directory to list like scp takes them, e.g. user@host:/var/www, the PATHs are
+    /// further paths on the host
+    #[clap(value_name = "HOST:PATH")]
+    destination: String,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatsArgs {
+    /// Print the groups as JSON
+    #[clap(long)]
+    json: bool,
+
+    #[clap(flatten)]
+    tree: TreeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct TreeArgs {
     #[clap(short = 'a', long = "all", help = "List also hidden files")]
     all_files: bool,
 
@@ -43,6 +242,10 @@ struct Args {
     )]
     ignore_case: bool,
 
+    /// Ignore case when pattern matching unless the pattern contains an uppercase character
+    #[clap(long)]
+    smart_case: bool,
+
     #[clap(
         short = 'L',
         long = "level",
@@ -50,6 +253,73 @@ struct Args {
     )]
     level: Option<i32>,
 
+    /// List the tree level by level, annotated with the depth of each entry
+    #[clap(long)]
+    bfs: bool,
+
+    /// Print chains of directories containing only a single directory on one line
+    #[clap(long)]
+    compact: bool,
+
+    /// Print directories after their contents, with -s their cumulative size
+    #[clap(long)]
+    post_order: bool,
+
+    /// Do not descend into network or FUSE filesystems
+    #[clap(long)]
+    skip_remote_fs: bool,
+
+    /// Annotate mount points with their device and filesystem type
+    #[clap(long)]
+    mounts: bool,
+
+    /// List the entries of zip, tar, and with 7-Zip installed 7z and rar files as if they were
+    /// directories, without extracting them
+    #[clap(long)]
+    archives: bool,
+
+    /// Number of threads reading directories in parallel (Default: number of CPUs)
+    #[clap(long, value_name = "N", default_value = "0")]
+    threads: usize,
+
+    /// Maximum number of directories read at once (Default: half the open file limit)
+    #[clap(long, value_name = "N", default_value = "0")]
+    max_open_dirs: usize,
+
+    /// Log diagnostics to stderr, repeat for more: skipped entries, options and timings
+    #[clap(short = 'v', long, parse(from_occurrences))]
+    verbose: usize,
+
+    /// Report wall time, entries per second, metadata calls and bytes written to stderr
+    #[clap(long)]
+    perf_stats: bool,
+
+    /// Omit the directory and file count at the end of the tree
+    #[clap(long)]
+    noreport: bool,
+
+    /// Print the report from TEMPLATE, e.g. "{dirs} dirs, {files} files, {size}", other fields are
+    /// {errors}, {bytes} and {duration}
+    #[clap(long, value_name = "TEMPLATE", conflicts_with = "noreport")]
+    report_format: Option<ReportFormat>,
+
+    /// Exit with status 3 if no file is listed, e.g. as none matches the patterns
+    #[clap(long)]
+    fail_if_empty: bool,
+
+    /// Browse the tree in the terminal: expand and collapse directories, filter with /, open
+    /// entries with enter and copy their path with y
+    #[clap(long, conflicts_with_all = &["output", "stdin-paths"])]
+    interactive: bool,
+
+    /// Implementation used to read directories, io-uring requires the io-uring feature
+    #[clap(long, arg_enum, default_value = "std")]
+    backend: Backend,
+
+    /// Follow symbolic links to directories
+    #[clap(short = 'l', long)]
+    follow_links: bool,
+
     #[clap(
         short = 'f',
         long = "fullpath",
@@ -57,86 +327,1201 @@ struct Args {
     )]
     print_fullpath: bool,
 
+    /// Print the canonical absolute path of each root as header
+    #[clap(long)]
+    absolute_root: bool,
+
+    /// Print full paths relative to the current working directory (implies -f)
+    #[clap(long)]
+    relative: bool,
+
+    /// List only files tracked by git
+    #[clap(long)]
+    tracked: bool,
+
+    /// Sort the entries of each directory by the given comma separated keys
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "name",
+        use_value_delimiter = true,
+        require_value_delimiter = true
+    )]
+    sort: Vec<SortKey>,
+
+    /// Do not sort, list entries in directory order
+    #[clap(short = 'U', long)]
+    no_sort: bool,
+
+    /// List directories before files
+    #[clap(
+        long,
+        value_name = "BOOL",
+        default_value = "true",
+        min_values = 0,
+        max_values = 1,
+        require_equals = true,
+        default_missing_value = "true",
+        parse(try_from_str)
+    )]
+    dirs_first: bool,
+
+    /// List files before directories
+    #[clap(long, conflicts_with = "dirs-first")]
+    files_first: bool,
+
+    /// Ignore case when sorting by name
+    #[clap(long)]
+    sort_ignore_case: bool,
+
+    /// Reverse the order of the sort
+    #[clap(short = 'r', long)]
+    reverse: bool,
+
+    /// Sort the entries of each directory by modification time, newest first
+    #[clap(short = 't', long)]
+    sort_mtime: bool,
+
+    /// Sort the entries of each directory by status change time, newest first (with -D print it instead)
+    #[clap(short = 'c')]
+    sort_ctime: bool,
+
     /// Print the size in a more human readable way
     #[clap(short = 'H', long)]
     human_readable: bool,
 
+    /// Print human readable sizes in powers of 1000 (kB, MB) instead of 1024 (KiB, MiB)
+    #[clap(long)]
+    si: bool,
+
+    /// Separate the thousands of sizes in bytes, e.g. 1,234,567, as the locale does
+    #[clap(long)]
+    group_digits: bool,
+
     /// Print the size in bytes of each file
     #[clap(short = 's', long)]
     size: bool,
 
+    /// Print the date of the last modification or (-c) status change
+    #[clap(short = 'D', long)]
+    date: bool,
+
+    /// Print the depth of each entry, the entries of a root have depth 1
+    #[clap(long)]
+    show_depth: bool,
+
     /// Print non-printable characters as '?'
     #[clap(short = 'q', long)]
     replace_nonprintable: bool,
 
-    /// Output to file instead of stdout
-    #[clap(short = 'o', long)]
-    output: Option<String>,
+    /// Print an icon for the type of each entry before its name, requires a Nerd Font
+    #[clap(long)]
+    icons: bool,
+
+    #[clap(skip)]
+    icon_map: IconsConfig,
+
+    /// Shorten long names with an ellipsis so lines fit the width of the terminal or $COLUMNS
+    #[clap(long)]
+    truncate: bool,
+
+    /// Unicode normalization form names are printed, sorted and matched in
+    #[clap(long, arg_enum, value_name = "FORM", default_value = "none")]
+    normalize: Normalization,
+
+    /// Highlight the part of file names matched by the include patterns
+    #[clap(long, requires = "include-pattern")]
+    highlight_matches: bool,
+
+    /// Link names to their file:// URL, terminals supporting OSC 8 make them clickable
+    #[clap(long)]
+    hyperlinks: bool,
+
+    /// Quote names with spaces, quotes or control characters so they can be pasted into a shell
+    #[clap(short = 'Q', long)]
+    quote: bool,
+
+    /// Output to file instead of stdout ('-' for stdout), repeat it to write the same tree to
+    /// several files
+    #[clap(short = 'o', long, value_name = "FILE")]
+    output: Vec<String>,
+
+    /// Append to the output files instead of overwriting them
+    #[clap(long, requires = "output")]
+    output_append: bool,
+
+    /// Write the output to stdout as well as to the output files
+    #[clap(long, requires = "output")]
+    tee: bool,
+
+    /// Write errors and log messages to stderr (default), stdout or a file
+    #[clap(long, value_name = "DEST")]
+    output_errors_to: Option<String>,
+
+    /// When to page the output with $PAGER or less, auto only pages a terminal and long trees
+    #[clap(long, arg_enum, value_name = "WHEN", default_value = "auto")]
+    paging: Paging,
 
     /// Print ASCII only indentation lines
     #[clap(short = 'A', long)]
     ascii: bool,
 
-    /// Turn colorization off
-    #[clap(short = 'n', long)]
+    /// Character set of the output, names are transliterated to it and other charsets than
+    /// UTF-8 use ASCII indentation lines
+    #[clap(long, arg_enum, value_name = "CHARSET", default_value = "utf-8")]
+    charset: Charset,
+
+    /// When to color names, auto only colors a terminal and honors NO_COLOR and CLICOLOR_FORCE
+    #[clap(long, arg_enum, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
+
+    /// Color entries by type and extension like ls, following LS_COLORS or the dircolors defaults
+    #[clap(long)]
+    ls_colors: bool,
+
+    /// Color entries with the theme NAME, styles in the [colors] section of the config override it
+    #[clap(long, value_name = "NAME", possible_values = THEMES)]
+    theme: Option<String>,
+
+    #[clap(skip)]
+    theme_colors: ColorsConfig,
+
+    /// Turn colorization off, same as --color=never
+    #[clap(short = 'n', long, hide = true)]
     no_colors: bool,
 
-    /// Working directory of this command (Default: Current directory)
-    path: Option<String>,
+    /// Read defaults from FILE instead of ~/.config/aldar/config.toml, flags override them
+    #[clap(long, value_name = "FILE")]
+    config: Option<String>,
+
+    /// Follow the flags and output of another tool, for scripts parsing its output. With tree,
+    /// flags are read like GNU tree does, e.g. -I excludes a wildcard pattern
+    #[clap(long, arg_enum, value_name = "TOOL", default_value = "none")]
+    compat: Compat,
+
+    /// Print the tree as JSON or XML like GNU tree, -J and -X with --compat tree
+    #[clap(long, arg_enum, value_name = "FORMAT", hide = true)]
+    markup: Option<Markup>,
+
+    /// Read the directories to list from FILE, one per line ('-' for stdin)
+    #[clap(long, value_name = "FILE")]
+    fromfile: Option<String>,
+
+    /// Print the tree of newline or NUL separated paths read from stdin
+    #[clap(long, conflicts_with_all = &["path", "fromfile"])]
+    stdin_paths: bool,
+
+    /// Working directories of this command (Default: Current directory)
+    path: Vec<String>,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Paging {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
 }
 
 fn main() {
-    let args: Args = Args::parse();
+    let args = match compat::translate_args(env::args_os().collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    };
+    let matches = Cli::command().get_matches_from(args);
+    let cli = match Cli::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(e) => e.exit(),
+    };
 
-    // Disable color if specified or a file is used as output
-    if args.no_colors || args.output.is_some() {
-        colored::control::set_override(false);
+    match cli.command {
+        Some(Commands::Tree(args)) => tree(*args, matches.subcommand_matches("tree").unwrap()),
+        Some(Commands::Watch(args)) => watch(*args, matches.subcommand_matches("watch").unwrap()),
+        Some(Commands::Diff(args)) => diff(*args, matches.subcommand_matches("diff").unwrap()),
+        Some(Commands::Du(args)) => du(*args, matches.subcommand_matches("du").unwrap()),
+        Some(Commands::Dupes(args)) => dupes(*args, matches.subcommand_matches("dupes").unwrap()),
+        Some(Commands::Find(args)) => find(*args, matches.subcommand_matches("find").unwrap()),
+        Some(Commands::GitTree(args)) => {
+            git_tree(*args, matches.subcommand_matches("git-tree").unwrap())
+        }
+        Some(Commands::Stats(args)) => stats(*args, matches.subcommand_matches("stats").unwrap()),
+        Some(Commands::Snapshot(args)) => {
+            snapshot(*args, matches.subcommand_matches("snapshot").unwrap())
+        }
+        Some(Commands::Ssh(args)) => ssh(*args, matches.subcommand_matches("ssh").unwrap()),
+        Some(Commands::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Cli::command(), "aldar", &mut io::stdout())
+        }
+        None => tree(cli.tree, &matches),
+    }
+}
+
+// Configures the listing from the command line and the config file, errors exit.
+fn build(args: &mut TreeArgs, matches: &ArgMatches) -> Aldar {
+    if let Some(destination) = args.output_errors_to.as_deref() {
+        let sink: Box<dyn Write + Send> = match destination {
+            "stderr" => Box::new(io::stderr()),
+            "stdout" | "-" => Box::new(io::stdout()),
+            path => match File::create(path) {
+                Ok(file) => Box::new(file),
+                Err(e) => {
+                    print_error!("failed to open file {}: {}", path, e);
+                    process::exit(1);
+                }
+            },
+        };
+        DIAGNOSTICS.set(Mutex::new(sink)).ok();
+    }
+
+    let config = match args.config.as_ref() {
+        Some(path) => Config::load(path),
+        None => Config::load_default(),
+    };
+    match config {
+        Ok(config) => apply_config(args, matches, config),
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    log::set_logger(&DiagnosticsLogger).ok();
+    log::set_max_level(match args.verbose {
+        0 => log::LevelFilter::Error,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    });
+
+    match (args.color, args.no_colors) {
+        (_, true) | (ColorChoice::Never, _) => colored::control::set_override(false),
+        (ColorChoice::Always, _) => colored::control::set_override(true),
+        // A file is no terminal, only CLICOLOR_FORCE colors it
+        (ColorChoice::Auto, _) if args.output.iter().any(|o| o != "-") => {
+            colored::control::set_override(env::var("CLICOLOR_FORCE").is_ok_and(|v| v != "0"))
+        }
+        (ColorChoice::Auto, _) => {}
+    }
+
+    let mut paths = args.path.clone();
+    if let Some(fromfile) = args.fromfile.as_ref() {
+        match read_paths(fromfile) {
+            Ok(p) => paths.extend(p),
+            Err(e) => {
+                print_error!("failed to read file {}: {}", fromfile, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if paths.is_empty() {
+        paths.push(".".to_string());
     }
 
     let mut a = Aldar::new();
     let aldar = a
-        .use_path(args.path.unwrap_or_else(|| ".".to_string()))
+        .use_paths(paths)
         .show_hidden(args.all_files)
         .show_dirs_only(args.dir_only)
+        .show_tracked_only(args.tracked)
         .case_sensitive(args.ignore_case)
-        .use_glyphset(match args.ascii {
-            true => Box::new(aldar::ASCII_GLYPHSET),
-            false => Box::new(aldar::UNICODE_GLYPHSET),
+        .smart_case(args.smart_case)
+        .use_charset(args.charset)
+        .use_glyphset(glyphs(args))
+        .use_sort_keys(match (args.sort_ctime, args.sort_mtime) {
+            (true, _) => &[SortKey::Ctime],
+            (_, true) => &[SortKey::Mtime],
+            _ => &args.sort,
+        })
+        .use_grouping(match (args.files_first, args.dirs_first) {
+            (true, _) => Grouping::FilesFirst,
+            (_, true) => Grouping::DirsFirst,
+            _ => Grouping::None,
+        })
+        .sort_ignore_case(args.sort_ignore_case)
+        .reverse_sort(args.reverse)
+        .no_sort(args.no_sort)
+        .use_max_level(args.level.unwrap_or(-1))
+        .use_threads(args.threads)
+        .use_max_open_dirs(args.max_open_dirs)
+        .use_backend(args.backend)
+        .show_perf_stats(args.perf_stats)
+        .show_report(!args.noreport)
+        .use_report_format(args.report_format.take())
+        .use_compat(args.compat)
+        .show_hyperlinks(args.hyperlinks)
+        .use_normalization(args.normalize)
+        .highlight_matches(args.highlight_matches)
+        .use_max_width(match args.truncate {
+            true => terminal_width().unwrap_or(0),
+            false => 0,
         })
-        .use_max_level(args.level.unwrap_or_else(|| -1))
-        .show_fullpath(args.print_fullpath)
+        .follow_links(args.follow_links)
+        .skip_remote_fs(args.skip_remote_fs)
+        .show_mounts(args.mounts)
+        .show_archives(args.archives)
+        .add_archive_provider(Box::new(SevenZipProvider::default()))
+        .breadth_first(args.bfs)
+        .compact_dirs(args.compact)
+        .post_order(args.post_order)
+        .show_fullpath(args.print_fullpath || args.relative)
+        .show_relative_paths(args.relative)
+        .show_absolute_root(args.absolute_root)
         .show_size(args.size)
-        .show_human_readable(args.human_readable)
-        .do_replace_nonprintable_chars(args.replace_nonprintable);
+        .show_date(args.date)
+        .show_depth(args.show_depth)
+        .use_change_time(args.sort_ctime)
+        .show_human_readable(args.human_readable || args.si)
+        .use_units(match args.si {
+            true => Units::Si,
+            false => Units::Iec,
+        })
+        .use_digit_separator(args.group_digits.then(|| {
+            // The first of these variables set decides the locale of numbers
+            let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+                .iter()
+                .find_map(|name| env::var(name).ok().filter(|v| !v.is_empty()));
+            units::thousands_separator(&locale.unwrap_or_default()).to_string()
+        }))
+        .use_escaping(match (args.quote, args.replace_nonprintable) {
+            (true, _) => Escaping::Quote,
+            (_, true) => Escaping::Replace,
+            _ => Escaping::None,
+        });
 
-    let error_str = "Error:".red();
+    if args.icons {
+        let mut icons = Icons::default();
+        args.icon_map.apply(&mut icons);
+        aldar.use_icons(icons);
+    }
 
-    if let Some(output) = args.output {
-        let result = File::create(output.clone());
-        if result.is_err() {
-            println!(
-                "{} failed to open file {}: {}",
-                error_str,
-                output,
-                result.unwrap_err()
-            );
-            process::exit(1);
-        }
+    if let Some(colorizer) = colorizer(args) {
+        aldar.use_colorizer(colorizer);
+    }
 
-        aldar.use_writer(Box::new(result.unwrap()));
+    if let Some(markup) = args.markup {
+        let formatter = MarkupFormatter::new(markup)
+            .with_size(args.size)
+            .with_date(args.date, args.sort_ctime);
+        aldar.use_formatter(Box::new(formatter));
     }
 
-    if let Some(pattern) = args.include_pattern {
+    if let Some(pattern) = args.include_pattern.as_ref() {
         let v: Vec<&str> = pattern.iter().map(String::as_ref).collect();
-        aldar.set_include_patterns(&v);
+        if let Err(e) = aldar.set_include_patterns(&v) {
+            print_error!("{}", e);
+            process::exit(1);
+        }
     }
 
-    if let Some(pattern) = args.exclude_pattern {
+    if let Some(pattern) = args.exclude_pattern.as_ref() {
         let v: Vec<&str> = pattern.iter().map(String::as_ref).collect();
-        aldar.set_exclude_patterns(&v);
+        if let Err(e) = aldar.set_exclude_patterns(&v) {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    a
+}
+
+fn tree(mut args: TreeArgs, matches: &ArgMatches) {
+    let aldar = build(&mut args, matches);
+    if args.interactive {
+        return browse(aldar, &args);
+    }
+    list(aldar, &args);
+}
+
+// Prints the tree to the outputs or the pager, errors exit.
+fn list(aldar: Aldar, args: &TreeArgs) {
+    list_with_report(aldar, args, None);
+}
+
+// Prints the tree like list, followed by a report line in the same outputs.
+fn list_with_report(mut aldar: Aldar, args: &TreeArgs, report: Option<&str>) {
+    let mut writers = open_outputs(args);
+    let mut paged = false;
+    let writer: Box<dyn Write + Send> = if writers.len() > 1 {
+        Box::new(Tee(writers))
+    } else if let Some(writer) = writers.pop() {
+        writer
+    } else if let Some(pager) = Pager::new(args.paging) {
+        paged = true;
+        Box::new(pager)
+    } else {
+        Box::new(io::stdout())
+    };
+    let writer = Shared(Arc::new(Mutex::new(writer)));
+    aldar.use_writer(Box::new(writer.clone()));
+
+    let result = match args.stdin_paths {
+        true => {
+            let mut input = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut input) {
+                print_error!("failed to read stdin: {}", e);
+                process::exit(1);
+            }
+            aldar.run_with_paths(&PathTree::parse(&input))
+        }
+        false => aldar.run(),
+    };
+    let result = match report {
+        Some(report) => result.and_then(|stats| {
+            let mut writer = writer.clone();
+            writeln!(writer, "{}", report)
+                .and_then(|_| writer.flush())
+                .map(|_| stats)
+                .map_err(AldarError::Write)
+        }),
+        None => result,
+    };
+
+    if paged {
+        // Drops the pager, which prints held back output or waits for the user to be done.
+        aldar.use_writer(Box::new(io::sink()));
+        drop(writer);
     }
 
-    if let Err(e) = aldar.run() {
-        println!("{} {}", error_str, e);
+    match result {
+        // The reader went away, e.g. output piped into head, there is nobody left to tell.
+        Err(AldarError::Write(e)) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(AldarError::Write(e)) => {
+            print_error!("failed to write output: {}", e);
+            process::exit(1);
+        }
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+        Ok(stats) if args.fail_if_empty && stats.files == 0 => process::exit(EXIT_EMPTY),
+        Ok(_) => {}
     }
 }
+
+fn browse(mut aldar: Aldar, args: &TreeArgs) {
+    if !io::stdout().is_terminal() {
+        print_error!("--interactive requires a terminal");
+        process::exit(2);
+    }
+
+    let units = match args.si {
+        true => Units::Si,
+        false => Units::Iec,
+    };
+    let result = aldar.build_tree().and_then(|tree| {
+        Browser::new(tree)
+            .use_units(units)
+            .run()
+            .map_err(AldarError::Write)
+    });
+    if let Err(e) = result {
+        print_error!("{}", e);
+        process::exit(1);
+    }
+}
+
+// Opens the files given with -o, stdout for - and with --tee. Failing to open one exits.
+fn open_outputs(args: &TreeArgs) -> Vec<Box<dyn Write + Send>> {
+    let mut writers: Vec<Box<dyn Write + Send>> = Vec::new();
+    for output in &args.output {
+        if output == "-" {
+            writers.push(Box::new(io::stdout()));
+            continue;
+        }
+
+        let file = match args.output_append {
+            true => OpenOptions::new().create(true).append(true).open(output),
+            false => File::create(output),
+        };
+        match file {
+            Ok(file) => writers.push(Box::new(file)),
+            Err(e) => {
+                print_error!("failed to open file {}: {}", output, e);
+                process::exit(1);
+            }
+        }
+    }
+    if args.tee {
+        writers.push(Box::new(io::stdout()));
+    }
+    writers
+}
+
+fn du(mut args: DuArgs, matches: &ArgMatches) {
+    // Files and deeper levels are needed for the sizes, they are left out when printing
+    let dirs_only = mem::take(&mut args.tree.dir_only);
+    let level = args.tree.level.take();
+    let mut aldar = build(&mut args.tree, matches);
+    let mut usage = match aldar.build_tree() {
+        Ok(tree) => DiskUsage::of(&tree),
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(level) = level.filter(|level| *level >= 0) {
+        let depth = level as usize + 1;
+        match usage.name.is_empty() {
+            // Several roots are the children of a node without name
+            true => usage
+                .children
+                .iter_mut()
+                .for_each(|root| root.truncate(depth)),
+            false => usage.truncate(depth),
+        }
+    }
+
+    let units = match args.tree.si {
+        true => Units::Si,
+        false => Units::Iec,
+    };
+    let glyphs = glyphs(&args.tree);
+    let mut stdout = io::stdout().lock();
+    match usage.write_top(&mut stdout, args.top, dirs_only, glyphs.as_ref(), units) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => {
+            print_error!("failed to write output: {}", e);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+fn dupes(mut args: DupesArgs, matches: &ArgMatches) {
+    let mut aldar = build(&mut args.tree, matches);
+    let tree = match aldar.build_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    };
+    let groups = dupes::find(&tree);
+
+    let units = match args.tree.si {
+        true => Units::Si,
+        false => Units::Iec,
+    };
+    let size = |size: u64| match args.tree.human_readable || args.tree.si {
+        true if size >= units.base() => units.format(size),
+        _ => format!("{} bytes", size),
+    };
+    let files: usize = groups.iter().map(|group| group.paths.len() - 1).sum();
+    let reclaimable: u64 = groups.iter().map(|group| group.reclaimable()).sum();
+    let report = format!(
+        "{} groups, {} duplicate files, {} reclaimable",
+        groups.len(),
+        files,
+        size(reclaimable)
+    );
+
+    if args.inline {
+        let mut marks = HashMap::new();
+        for (i, group) in groups.iter().enumerate() {
+            for path in &group.paths {
+                marks.insert(path.clone(), i + 1);
+            }
+        }
+        aldar.annotate_with(move |entry| {
+            let group = marks.get(&entry.path())?;
+            Some(format!("[dup {}]", group).yellow().to_string())
+        });
+        list_with_report(aldar, &args.tree, Some(&report));
+        return;
+    }
+
+    let glyphs = glyphs(&args.tree);
+    let mut out = String::new();
+    for (i, group) in groups.iter().enumerate() {
+        let header = format!(
+            "[{}] {} files of {}",
+            i + 1,
+            group.paths.len(),
+            size(group.size)
+        );
+        out.push_str(&format!("{}\n", header.bold()));
+        for (j, path) in group.paths.iter().enumerate() {
+            let glyph = match j + 1 == group.paths.len() {
+                true => glyphs.last(),
+                false => glyphs.item(),
+            };
+            out.push_str(&format!("{} {}\n", glyph, path.display()));
+        }
+        out.push('\n');
+    }
+    out.push_str(&report);
+    out.push('\n');
+
+    let mut stdout = io::stdout().lock();
+    match stdout.write_all(out.as_bytes()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => {
+            print_error!("failed to write output: {}", e);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+fn find(mut args: FindArgs, matches: &ArgMatches) {
+    let mut aldar = build(&mut args.tree, matches);
+    // The include patterns match if any of them does, the pattern has to match as well
+    let normalization = args.tree.normalize;
+    let pattern = normalization.apply(&args.pattern).into_owned();
+    let pattern = match Matcher::new(&[pattern], args.tree.ignore_case, args.tree.smart_case) {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    };
+    let tree = match aldar.build_tree() {
+        Ok(tree) => tree,
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let colorizer = colorizer(&args.tree).unwrap_or_else(|| Box::new(DefaultColorizer));
+    // Several roots are the children of a node without name
+    let roots = match tree.name.is_empty() {
+        true => tree.children.iter().collect(),
+        false => vec![&tree],
+    };
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut write = || -> io::Result<()> {
+        for node in roots.iter().flat_map(|root| root.walk().skip(1)) {
+            if !pattern.is_match(&normalization.apply(&node.name)) {
+                continue;
+            }
+            // Files are filtered while listing, directories only here
+            if node.is_dir && !aldar.matches_patterns(&node.name) {
+                continue;
+            }
+
+            // Paths below the current directory are printed without ./ like fd does
+            let path = node.path.strip_prefix(".").unwrap_or(&node.path);
+            // Consumers of NUL separated paths get them as they are, to open them
+            if args.print0 {
+                out.write_all(path.as_os_str().as_encoded_bytes())?;
+                out.write_all(b"\0")?;
+                continue;
+            }
+
+            let entry = Entry::with_stat(node.path.clone(), node.metadata);
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => {
+                    format!("{}{}", parent.display(), std::path::MAIN_SEPARATOR)
+                }
+                _ => String::new(),
+            };
+            let name = colorizer.entry(&entry, &node.name);
+            writeln!(out, "{}{}", parent, name)?;
+        }
+        out.flush()
+    };
+    match write() {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => {
+            print_error!("failed to write output: {}", e);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+fn git_tree(mut args: GitTreeArgs, matches: &ArgMatches) {
+    let fs = match gittree::read_revision(Path::new(&args.repo), &args.rev) {
+        Ok(fs) => fs,
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // The entries of the revision are below a directory named like it
+    let root = Path::new(&args.rev);
+    args.tree.path = match args.tree.path.is_empty() {
+        true => vec![args.rev.clone()],
+        false => args
+            .tree
+            .path
+            .iter()
+            .map(|path| root.join(path).display().to_string())
+            .collect(),
+    };
+    let mut aldar = build(&mut args.tree, matches);
+    aldar.use_filesystem(Box::new(fs));
+    match args.tree.interactive {
+        true => browse(aldar, &args.tree),
+        false => list(aldar, &args.tree),
+    }
+}
+
+fn ssh(mut args: SshArgs, matches: &ArgMatches) {
+    // Paths are relative to the home directory on the host, as with scp
+    let (host, path) = match args.destination.split_once(':') {
+        Some((host, path)) => (host, path.trim_start_matches("~/")),
+        None => (args.destination.as_str(), ""),
+    };
+    let fs = match SftpFs::connect(host) {
+        Ok(fs) => fs,
+        Err(e) => {
+            print_error!("{}: {}", host, e);
+            process::exit(1);
+        }
+    };
+
+    let path = match path {
+        "" | "~" => ".".to_string(),
+        path => path.to_string(),
+    };
+    args.tree.path.insert(0, path);
+    let mut aldar = build(&mut args.tree, matches);
+    aldar.use_filesystem(Box::new(fs));
+    match args.tree.interactive {
+        true => browse(aldar, &args.tree),
+        false => list(aldar, &args.tree),
+    }
+}
+
+fn stats(mut args: StatsArgs, matches: &ArgMatches) {
+    let mut aldar = build(&mut args.tree, matches);
+    let breakdown = match aldar.build_tree() {
+        Ok(tree) => Breakdown::of(&tree),
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let units = match args.tree.si {
+        true => Units::Si,
+        false => Units::Iec,
+    };
+    let mut stdout = io::stdout().lock();
+    let result = match args.json {
+        true => serde_json::to_writer_pretty(&mut stdout, &breakdown)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(stdout)),
+        false => breakdown.write_tables(&mut stdout, units),
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => {
+            print_error!("failed to write output: {}", e);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+fn snapshot(mut args: SnapshotArgs, matches: &ArgMatches) {
+    let mut aldar = build(&mut args.tree, matches);
+    aldar.compute_digests(args.hash);
+    let snapshot = match Snapshot::take(&mut aldar) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            print_error!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut writers = open_outputs(&args.tree);
+    let result = match writers.len() {
+        0 => snapshot.write(io::stdout()),
+        1 => snapshot.write(writers.pop().unwrap()),
+        _ => snapshot.write(Tee(writers)),
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => {
+            print_error!("failed to write output: {}", e);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+fn diff(mut args: DiffArgs, matches: &ArgMatches) {
+    let (expected, message) = match args.against {
+        Some(_) => (
+            1,
+            "diff --against requires one path, e.g. aldar diff --against state.json dir",
+        ),
+        None => (2, "diff requires two paths, e.g. aldar diff old new"),
+    };
+    if args.tree.path.len() != expected {
+        Cli::command()
+            .error(clap::ErrorKind::WrongNumberOfValues, message)
+            .exit();
+    }
+
+    // Digests are computed in parallel while listing
+    let hashed = args.compare.contains(&Criterion::Hash);
+    let mut aldar = build(&mut args.tree, matches);
+    aldar.compute_digests(hashed);
+    let fail = |e: AldarError| -> ! {
+        print_error!("{}", e);
+        process::exit(1);
+    };
+
+    let (before, after) = match args.against.as_ref() {
+        Some(path) => {
+            let snapshot = Snapshot::load(path).unwrap_or_else(|e| fail(e));
+            if hashed && !snapshot.has_digests() {
+                print_error!("{} has no digests to compare, take it with --hash", path);
+                process::exit(1);
+            }
+            (
+                snapshot.root,
+                aldar.build_tree().unwrap_or_else(|e| fail(e)),
+            )
+        }
+        None => {
+            let mut build_tree = |path: &str| {
+                aldar
+                    .use_path(path.to_string())
+                    .build_tree()
+                    .unwrap_or_else(|e| fail(e))
+            };
+            (
+                build_tree(&args.tree.path[0]),
+                build_tree(&args.tree.path[1]),
+            )
+        }
+    };
+    let diff = DiffNode::compare(&before, &after, &args.compare);
+
+    let mut stdout = io::stdout().lock();
+    let result = match args.json {
+        true => {
+            let report = serde_json::json!({
+                "summary": diff.summary(),
+                "changes": diff.differences(),
+            });
+            serde_json::to_writer_pretty(&mut stdout, &report)
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(stdout))
+        }
+        false => diff.write_tree(&mut stdout, glyphs(&args.tree).as_ref(), args.changes_only),
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => {
+            print_error!("failed to write output: {}", e);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+fn watch(mut args: WatchArgs, matches: &ArgMatches) {
+    let mut aldar = build(&mut args.tree, matches);
+    let result = Watch::new()
+        .use_debounce(Duration::from_millis(args.debounce))
+        .use_highlight(Duration::from_secs(args.highlight))
+        .run(&mut aldar);
+    if let Err(e) = result {
+        print_error!("{}", e);
+        process::exit(1);
+    }
+}
+
+// Writes a line to where diagnostics go.
+fn diagnose(message: fmt::Arguments) {
+    let sink = DIAGNOSTICS.get_or_init(|| Mutex::new(Box::new(io::stderr())));
+    let mut sink = sink.lock().unwrap_or_else(PoisonError::into_inner);
+    writeln!(sink, "{}", message).ok();
+    sink.flush().ok();
+}
+
+// Writes log records to where diagnostics go as `LEVEL target: message`, filtered by the max
+// level.
+struct DiagnosticsLogger;
+
+impl log::Log for DiagnosticsLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        diagnose(format_args!(
+            "{:<5} {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+// Uses the settings of the config file for all options not given on the command line.
+fn apply_config(args: &mut TreeArgs, matches: &ArgMatches, config: Config) {
+    let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    let enabled = config.colors.enabled;
+    if let (Some(colors), false) = (enabled, given("color") || given("no-colors")) {
+        args.color = match colors {
+            true => ColorChoice::Auto,
+            false => ColorChoice::Never,
+        };
+    }
+
+    if let (Some(glyphs), false) = (config.glyphs, given("ascii")) {
+        args.ascii = glyphs == GlyphStyle::Ascii;
+    }
+
+    if let (Some(exclude), false) = (config.exclude, given("exclude-pattern")) {
+        args.exclude_pattern = Some(exclude);
+    }
+
+    if let (Some(sort), false) = (config.sort, given("sort")) {
+        args.sort = sort;
+    }
+
+    let grouping_given = given("dirs-first") || given("files-first");
+    if let (Some(dirs_first), false) = (config.dirs_first, grouping_given) {
+        args.dirs_first = dirs_first;
+    }
+
+    if let (Some(reverse), false) = (config.reverse, given("reverse")) {
+        args.reverse = reverse;
+    }
+
+    if let (Some(size), false) = (config.size, given("human-readable") || given("si")) {
+        args.human_readable = size == SizeUnits::Human;
+        args.si = size == SizeUnits::Si;
+    }
+
+    if !given("theme") {
+        args.theme = config.colors.theme.clone();
+    }
+    args.theme_colors = config.colors;
+
+    if let (Some(icons), false) = (config.icons.enabled, given("icons")) {
+        args.icons = icons;
+    }
+    args.icon_map = config.icons;
+}
+
+// Writes the output to all writers, so the tree is only read once for all of them.
+struct Tee(Vec<Box<dyn Write + Send>>);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for writer in &mut self.0 {
+            writer.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.iter_mut().try_for_each(|writer| writer.flush())
+    }
+}
+
+// Shares a writer between aldar and what is printed after the tree.
+#[derive(Clone)]
+struct Shared(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .flush()
+    }
+}
+
+// Writes the output to the pager. In auto mode output is held back until it has more lines than
+// fit the terminal, shorter output is printed to stdout once the pager is dropped.
+struct Pager {
+    paging: Paging,
+    height: usize,
+    held: Vec<u8>,
+    out: Option<Box<dyn Write + Send>>,
+    child: Option<Child>,
+}
+
+impl Pager {
+    // Returns None if the output isn't paged.
+    fn new(paging: Paging) -> Option<Pager> {
+        let height = match paging {
+            Paging::Never => return None,
+            Paging::Auto if !io::stdout().is_terminal() => return None,
+            Paging::Auto => terminal_height(),
+            Paging::Always => None,
+        };
+        let mut pager = Pager {
+            paging,
+            height: height.unwrap_or(0),
+            held: vec![],
+            out: None,
+            child: None,
+        };
+        // Without a known height the pager decides itself whether the tree fits
+        if height.is_none() {
+            pager.open();
+        }
+        Some(pager)
+    }
+
+    // Starts the pager with the held back output, stdout is used if it can't be started.
+    fn open(&mut self) -> &mut Box<dyn Write + Send> {
+        let out: Box<dyn Write + Send> = match spawn_pager(self.paging) {
+            Some(mut child) => {
+                let stdin = child.stdin.take();
+                self.child = Some(child);
+                match stdin {
+                    Some(stdin) => Box::new(stdin),
+                    None => Box::new(io::sink()),
+                }
+            }
+            None => Box::new(io::stdout()),
+        };
+        self.out.insert(out)
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(out) = self.out.as_mut() {
+            return out.write(buf);
+        }
+
+        self.held.extend_from_slice(buf);
+        if self.held.iter().filter(|b| **b == b'\n').count() >= self.height {
+            let held = std::mem::take(&mut self.held);
+            self.open().write_all(&held)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.out.as_mut() {
+            Some(out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        if !self.held.is_empty() {
+            io::stdout().write_all(&self.held).ok();
+        }
+        // Closes the input of the pager, it exits once the user is done reading.
+        self.out = None;
+        if let Some(child) = self.child.as_mut() {
+            child.wait().ok();
+        }
+    }
+}
+
+// Returns the colorizer of --ls-colors or the theme, None for the default colors. Unknown
+// themes exit.
+fn colorizer(args: &TreeArgs) -> Option<Box<dyn Colorizer>> {
+    if args.ls_colors {
+        let colors = LsColors::from_env().unwrap_or_else(LsColors::dircolors);
+        return Some(Box::new(colors));
+    }
+    if args.theme.is_none() && !args.theme_colors.has_styles() {
+        return None;
+    }
+
+    let name = args.theme.as_deref().unwrap_or("default");
+    match Theme::named(name) {
+        Some(mut theme) => {
+            args.theme_colors.apply(&mut theme);
+            Some(Box::new(theme))
+        }
+        None => {
+            let available = THEMES.join(", ");
+            print_error!("unknown theme {}, available: {}", name, available);
+            process::exit(1);
+        }
+    }
+}
+
+// Returns the glyphs lines are drawn with, ASCII unless the output is UTF-8.
+fn glyphs(args: &TreeArgs) -> Box<dyn Glyphs> {
+    match args.ascii || args.charset != Charset::Utf8 {
+        true => Box::new(aldar::ASCII_GLYPHSET),
+        false => Box::new(aldar::UNICODE_GLYPHSET),
+    }
+}
+
+// Returns the width of the terminal, or $COLUMNS if the output isn't one.
+fn terminal_width() -> Option<usize> {
+    match terminal_size::terminal_size() {
+        Some((terminal_size::Width(width), _)) => Some(width as usize),
+        None => env::var("COLUMNS").ok()?.parse().ok(),
+    }
+}
+
+fn terminal_height() -> Option<usize> {
+    match terminal_size::terminal_size() {
+        Some((_, terminal_size::Height(height))) => Some(height as usize),
+        None => env::var("LINES").ok()?.parse().ok(),
+    }
+}
+
+// Starts the pager the output is written to, none if it can't be started.
+fn spawn_pager(paging: Paging) -> Option<Child> {
+    let pager = env::var("PAGER").unwrap_or_default();
+    let mut words = pager.split_whitespace();
+    let program = words.next().unwrap_or("less");
+    let mut args: Vec<&str> = words.collect();
+    // Plain less needs to pass colors through and, like with bat, quits if the tree fits
+    if program.ends_with("less") && args.is_empty() {
+        args.push("-R");
+        if paging == Paging::Auto {
+            args.push("-F");
+        }
+    }
+
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .ok()
+}
+
+// Reads non empty lines from the given file or stdin if file is '-'.
+fn read_paths(file: &str) -> io::Result<Vec<String>> {
+    let reader: Box<dyn BufRead> = match file {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        _ => Box::new(BufReader::new(File::open(file)?)),
+    };
+
+    let mut paths = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            paths.push(line);
+        }
+    }
+
+    Ok(paths)
+}
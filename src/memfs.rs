@@ -0,0 +1,264 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+#[cfg(unix)]
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    fsutil::{self, Entry, FileId, FileSystem},
+    stat::{Fields, Stat},
+};
+
+// Symbolic links resolved in a row before giving up, like ELOOP.
+const MAX_LINKS: usize = 40;
+
+#[derive(Clone, Debug)]
+enum Node {
+    Dir,
+    File {
+        size: u64,
+        // Windows tells executables by their extension
+        #[cfg_attr(not(unix), allow(dead_code))]
+        executable: bool,
+    },
+    Symlink(PathBuf),
+}
+
+/// A filesystem kept in memory, for tests and virtual trees. Parents are created as needed and
+/// all times are the unix epoch unless set.
+///
+/// ```
+/// use aldar::memfs::MemoryFs;
+///
+/// let mut fs = MemoryFs::new();
+/// fs.file("project/src/main.rs", 120).symlink("project/lib", "src");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFs {
+    nodes: BTreeMap<PathBuf, (Node, SystemTime)>,
+    // Permission bits replacing the defaults of the type, only unix reports them
+    #[cfg_attr(not(unix), allow(dead_code))]
+    permissions: BTreeMap<PathBuf, u32>,
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Adds a directory.
+    pub fn dir(&mut self, path: impl AsRef<Path>) -> &mut MemoryFs {
+        self.insert(path.as_ref(), Node::Dir)
+    }
+
+    /// Adds a file of the given size.
+    pub fn file(&mut self, path: impl AsRef<Path>, size: u64) -> &mut MemoryFs {
+        let node = Node::File {
+            size,
+            executable: false,
+        };
+        self.insert(path.as_ref(), node)
+    }
+
+    /// Adds an executable file of the given size.
+    pub fn executable(&mut self, path: impl AsRef<Path>, size: u64) -> &mut MemoryFs {
+        let node = Node::File {
+            size,
+            executable: true,
+        };
+        self.insert(path.as_ref(), node)
+    }
+
+    /// Adds a symbolic link, relative targets are resolved from the directory of the link.
+    pub fn symlink(&mut self, path: impl AsRef<Path>, target: impl AsRef<Path>) -> &mut MemoryFs {
+        self.insert(path.as_ref(), Node::Symlink(target.as_ref().to_path_buf()))
+    }
+
+    /// Sets the modification and status change time of an existing path.
+    pub fn set_modified(&mut self, path: impl AsRef<Path>, time: SystemTime) -> &mut MemoryFs {
+        if let Some((_, modified)) = self.nodes.get_mut(&fsutil::normalize_path(path.as_ref())) {
+            *modified = time;
+        }
+        self
+    }
+
+    /// Sets the permission bits of an existing path, e.g. `0o640`. Without, directories and
+    /// executables have `0o755` and other files `0o644`.
+    pub fn set_permissions(&mut self, path: impl AsRef<Path>, mode: u32) -> &mut MemoryFs {
+        let path = fsutil::normalize_path(path.as_ref());
+        if self.nodes.contains_key(&path) {
+            self.permissions.insert(path, mode & 0o7777);
+        }
+        self
+    }
+
+    fn insert(&mut self, path: &Path, node: Node) -> &mut MemoryFs {
+        let path = fsutil::normalize_path(path);
+        for parent in path.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() || self.nodes.contains_key(parent) {
+                break;
+            }
+            self.nodes
+                .insert(parent.to_path_buf(), (Node::Dir, SystemTime::UNIX_EPOCH));
+        }
+        self.nodes.insert(path, (node, SystemTime::UNIX_EPOCH));
+        self
+    }
+
+    // Replaces symbolic links in all components of the path by their targets.
+    fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut path = fsutil::normalize_path(path);
+        for _ in 0..MAX_LINKS {
+            let link = path
+                .ancestors()
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .find_map(|p| match self.nodes.get(p) {
+                    Some((Node::Symlink(target), _)) => Some((p.to_path_buf(), target.clone())),
+                    _ => None,
+                });
+
+            let (link, target) = match link {
+                Some(link) => link,
+                None if self.nodes.contains_key(&path) => return Ok(path),
+                None => return Err(not_found(&path)),
+            };
+
+            let rest = path.strip_prefix(&link).unwrap_or(Path::new(""));
+            let parent = link.parent().unwrap_or(Path::new(""));
+            path = fsutil::normalize_path(&parent.join(target).join(rest));
+        }
+
+        Err(io::Error::other(format!(
+            "{}: too many levels of symbolic links",
+            path.display()
+        )))
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Stat> {
+        let normalized = fsutil::normalize_path(path);
+        let (node, modified) = self.nodes.get(&normalized).ok_or_else(|| not_found(path))?;
+
+        let size = match node {
+            Node::Dir => 0,
+            Node::File { size, .. } => *size,
+            Node::Symlink(target) => target.as_os_str().len() as u64,
+        };
+
+        Ok(Stat {
+            is_dir: matches!(node, Node::Dir),
+            is_symlink: matches!(node, Node::Symlink(_)),
+            size,
+            modified: *modified,
+            changed: *modified,
+            #[cfg(unix)]
+            mode: match (node, self.permissions.get(&normalized)) {
+                (Node::Dir, Some(mode)) => 0o40000 | mode,
+                (Node::File { .. }, Some(mode)) => 0o100000 | mode,
+                (Node::Dir, None) => 0o40755,
+                (
+                    Node::File {
+                        executable: true, ..
+                    },
+                    None,
+                ) => 0o100755,
+                (Node::File { .. }, None) => 0o100644,
+                (Node::Symlink(_), _) => 0o120777,
+            },
+            #[cfg(windows)]
+            hidden: path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('.')),
+        })
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn read_dir(
+        &self,
+        dir: &Path,
+        follow_links: bool,
+        _fields: Fields,
+    ) -> io::Result<Vec<io::Result<Entry>>> {
+        let resolved = self.resolve(dir)?;
+        if !self.stat(&resolved)?.is_dir {
+            return Err(io::Error::other(format!(
+                "{}: not a directory",
+                dir.display()
+            )));
+        }
+
+        // Paths order by component, so the descendants of a directory directly follow it.
+        let children = self
+            .nodes
+            .range(resolved.clone()..)
+            .skip(1)
+            .take_while(|(path, _)| path.starts_with(&resolved))
+            .filter(|(path, _)| path.parent() == Some(resolved.as_path()));
+
+        let mut entries = vec![];
+        for (path, _) in children {
+            let name = path.file_name().unwrap_or_default();
+            let stat = self.stat(path)?;
+            let target = match stat.is_symlink {
+                true => self.metadata(path).ok(),
+                false => Some(stat),
+            };
+
+            let entry = match follow_links && target.is_some() {
+                true => Entry::with_stat(dir.join(name), target),
+                false => Entry::with_stat(dir.join(name), Some(stat)).with_target(target),
+            };
+            entries.push(Ok(entry));
+        }
+
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Stat> {
+        self.stat(&self.resolve(path)?)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.nodes.get(&fsutil::normalize_path(path)) {
+            Some((Node::Symlink(target), _)) => Ok(target.clone()),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: not a symbolic link", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    // Resolved paths identify files, on unix hashed into the inode of a made up device.
+    fn file_id(&self, path: &Path) -> Option<FileId> {
+        let resolved = self.resolve(path).ok()?;
+        #[cfg(unix)]
+        {
+            let mut hasher = DefaultHasher::new();
+            resolved.hash(&mut hasher);
+            Some((0, hasher.finish()))
+        }
+        #[cfg(not(unix))]
+        Some(resolved)
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{}: no such file or directory", path.display()),
+    )
+}
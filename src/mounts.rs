@@ -0,0 +1,249 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+// Filesystem types served over the network or by userspace daemons which may hang.
+const REMOTE_FS_TYPES: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "ncpfs",
+    "afs",
+    "9p",
+    "ceph",
+    "glusterfs",
+    "sshfs",
+    "fuse",
+    "lustre",
+    "gpfs",
+];
+
+/// A mounted filesystem.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub device: String,
+    pub fs_type: String,
+    remote: bool,
+}
+
+impl Mount {
+    /// Returns true if the filesystem is a network or FUSE filesystem.
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+}
+
+/// Mounted filesystems by their mount point.
+#[derive(Debug, Default, Clone)]
+pub struct MountTable {
+    mounts: HashMap<PathBuf, Mount>,
+}
+
+impl MountTable {
+    /// Reads the mount table of the system, it is empty where this is unsupported.
+    #[cfg(target_os = "linux")]
+    pub fn load() -> MountTable {
+        match std::fs::read_to_string("/proc/self/mounts") {
+            Ok(content) => MountTable::parse(&content),
+            _ => MountTable::default(),
+        }
+    }
+
+    /// Reads the drive letters and their volume information.
+    #[cfg(windows)]
+    pub fn load() -> MountTable {
+        let mut table = MountTable::default();
+        for drive in win32::logical_drives() {
+            let mount = Mount {
+                device: drive.clone(),
+                fs_type: win32::file_system_name(&drive).unwrap_or_default(),
+                remote: win32::is_remote_drive(&drive),
+            };
+            // Canonical paths use the verbatim prefix.
+            table
+                .mounts
+                .insert(PathBuf::from(format!("\\\\?\\{}", drive)), mount.clone());
+            table.mounts.insert(PathBuf::from(drive), mount);
+        }
+        table
+    }
+
+    /// Reads the mount table of the system, it is empty where this is unsupported.
+    #[cfg(not(any(target_os = "linux", windows)))]
+    pub fn load() -> MountTable {
+        MountTable::default()
+    }
+
+    // Parses the fstab like format of /proc/self/mounts, later mounts shadow earlier ones.
+    #[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+    pub(crate) fn parse(content: &str) -> MountTable {
+        let mut table = MountTable::default();
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            if let (Some(device), Some(mount_point), Some(fs_type)) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                table.mounts.insert(
+                    PathBuf::from(unescape(mount_point)),
+                    Mount {
+                        device: unescape(device),
+                        fs_type: fs_type.to_string(),
+                        remote: REMOTE_FS_TYPES.contains(&fs_type) || fs_type.starts_with("fuse."),
+                    },
+                );
+            }
+        }
+        table
+    }
+
+    /// Returns the filesystem mounted exactly at the canonical `path`.
+    pub fn get(&self, path: &Path) -> Option<&Mount> {
+        self.mounts.get(path)
+    }
+}
+
+// Decodes the octal escapes (e.g. `\040` for space) used in the mount table.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 3 < bytes.len()
+            && bytes[i + 1..i + 4]
+                .iter()
+                .all(|b| (b'0'..=b'7').contains(b))
+        {
+            out.push(
+                (bytes[i + 1] - b'0') * 64 + (bytes[i + 2] - b'0') * 8 + (bytes[i + 3] - b'0'),
+            );
+            i += 4;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(windows)]
+mod win32 {
+    const DRIVE_REMOTE: u32 = 4;
+    const MAX_PATH: usize = 260;
+
+    #[link(name = "Kernel32")]
+    extern "system" {
+        /// Writes the NUL separated root paths of all drives, see `GetLogicalDriveStringsW` in
+        /// the fileapi.h reference of the Win32 API.
+        fn GetLogicalDriveStringsW(nBufferLength: u32, lpBuffer: *mut u16) -> u32;
+
+        /// Returns the type of a drive, see `GetDriveTypeW` in the fileapi.h reference.
+        fn GetDriveTypeW(lpRootPathName: *const u16) -> u32;
+
+        /// Writes the name and filesystem of a volume, see `GetVolumeInformationW` in the
+        /// fileapi.h reference.
+        fn GetVolumeInformationW(
+            lpRootPathName: *const u16,
+            lpVolumeNameBuffer: *mut u16,
+            nVolumeNameSize: u32,
+            lpVolumeSerialNumber: *mut u32,
+            lpMaximumComponentLength: *mut u32,
+            lpFileSystemFlags: *mut u32,
+            lpFileSystemNameBuffer: *mut u16,
+            nFileSystemNameSize: u32,
+        ) -> i32;
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(Some(0)).collect()
+    }
+
+    /// Returns the root paths of all drives, e.g. `C:\`.
+    pub fn logical_drives() -> Vec<String> {
+        let mut buffer = vec![0u16; 1024];
+        let len = unsafe { GetLogicalDriveStringsW(buffer.len() as u32, buffer.as_mut_ptr()) };
+        if len == 0 || len as usize > buffer.len() {
+            return vec![];
+        }
+
+        buffer[..len as usize]
+            .split(|c| *c == 0)
+            .filter(|d| !d.is_empty())
+            .map(String::from_utf16_lossy)
+            .collect()
+    }
+
+    pub fn is_remote_drive(drive: &str) -> bool {
+        unsafe { GetDriveTypeW(wide(drive).as_ptr()) == DRIVE_REMOTE }
+    }
+
+    pub fn file_system_name(drive: &str) -> Option<String> {
+        let mut name = vec![0u16; MAX_PATH + 1];
+        let success = unsafe {
+            GetVolumeInformationW(
+                wide(drive).as_ptr(),
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                name.as_mut_ptr(),
+                name.len() as u32,
+            )
+        };
+        if success == 0 {
+            return None;
+        }
+
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        Some(String::from_utf16_lossy(&name[..len]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mount_table() {
+        let table = MountTable::parse(
+            "/dev/sda1 / ext4 rw,relatime 0 0\n\
+             server:/export /mnt/my\\040share nfs4 rw 0 0\n\
+             user@host: /home/u/tab\\011dir fuse.sshfs rw 0 0\n\
+             /dev/sdb1 /data ext4 rw 0 0\n\
+             /dev/sdc1 /data xfs rw 0 0\n\
+             incomplete\n",
+        );
+
+        let share = table.get(Path::new("/mnt/my share")).unwrap();
+        assert_eq!(
+            (share.device.as_str(), share.fs_type.as_str()),
+            ("server:/export", "nfs4")
+        );
+        assert!(share.is_remote());
+        assert!(table
+            .get(Path::new("/home/u/tab\tdir"))
+            .unwrap()
+            .is_remote());
+        assert!(!table.get(Path::new("/")).unwrap().is_remote());
+        // Later mounts shadow earlier ones at the same point
+        assert_eq!(table.get(Path::new("/data")).unwrap().fs_type, "xfs");
+        assert!(table.get(Path::new("incomplete")).is_none());
+    }
+
+    #[test]
+    fn unescapes_only_complete_octal_escapes() {
+        assert_eq!(unescape("a\\040b\\134c"), "a b\\c");
+        assert_eq!(unescape("end\\04"), "end\\04");
+        assert_eq!(unescape("not\\089"), "not\\089");
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{mem, panic, sync::Mutex};
+use tokio::{sync::mpsc, task};
+
+use crate::{error::AldarError, Aldar, Stats, TreeNode};
+
+// Nodes sent ahead of the receiver before the scan waits.
+const CHANNEL_SIZE: usize = 256;
+
+// The traversal blocks, so it runs on the blocking threads of tokio. They are available on both
+// the multi-threaded and the current thread runtime.
+impl Aldar {
+    /// Like [`Aldar::run`] without blocking the executor. If the future is dropped before it
+    /// completes, the configuration is reset to the default one.
+    pub async fn run_async(&mut self) -> Result<Stats, AldarError> {
+        self.blocking(Aldar::run).await
+    }
+
+    /// Like [`Aldar::build_tree`] without blocking the executor. If the future is dropped before
+    /// it completes, the configuration is reset to the default one.
+    pub async fn build_tree_async(&mut self) -> Result<TreeNode, AldarError> {
+        self.blocking(Aldar::build_tree).await
+    }
+
+    /// Scans the configured roots and returns a receiver yielding every node in depth first
+    /// order as soon as it is read, without its children. An error ending the scan, e.g. a
+    /// missing root, is the last item. Dropping the receiver stops the scan. Must be called
+    /// within a tokio runtime.
+    pub fn entries_async(mut self) -> mpsc::Receiver<Result<TreeNode, AldarError>> {
+        let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+        let nodes = tx.clone();
+        self.node_sink = Some(Mutex::new(Box::new(move |node| {
+            nodes.blocking_send(Ok(node.clone())).is_ok()
+        })));
+
+        task::spawn_blocking(move || {
+            if let Err(e) = self.build_tree() {
+                // Fails if the scan stopped because the receiver was dropped
+                tx.blocking_send(Err(e)).ok();
+            }
+        });
+        rx
+    }
+
+    // Moves this to a blocking thread to run `f` there, it is put back once `f` returns.
+    async fn blocking<T: Send + 'static>(&mut self, f: fn(&mut Aldar) -> T) -> T {
+        let mut aldar = mem::take(self);
+        let joined = task::spawn_blocking(move || {
+            let result = f(&mut aldar);
+            (aldar, result)
+        })
+        .await;
+
+        match joined {
+            Ok((aldar, result)) => {
+                *self = aldar;
+                result
+            }
+            Err(e) => match e.try_into_panic() {
+                Ok(payload) => panic::resume_unwind(payload),
+                Err(e) => panic!("scan did not finish: {}", e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemoryFs;
+    use tokio::runtime::Builder;
+
+    fn aldar() -> Aldar {
+        let mut fs = MemoryFs::new();
+        fs.file("root/a/b", 1).file("root/c", 2);
+        let mut aldar = Aldar::new();
+        aldar
+            .use_path("root".to_string())
+            .use_filesystem(Box::new(fs))
+            .use_writer(Box::new(std::io::sink()));
+        aldar
+    }
+
+    #[test]
+    fn runs_on_current_thread_runtime() {
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let mut aldar = aldar();
+        let stats = runtime.block_on(aldar.run_async()).unwrap();
+        assert_eq!((stats.dirs, stats.files), (1, 2));
+
+        // The configuration is kept for the next run
+        let tree = runtime.block_on(aldar.build_tree_async()).unwrap();
+        assert_eq!(tree.name, "root");
+    }
+
+    #[test]
+    fn streams_entries() {
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let names = runtime.block_on(async {
+            let mut rx = aldar().entries_async();
+            let mut names = vec![];
+            while let Some(node) = rx.recv().await {
+                let node = node.unwrap();
+                assert!(node.children.is_empty());
+                names.push(node.name);
+            }
+            names
+        });
+        assert_eq!(names, ["root", "a", "b", "c"]);
+
+        let mut aldar = aldar();
+        aldar.use_path("missing".to_string());
+        let errors = runtime.block_on(async {
+            let mut rx = aldar.entries_async();
+            let mut errors = vec![];
+            while let Some(node) = rx.recv().await {
+                errors.extend(node.err());
+            }
+            errors
+        });
+        assert!(matches!(errors[..], [AldarError::RootNotFound(_)]));
+    }
+}
@@ -0,0 +1,57 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path},
+};
+
+/// A trie of path components built from a flat list of paths without touching the filesystem.
+#[derive(Debug, Default)]
+pub struct PathTree {
+    children: BTreeMap<String, PathTree>,
+}
+
+impl PathTree {
+    /// Builds a tree from newline separated paths, or NUL separated if the input contains any NUL.
+    pub fn parse(input: &str) -> PathTree {
+        let separator = match input.contains('\0') {
+            true => '\0',
+            false => '\n',
+        };
+
+        let mut tree = PathTree::default();
+        for path in input.split(separator) {
+            let path = path.trim_end_matches('\r');
+            if !path.is_empty() {
+                tree.insert(path);
+            }
+        }
+        tree
+    }
+
+    /// Inserts all components of the given path.
+    pub fn insert(&mut self, path: &str) {
+        let mut node = self;
+        for component in Path::new(path).components() {
+            let name = match component {
+                Component::CurDir => continue,
+                Component::RootDir => std::path::MAIN_SEPARATOR.to_string(),
+                c => c.as_os_str().to_string_lossy().into_owned(),
+            };
+            node = node.children.entry(name).or_default();
+        }
+    }
+
+    /// Returns true if any path continues below this node.
+    pub fn is_dir(&self) -> bool {
+        !self.children.is_empty()
+    }
+
+    /// Returns the child nodes ordered by name.
+    pub fn children(&self) -> impl Iterator<Item = (&String, &PathTree)> {
+        self.children.iter()
+    }
+}
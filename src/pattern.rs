@@ -0,0 +1,113 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+#[cfg(feature = "regex")]
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+#[cfg(feature = "regex")]
+use std::ops::Range;
+
+#[cfg(feature = "regex")]
+use crate::error::AldarError;
+
+/// Compiled include or exclude patterns, matching a path if any of them does.
+#[cfg(feature = "regex")]
+#[derive(Debug)]
+pub struct Matcher {
+    set: RegexSet,
+    // The set can't tell where a pattern matched, so every pattern is kept on its own too
+    regexes: Vec<Regex>,
+}
+
+/// Without the regex feature patterns can't be set, so there is never a matcher.
+#[cfg(not(feature = "regex"))]
+#[derive(Debug)]
+pub enum Matcher {}
+
+#[cfg(feature = "regex")]
+impl Matcher {
+    /// Compiles the patterns, with smart case enabled patterns containing an uppercase
+    /// character stay case sensitive while all others are matched case insensitive.
+    pub fn new(
+        patterns: &[String],
+        ignore_case: bool,
+        smart_case: bool,
+    ) -> Result<Matcher, AldarError> {
+        // The set doesn't tell which pattern is invalid, so each one is checked on its own.
+        for pattern in patterns {
+            if let Err(source) = Regex::new(pattern) {
+                return Err(AldarError::InvalidPattern {
+                    pattern: pattern.to_owned(),
+                    source,
+                });
+            }
+        }
+
+        let patterns: Vec<String> = patterns
+            .iter()
+            .map(|p| {
+                if !ignore_case && smart_case && !p.chars().any(char::is_uppercase) {
+                    return format!("(?i){}", p);
+                }
+                p.to_owned()
+            })
+            .collect();
+
+        let invalid = |source| AldarError::InvalidPattern {
+            pattern: patterns.join("|"),
+            source,
+        };
+        let set = RegexSetBuilder::new(&patterns)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(invalid)?;
+        let regexes = patterns
+            .iter()
+            .map(|p| RegexBuilder::new(p).case_insensitive(ignore_case).build())
+            .collect::<Result<_, _>>()
+            .map_err(invalid)?;
+
+        Ok(Matcher { set, regexes })
+    }
+
+    /// Returns true if any of the patterns matches.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.set.is_match(text)
+    }
+
+    /// Returns where the first of the patterns matching `text` matches, if it isn't empty.
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        let first = self.set.matches(text).into_iter().next()?;
+        let found = self.regexes[first].find(text)?;
+        (!found.is_empty()).then(|| found.range())
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+impl Matcher {
+    /// Returns true if any of the patterns matches.
+    pub fn is_match(&self, _: &str) -> bool {
+        match *self {}
+    }
+
+    /// Returns where the first of the patterns matching `text` matches.
+    pub fn find(&self, _: &str) -> Option<std::ops::Range<usize>> {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "regex"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_first_matching_pattern() {
+        let patterns = vec!["x$".to_string(), "b+".to_string(), "B".to_string()];
+        let matcher = Matcher::new(&patterns, false, true).unwrap();
+
+        assert_eq!(matcher.find("abbx"), Some(3..4));
+        assert_eq!(matcher.find("aBbc"), Some(1..3));
+        assert_eq!(matcher.find("none"), None);
+    }
+}
@@ -0,0 +1,657 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! A filesystem on a remote host read over SFTP, version 3 of the protocol as OpenSSH speaks it.
+//! The connection is made by running `ssh -s sftp`, so the keys, agent and `~/.ssh/config` of
+//! the user apply.
+//!
+//! ```no_run
+//! use aldar::{sftp::SftpFs, Aldar};
+//!
+//! let fs = SftpFs::connect("deploy@example.com").unwrap();
+//! let mut aldar = Aldar::new();
+//! aldar
+//!     .use_path("/var/www".to_string())
+//!     .use_filesystem(Box::new(fs));
+//! aldar.run().unwrap();
+//! ```
+
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(unix)]
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+use std::{
+    ffi::{OsStr, OsString},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    fsutil::{Entry, FileId, FileSystem},
+    stat::{Fields, Stat},
+};
+
+const VERSION: u32 = 3;
+
+// Longest packet accepted from the server, as OpenSSH limits it
+const MAX_PACKET: usize = 256 * 1024;
+
+// Packet types
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_CLOSE: u8 = 4;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_READLINK: u8 = 19;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+// Status codes
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_PERMISSION_DENIED: u32 = 3;
+
+// Attribute flags
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+// File types in permissions
+const TYPE_MASK: u32 = 0o170000;
+const TYPE_DIR: u32 = 0o040000;
+const TYPE_SYMLINK: u32 = 0o120000;
+
+// Requests are answered in order, one at a time.
+struct Session {
+    input: Box<dyn Read + Send>,
+    output: Box<dyn Write + Send>,
+    next_id: u32,
+}
+
+/// A filesystem of a remote host read over an SFTP session. Requests of several threads are
+/// sent one after the other.
+pub struct SftpFs {
+    session: Mutex<Session>,
+    child: Option<Child>,
+}
+
+impl SftpFs {
+    /// Connects to `destination`, e.g. `user@host`, by running `ssh -s sftp`. Relative paths are
+    /// relative to the home directory of the user on the host.
+    pub fn connect(destination: &str) -> io::Result<SftpFs> {
+        let mut command = Command::new("ssh");
+        command.args(["-s", "--", destination, "sftp"]);
+        SftpFs::spawn(command)
+    }
+
+    /// Runs `command`, which has to speak SFTP on its standard input and output, e.g. ssh with
+    /// further options.
+    pub fn spawn(mut command: Command) -> io::Result<SftpFs> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let (input, output) = match (child.stdout.take(), child.stdin.take()) {
+            (Some(input), Some(output)) => (input, output),
+            _ => return Err(io::Error::other("no pipes to the sftp command")),
+        };
+        let mut fs = match SftpFs::new(input, output) {
+            Ok(fs) => fs,
+            // The command failed to connect and told why on its standard error
+            Err(e) => {
+                let _ = child.kill();
+                let status = child.wait()?;
+                return match e.kind() {
+                    io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe => Err(
+                        io::Error::other(format!("connection closed, sftp command {}", status)),
+                    ),
+                    _ => Err(e),
+                };
+            }
+        };
+        fs.child = Some(child);
+        Ok(fs)
+    }
+
+    /// Starts a session over an established channel, `input` is read from the server and
+    /// `output` written to it.
+    pub fn new(
+        input: impl Read + Send + 'static,
+        output: impl Write + Send + 'static,
+    ) -> io::Result<SftpFs> {
+        let mut session = Session {
+            input: Box::new(BufReader::new(input)),
+            output: Box::new(BufWriter::new(output)),
+            next_id: 0,
+        };
+
+        // The init packet has no request id
+        let mut init = vec![FXP_INIT];
+        put_u32(&mut init, VERSION);
+        session.send(&init)?;
+        let mut reply = session.receive()?;
+        match reply.u8()? {
+            FXP_VERSION if reply.u32()? >= VERSION => {}
+            FXP_VERSION => return Err(io::Error::other("the server speaks an older sftp")),
+            _ => return Err(invalid()),
+        }
+
+        Ok(SftpFs {
+            session: Mutex::new(session),
+            child: None,
+        })
+    }
+
+    /// Returns the absolute path of `path` on the host, with symbolic links resolved.
+    pub fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut reply = self.request(FXP_REALPATH, |p| put_path(p, path))?;
+        match reply.u8()? {
+            FXP_NAME => match reply.names()?.pop() {
+                Some((name, _)) => Ok(PathBuf::from(name)),
+                None => Err(invalid()),
+            },
+            FXP_STATUS => Err(reply.error(path)),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn request(&self, kind: u8, payload: impl FnOnce(&mut Vec<u8>)) -> io::Result<Reply> {
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let id = session.next_id;
+        session.next_id = session.next_id.wrapping_add(1);
+
+        let mut packet = vec![kind];
+        put_u32(&mut packet, id);
+        payload(&mut packet);
+        session.send(&packet)?;
+
+        let mut reply = session.receive()?;
+        // Type and id lead every reply, the type is read by the caller
+        if reply.data.len() < 5 || u32::from_be_bytes(reply.data[1..5].try_into().unwrap()) != id {
+            return Err(invalid());
+        }
+        reply.data.drain(1..5);
+        Ok(reply)
+    }
+
+    fn attributes(&self, kind: u8, path: &Path) -> io::Result<Stat> {
+        let mut reply = self.request(kind, |p| put_path(p, path))?;
+        match reply.u8()? {
+            FXP_ATTRS => reply.attributes(file_name(path)),
+            FXP_STATUS => Err(reply.error(path)),
+            _ => Err(invalid()),
+        }
+    }
+
+    // Reads the names of an open directory until its end.
+    fn read_names(
+        &self,
+        dir: &Path,
+        handle: &[u8],
+    ) -> io::Result<Vec<(OsString, io::Result<Stat>)>> {
+        let mut names = vec![];
+        loop {
+            let mut reply = self.request(FXP_READDIR, |p| put_bytes(p, handle))?;
+            match reply.u8()? {
+                FXP_NAME => names.extend(reply.names()?),
+                FXP_STATUS => match reply.status()? {
+                    (FX_EOF, _) => return Ok(names),
+                    (code, message) => return Err(status_error(code, &message, dir)),
+                },
+                _ => return Err(invalid()),
+            }
+        }
+    }
+
+    fn close(&self, dir: &Path, handle: &[u8]) -> io::Result<()> {
+        let mut reply = self.request(FXP_CLOSE, |p| put_bytes(p, handle))?;
+        match reply.u8()? {
+            FXP_STATUS => match reply.status()? {
+                (FX_OK, _) => Ok(()),
+                (code, message) => Err(status_error(code, &message, dir)),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Drop for SftpFs {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            child.kill().ok();
+            child.wait().ok();
+        }
+    }
+}
+
+impl FileSystem for SftpFs {
+    fn read_dir(
+        &self,
+        dir: &Path,
+        follow_links: bool,
+        _fields: Fields,
+    ) -> io::Result<Vec<io::Result<Entry>>> {
+        let mut reply = self.request(FXP_OPENDIR, |p| put_path(p, dir))?;
+        let handle = match reply.u8()? {
+            FXP_HANDLE => reply.bytes()?,
+            FXP_STATUS => return Err(reply.error(dir)),
+            _ => return Err(invalid()),
+        };
+        // Names come in batches, the handle is closed whether reading them failed or not
+        let names = self.read_names(dir, &handle);
+        self.close(dir, &handle)?;
+
+        let entries = names?
+            .into_iter()
+            .filter(|(name, _)| name != "." && name != "..")
+            .map(|(name, stat)| {
+                let path = dir.join(&name);
+                let stat = stat?;
+                let target = match stat.is_symlink {
+                    true => self.metadata(&path).ok(),
+                    false => Some(stat),
+                };
+                Ok(match follow_links && target.is_some() {
+                    true => Entry::with_stat(path, target),
+                    false => Entry::with_stat(path, Some(stat)).with_target(target),
+                })
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Stat> {
+        self.attributes(FXP_STAT, path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut reply = self.request(FXP_READLINK, |p| put_path(p, path))?;
+        match reply.u8()? {
+            FXP_NAME => match reply.names()?.pop() {
+                Some((target, _)) => Ok(PathBuf::from(target)),
+                None => Err(invalid()),
+            },
+            FXP_STATUS => Err(reply.error(path)),
+            _ => Err(invalid()),
+        }
+    }
+
+    // Canonical paths identify files, on unix hashed into the inode of a made up device.
+    fn file_id(&self, path: &Path) -> Option<FileId> {
+        let resolved = self.canonicalize(path).ok()?;
+        #[cfg(unix)]
+        {
+            let mut hasher = DefaultHasher::new();
+            resolved.hash(&mut hasher);
+            Some((0, hasher.finish()))
+        }
+        #[cfg(not(unix))]
+        Some(resolved)
+    }
+}
+
+impl Session {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.output
+            .write_all(&(packet.len() as u32).to_be_bytes())?;
+        self.output.write_all(packet)?;
+        self.output.flush()
+    }
+
+    fn receive(&mut self) -> io::Result<Reply> {
+        let mut length = [0; 4];
+        self.input.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_PACKET {
+            return Err(invalid());
+        }
+        let mut data = vec![0; length];
+        self.input.read_exact(&mut data)?;
+        Ok(Reply { data, offset: 0 })
+    }
+}
+
+// A reply read field by field.
+struct Reply {
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl Reply {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + n)
+            .ok_or_else(invalid)?;
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        Ok(String::from_utf8_lossy(&self.bytes()?).into_owned())
+    }
+
+    // Names are kept as sent, on unix they need not be UTF-8.
+    fn name(&mut self) -> io::Result<OsString> {
+        let bytes = self.bytes()?;
+        #[cfg(unix)]
+        return Ok(OsString::from_vec(bytes));
+        #[cfg(not(unix))]
+        Ok(String::from_utf8_lossy(&bytes).into_owned().into())
+    }
+
+    // Reads the entries of a name reply, attributes without a type are errors.
+    fn names(&mut self) -> io::Result<Vec<(OsString, io::Result<Stat>)>> {
+        let count = self.u32()?;
+        let mut names = vec![];
+        for _ in 0..count {
+            let name = self.name()?;
+            // The long name is a line of ls -l
+            self.bytes()?;
+            let stat = self.attributes(&name);
+            names.push((name, stat));
+        }
+        Ok(names)
+    }
+
+    fn attributes(&mut self, name: &OsStr) -> io::Result<Stat> {
+        let flags = self.u32()?;
+        let size = match flags & ATTR_SIZE {
+            0 => 0,
+            _ => self.u64()?,
+        };
+        if flags & ATTR_UIDGID != 0 {
+            self.take(8)?;
+        }
+        let permissions = match flags & ATTR_PERMISSIONS {
+            0 => None,
+            _ => Some(self.u32()?),
+        };
+        let modified = match flags & ATTR_ACMODTIME {
+            0 => SystemTime::UNIX_EPOCH,
+            _ => {
+                self.u32()?;
+                SystemTime::UNIX_EPOCH + Duration::from_secs(self.u32()?.into())
+            }
+        };
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.bytes()?;
+                self.bytes()?;
+            }
+        }
+
+        let permissions = permissions.ok_or_else(|| {
+            io::Error::other(format!("{}: the server sent no file type", name.display()))
+        })?;
+        Ok(Stat {
+            is_dir: permissions & TYPE_MASK == TYPE_DIR,
+            is_symlink: permissions & TYPE_MASK == TYPE_SYMLINK,
+            size,
+            modified,
+            // Version 3 has no status change time
+            changed: modified,
+            #[cfg(unix)]
+            mode: permissions,
+            #[cfg(windows)]
+            hidden: name.as_encoded_bytes().starts_with(b"."),
+        })
+    }
+
+    // Reads the code and message of a status reply.
+    fn status(&mut self) -> io::Result<(u32, String)> {
+        Ok((self.u32()?, self.string()?))
+    }
+
+    // Turns a status reply to a request about `path` into an error.
+    fn error(&mut self, path: &Path) -> io::Error {
+        match self.status() {
+            Ok((code, message)) => status_error(code, &message, path),
+            Err(e) => e,
+        }
+    }
+}
+
+fn status_error(code: u32, message: &str, path: &Path) -> io::Error {
+    let kind = match code {
+        FX_NO_SUCH_FILE => io::ErrorKind::NotFound,
+        FX_PERMISSION_DENIED => io::ErrorKind::PermissionDenied,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{}: {}", path.display(), message))
+}
+
+fn put_u32(packet: &mut Vec<u8>, value: u32) {
+    packet.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_bytes(packet: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(packet, bytes.len() as u32);
+    packet.extend_from_slice(bytes);
+}
+
+// Remote paths are separated by slashes whatever the local platform uses.
+fn put_path(packet: &mut Vec<u8>, path: &Path) {
+    #[cfg(unix)]
+    put_bytes(packet, path.as_os_str().as_bytes());
+    #[cfg(not(unix))]
+    put_bytes(packet, path.to_string_lossy().replace('\\', "/").as_bytes());
+}
+
+fn file_name(path: &Path) -> &OsStr {
+    path.file_name().unwrap_or_default()
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid sftp reply")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Aldar;
+    use std::{collections::HashSet, thread};
+
+    // Paths, permissions, sizes and link targets served by the fake server.
+    const FILES: &[(&str, u32, u64, &str)] = &[
+        ("/srv", 0o40755, 0, ""),
+        ("/srv/a.txt", 0o100644, 5, ""),
+        ("/srv/link", 0o120777, 5, "a.txt"),
+        ("/srv/sub", 0o40700, 0, ""),
+        ("/srv/sub/b", 0o100755, 7, ""),
+    ];
+
+    fn attributes(packet: &mut Vec<u8>, mode: u32, size: u64) {
+        put_u32(packet, ATTR_SIZE | ATTR_PERMISSIONS | ATTR_ACMODTIME);
+        packet.extend_from_slice(&size.to_be_bytes());
+        put_u32(packet, mode);
+        put_u32(packet, 0);
+        put_u32(packet, 86400);
+    }
+
+    // Answers the requests of a session like a server with FILES would.
+    fn serve(input: impl Read + Send + 'static, output: impl Write + Send + 'static) {
+        let mut session = Session {
+            input: Box::new(BufReader::new(input)),
+            output: Box::new(output),
+            next_id: 0,
+        };
+        let mut listed = HashSet::new();
+        while let Ok(mut request) = session.receive() {
+            let kind = request.u8().unwrap();
+            if kind == FXP_INIT {
+                let mut reply = vec![FXP_VERSION];
+                put_u32(&mut reply, VERSION);
+                session.send(&reply).unwrap();
+                continue;
+            }
+
+            let id = request.u32().unwrap();
+            let path = request.string().unwrap();
+            let file = |path: &str| FILES.iter().find(|file| file.0 == path);
+            let mut reply = vec![];
+            put_u32(&mut reply, id);
+            let kind = match (kind, file(&path)) {
+                (FXP_STAT, Some(&(_, mode, size, target))) => {
+                    let (mode, size) = match target {
+                        "" => (mode, size),
+                        _ => (0o100644, 5),
+                    };
+                    attributes(&mut reply, mode, size);
+                    FXP_ATTRS
+                }
+                (FXP_OPENDIR, Some(_)) => {
+                    put_bytes(&mut reply, path.as_bytes());
+                    FXP_HANDLE
+                }
+                (FXP_READDIR, Some(_)) if listed.insert(path.clone()) => {
+                    let children: Vec<_> = FILES
+                        .iter()
+                        .filter(|file| Path::new(file.0).parent() == Some(Path::new(&path)))
+                        .collect();
+                    put_u32(&mut reply, children.len() as u32);
+                    for (child, mode, size, _) in children {
+                        put_bytes(&mut reply, file_name(Path::new(child)).as_encoded_bytes());
+                        put_bytes(&mut reply, b"");
+                        attributes(&mut reply, *mode, *size);
+                    }
+                    FXP_NAME
+                }
+                (FXP_READDIR, Some(_)) => {
+                    put_u32(&mut reply, FX_EOF);
+                    put_bytes(&mut reply, b"");
+                    FXP_STATUS
+                }
+                (FXP_CLOSE, Some(_)) => {
+                    put_u32(&mut reply, FX_OK);
+                    put_bytes(&mut reply, b"");
+                    FXP_STATUS
+                }
+                (FXP_REALPATH | FXP_READLINK, Some(&(_, _, _, target))) => {
+                    let name = match kind {
+                        FXP_READLINK => target,
+                        _ => &path,
+                    };
+                    put_u32(&mut reply, 1);
+                    put_bytes(&mut reply, name.as_bytes());
+                    put_bytes(&mut reply, b"");
+                    put_u32(&mut reply, 0);
+                    FXP_NAME
+                }
+                _ => {
+                    put_u32(&mut reply, FX_NO_SUCH_FILE);
+                    put_bytes(&mut reply, b"No such file");
+                    FXP_STATUS
+                }
+            };
+            reply.insert(0, kind);
+            session.send(&reply).unwrap();
+        }
+    }
+
+    fn session(input: Vec<u8>) -> Session {
+        Session {
+            input: Box::new(io::Cursor::new(input)),
+            output: Box::new(io::sink()),
+            next_id: 0,
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_packets() {
+        let mut packet = vec![];
+        put_u32(&mut packet, MAX_PACKET as u32 + 1);
+        let error = session(packet).receive().err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_names_as_sent() {
+        let mut packet = vec![];
+        put_u32(&mut packet, 1);
+        put_bytes(&mut packet, b"caf\xe9");
+        put_bytes(&mut packet, b"");
+        attributes(&mut packet, 0o100644, 3);
+        let mut reply = Reply {
+            data: packet,
+            offset: 0,
+        };
+
+        let names = reply.names().unwrap();
+        assert_eq!(names[0].0, OsStr::from_bytes(b"caf\xe9"));
+        assert_eq!(names[0].1.as_ref().unwrap().size, 3);
+    }
+
+    #[test]
+    fn reads_remote_tree() {
+        let (server_input, client_output) = io::pipe().unwrap();
+        let (client_input, server_output) = io::pipe().unwrap();
+        let server = thread::spawn(move || serve(server_input, server_output));
+
+        let fs = SftpFs::new(client_input, client_output).unwrap();
+        assert_eq!(
+            fs.read_link(Path::new("/srv/link")).unwrap(),
+            Path::new("a.txt")
+        );
+        let error = fs.metadata(Path::new("/srv/missing")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        let tree = Aldar::new()
+            .use_path("/srv".to_string())
+            .use_filesystem(Box::new(fs))
+            .build_tree()
+            .unwrap();
+        server.join().unwrap();
+
+        let entries: Vec<(&str, bool, u64)> = tree
+            .walk()
+            .map(|node| {
+                let size = node.metadata.map_or(0, |stat| stat.size);
+                (node.name.as_str(), node.is_dir, size)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("/srv", true, 0),
+                ("sub", true, 0),
+                ("b", false, 7),
+                ("a.txt", false, 5),
+                ("link", false, 5),
+            ]
+        );
+    }
+}
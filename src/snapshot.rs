@@ -0,0 +1,142 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Snapshots of a tree saved as JSON, to tell later what changed since, e.g. with
+//! [`DiffNode::compare`](crate::diff::DiffNode::compare).
+//!
+//! ```no_run
+//! use aldar::{snapshot::Snapshot, Aldar};
+//!
+//! let mut aldar = Aldar::new();
+//! aldar.use_path("/etc".to_string()).compute_digests(true);
+//! let snapshot = Snapshot::take(&mut aldar).unwrap();
+//! snapshot.save("etc.json").unwrap();
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use crate::{error::AldarError, fsutil, tree::TreeNode, Aldar};
+
+/// Version of the snapshot format, snapshots of other versions are rejected.
+pub const VERSION: u32 = 1;
+
+/// A tree with the sizes, modification times and, if hashing was turned on, digests of its
+/// files at the time it was taken.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// When the snapshot was taken.
+    pub taken: SystemTime,
+    pub root: TreeNode,
+}
+
+impl Snapshot {
+    /// Builds the tree of the roots of `aldar` with [`Aldar::build_tree`]. Digests are only
+    /// included with [`Aldar::compute_digests`].
+    pub fn take(aldar: &mut Aldar) -> Result<Snapshot, AldarError> {
+        Ok(Snapshot {
+            version: VERSION,
+            taken: SystemTime::now(),
+            root: aldar.build_tree()?,
+        })
+    }
+
+    /// Returns true if the digests of the files were taken.
+    pub fn has_digests(&self) -> bool {
+        self.root
+            .walk()
+            .any(|node| !node.is_dir && node.digest.is_some())
+    }
+
+    /// Writes the snapshot as JSON.
+    pub fn write(&self, out: impl Write) -> io::Result<()> {
+        let mut out = BufWriter::new(out);
+        serde_json::to_writer(&mut out, self)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    /// Writes the snapshot to the file at `path`, replacing it if it exists.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), AldarError> {
+        let path = path.as_ref();
+        File::create(path)
+            .and_then(|file| self.write(file))
+            .map_err(|source| AldarError::Io {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+
+    /// Reads a snapshot written by [`Snapshot::write`].
+    pub fn read(input: impl Read) -> Result<Snapshot, serde_json::Error> {
+        serde_json::from_reader(BufReader::new(input))
+    }
+
+    /// Reads the snapshot file at `path`, which has to be of the current [`VERSION`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, AldarError> {
+        let path = fsutil::expand_home(path.as_ref());
+        let file = File::open(&path).map_err(|source| AldarError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let snapshot = Snapshot::read(file).map_err(|source| AldarError::Snapshot {
+            path: path.clone(),
+            source: Box::new(source),
+        })?;
+
+        match snapshot.version {
+            VERSION => Ok(snapshot),
+            version => Err(AldarError::Snapshot {
+                path,
+                source: format!("unsupported version {}", version).into(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemoryFs;
+
+    #[test]
+    fn reads_what_it_writes() {
+        let mut fs = MemoryFs::new();
+        fs.file("project/src/main.rs", 120).dir("project/docs");
+
+        let snapshot = Snapshot::take(
+            Aldar::new()
+                .use_path("project".to_string())
+                .use_filesystem(Box::new(fs)),
+        )
+        .unwrap();
+        let mut json = Vec::new();
+        snapshot.write(&mut json).unwrap();
+
+        let read = Snapshot::read(json.as_slice()).unwrap();
+        assert_eq!(read.version, VERSION);
+        let sizes: Vec<(String, Option<u64>)> = read
+            .root
+            .walk()
+            .map(|node| (node.name.clone(), node.metadata.map(|stat| stat.size)))
+            .collect();
+        assert_eq!(
+            sizes,
+            [
+                ("project".to_string(), Some(0)),
+                ("docs".to_string(), Some(0)),
+                ("src".to_string(), Some(0)),
+                ("main.rs".to_string(), Some(120)),
+            ]
+        );
+        assert!(!read.has_digests());
+    }
+}
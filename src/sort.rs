@@ -0,0 +1,259 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+#[cfg(feature = "cli")]
+use clap::ArgEnum;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::fsutil::{AldarExt, Entry};
+
+/// Compares two entries of the same directory.
+pub type Comparator = Box<dyn Fn(&Entry, &Entry) -> Ordering + Send + Sync>;
+
+/// Available keys to sort the entries of a directory by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ArgEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum SortKey {
+    /// Sort by name
+    Name,
+    /// Sort by size, largest first
+    Size,
+    /// Sort by modification time, newest first
+    Mtime,
+    /// Sort by status change time, newest first
+    Ctime,
+    /// Sort by name with numbers ordered naturally (file2 before file10)
+    Version,
+    /// Sort by extension
+    Ext,
+    /// Sort directories before files
+    DirsFirst,
+    /// Sort files before directories
+    FilesFirst,
+}
+
+/// Defines whether directories and files are grouped before sorting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Grouping {
+    DirsFirst,
+    FilesFirst,
+    None,
+}
+
+impl Grouping {
+    /// Compares two entries by their group only.
+    pub fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        match self {
+            Grouping::DirsFirst => is_dir(b).cmp(&is_dir(a)),
+            Grouping::FilesFirst => is_dir(a).cmp(&is_dir(b)),
+            Grouping::None => Ordering::Equal,
+        }
+    }
+}
+
+impl SortKey {
+    /// Returns the comparator implementing this sort key only, names are compared case
+    /// insensitive if requested.
+    pub fn comparator(&self, ignore_case: bool) -> Comparator {
+        match self {
+            SortKey::Name => Box::new(move |a, b| match ignore_case {
+                true => lowercase_name(a).cmp(&lowercase_name(b)),
+                false => a.file_name().cmp(&b.file_name()),
+            }),
+            SortKey::Size => Box::new(|a, b| b.size().cmp(&a.size())),
+            SortKey::Mtime => Box::new(|a, b| b.modified().cmp(&a.modified())),
+            SortKey::Ctime => Box::new(|a, b| b.changed().cmp(&a.changed())),
+            SortKey::Version => Box::new(move |a, b| match ignore_case {
+                true => natural_cmp(&lowercase_name(a), &lowercase_name(b)),
+                false => natural_cmp(
+                    &a.file_name().to_string_lossy(),
+                    &b.file_name().to_string_lossy(),
+                ),
+            }),
+            SortKey::Ext => Box::new(move |a, b| match ignore_case {
+                true => extension(a)
+                    .to_lowercase()
+                    .cmp(&extension(b).to_lowercase()),
+                false => extension(a).cmp(&extension(b)),
+            }),
+            SortKey::DirsFirst => Box::new(|a, b| Grouping::DirsFirst.compare(a, b)),
+            SortKey::FilesFirst => Box::new(|a, b| Grouping::FilesFirst.compare(a, b)),
+        }
+    }
+}
+
+/// Chains the comparators of the given keys, each key only decides ties left by the previous
+/// ones and the path is used as final tie breaker.
+pub fn pipeline(keys: &[SortKey], ignore_case: bool) -> Comparator {
+    let comparators: Vec<Comparator> = keys.iter().map(|k| k.comparator(ignore_case)).collect();
+
+    Box::new(move |a, b| {
+        comparators
+            .iter()
+            .map(|c| c(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.path().cmp(&b.path()))
+    })
+}
+
+// Symbolic links to directories are grouped with directories even if they aren't followed.
+fn is_dir(entry: &Entry) -> bool {
+    entry.target().is_some_and(|s| s.is_dir)
+}
+
+// Entries without an extension have an empty one and are listed first.
+fn extension(entry: &Entry) -> String {
+    match entry.path().extension() {
+        Some(ext) => ext.to_string_lossy().into_owned(),
+        _ => String::new(),
+    }
+}
+
+fn lowercase_name(entry: &Entry) -> String {
+    entry.file_name().to_string_lossy().to_lowercase()
+}
+
+/// Compares two strings treating runs of digits as numbers, so `v1.2.9` orders before `v1.2.10`.
+/// Numbers only differing in leading zeros are ordered like the strings, `a01` before `a1`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    numbers_cmp(a, b).then_with(|| a.cmp(b))
+}
+
+fn numbers_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // Without leading zeros the longer number is the bigger one.
+                let ord = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.cmp(y);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        if number.is_empty() && c == '0' {
+            continue;
+        }
+        number.push(c);
+    }
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stat::Stat;
+    use std::path::PathBuf;
+
+    fn entry(path: &str, is_dir: bool, size: u64) -> Entry {
+        let stat = Stat {
+            size,
+            ..Stat::of_kind(is_dir)
+        };
+        Entry::with_stat(PathBuf::from(path), Some(stat))
+    }
+
+    fn sorted(keys: &[SortKey], ignore_case: bool, mut entries: Vec<Entry>) -> Vec<PathBuf> {
+        let comparator = pipeline(keys, ignore_case);
+        entries.sort_by(|a, b| comparator(a, b));
+        entries.iter().map(Entry::path).collect()
+    }
+
+    #[test]
+    fn later_keys_break_ties() {
+        let entries = vec![
+            entry("d/b.rs", false, 1),
+            entry("d/a.txt", false, 5),
+            entry("d/c.rs", false, 3),
+            entry("d/a.rs", false, 3),
+        ];
+        // Equal extensions are ordered by size, equal sizes by the path
+        let paths = sorted(&[SortKey::Ext, SortKey::Size], false, entries);
+        assert_eq!(
+            paths,
+            ["d/a.rs", "d/c.rs", "d/b.rs", "d/a.txt"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn groups_before_names() {
+        let entries = vec![
+            entry("d/b", false, 0),
+            entry("d/A", false, 0),
+            entry("d/c", true, 0),
+            entry("d/B", true, 0),
+        ];
+        let paths = sorted(&[SortKey::DirsFirst, SortKey::Name], true, entries);
+        assert_eq!(paths, ["d/B", "d/c", "d/A", "d/b"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn falls_back_to_the_path() {
+        // Names equal but for the case are ordered by their path
+        let entries = vec![entry("d/a", false, 0), entry("d/A", false, 0)];
+        let paths = sorted(&[SortKey::Name], true, entries);
+        assert_eq!(paths, ["d/A", "d/a"].map(PathBuf::from));
+
+        let entries = vec![entry("d/b", false, 2), entry("d/a", false, 2)];
+        let paths = sorted(&[SortKey::Size, SortKey::Mtime], false, entries);
+        assert_eq!(paths, ["d/a", "d/b"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn compares_numbers_naturally() {
+        assert_eq!(natural_cmp("v1.2.9", "v1.2.10"), Ordering::Less);
+        assert_eq!(natural_cmp("file100", "file99"), Ordering::Greater);
+        assert_eq!(natural_cmp("a010", "a9"), Ordering::Greater);
+        assert_eq!(natural_cmp("a2", "a2"), Ordering::Equal);
+    }
+
+    #[test]
+    fn orders_leading_zeros_like_strings() {
+        assert_eq!(natural_cmp("a01", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("a1", "a01"), Ordering::Greater);
+        assert_eq!(natural_cmp("a01", "a2"), Ordering::Less);
+        // Runs of zeros only are the number zero
+        assert_eq!(natural_cmp("x000", "x1"), Ordering::Less);
+        assert_eq!(natural_cmp("x000", "x0"), Ordering::Greater);
+        assert_eq!(natural_cmp("x000b", "x0a"), Ordering::Greater);
+    }
+
+    #[test]
+    fn compares_digits_and_letters_as_characters() {
+        assert_eq!(natural_cmp("a1", "ab"), Ordering::Less);
+        assert_eq!(natural_cmp("1a", "a"), Ordering::Less);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("a1", "a1b"), Ordering::Less);
+        assert_eq!(natural_cmp("a10b", "a10a"), Ordering::Greater);
+    }
+}
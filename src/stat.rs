@@ -0,0 +1,252 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(windows)]
+use std::os::windows::fs::MetadataExt;
+use std::{
+    fs::{DirEntry, Metadata},
+    io,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+// Number of metadata calls made by all threads, reported by --perf-stats.
+static CALLS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of metadata calls made so far.
+pub fn calls() -> u64 {
+    CALLS.load(Ordering::Relaxed)
+}
+
+/// Records `n` metadata calls made outside of this module.
+pub fn record_calls(n: u64) {
+    CALLS.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Queries the metadata of `path` following symbolic links, the call is recorded.
+pub fn metadata(path: &Path) -> io::Result<Metadata> {
+    record_calls(1);
+    path.metadata()
+}
+
+/// Metadata fields an enabled feature needs besides the file type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fields {
+    pub size: bool,
+    pub modified: bool,
+    pub changed: bool,
+    pub mode: bool,
+}
+
+impl Fields {
+    /// All fields, used when it's unknown what will be needed.
+    pub const ALL: Fields = Fields {
+        size: true,
+        modified: true,
+        changed: true,
+        mode: true,
+    };
+
+    /// Returns true if only the file type is needed.
+    #[cfg(unix)]
+    pub fn is_empty(&self) -> bool {
+        *self == Fields::default()
+    }
+}
+
+/// The subset of metadata used by aldar, fields not requested are zero.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Stat {
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub changed: SystemTime,
+    #[cfg(unix)]
+    pub mode: u32,
+    #[cfg(windows)]
+    pub hidden: bool,
+}
+
+impl Stat {
+    /// Fetches the metadata of an entry, with `follow` the target of a symbolic link.
+    pub fn of(entry: &DirEntry, follow: bool, fields: Fields) -> io::Result<Stat> {
+        // The file type of the directory entry is enough if no other field is needed.
+        #[cfg(unix)]
+        if fields.is_empty() && !follow {
+            if let Ok(t) = entry.file_type() {
+                return Ok(Stat::from_file_type(t));
+            }
+        }
+
+        Stat::fetch(entry, follow, fields)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn fetch(entry: &DirEntry, follow: bool, fields: Fields) -> io::Result<Stat> {
+        match statx(entry, follow, fields) {
+            // Kernels before 4.11 or sandboxes may not provide statx.
+            Err(e)
+                if e.raw_os_error() == Some(libc::ENOSYS)
+                    || e.raw_os_error() == Some(libc::EPERM) =>
+            {
+                Stat::from_std(entry, follow)
+            }
+            result => result,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn fetch(entry: &DirEntry, follow: bool, _fields: Fields) -> io::Result<Stat> {
+        Stat::from_std(entry, follow)
+    }
+
+    fn from_std(entry: &DirEntry, follow: bool) -> io::Result<Stat> {
+        let metadata = match follow {
+            true => metadata(&entry.path())?,
+            false => {
+                record_calls(1);
+                entry.metadata()?
+            }
+        };
+        Ok(Stat::from_metadata(&metadata))
+    }
+
+    /// Returns the metadata of a path known by its kind only, e.g. one read from a list.
+    pub fn of_kind(is_dir: bool) -> Stat {
+        Stat {
+            is_dir,
+            is_symlink: false,
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            changed: SystemTime::UNIX_EPOCH,
+            #[cfg(unix)]
+            mode: 0,
+            #[cfg(windows)]
+            hidden: false,
+        }
+    }
+
+    #[cfg(unix)]
+    fn from_file_type(t: std::fs::FileType) -> Stat {
+        Stat {
+            is_dir: t.is_dir(),
+            is_symlink: t.is_symlink(),
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            changed: SystemTime::UNIX_EPOCH,
+            mode: 0,
+        }
+    }
+
+    pub fn from_metadata(m: &Metadata) -> Stat {
+        Stat {
+            is_dir: m.is_dir(),
+            is_symlink: m.file_type().is_symlink(),
+            size: m.len(),
+            modified: m.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            changed: changed(m),
+            #[cfg(unix)]
+            mode: m.permissions().mode(),
+            #[cfg(windows)]
+            hidden: m.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0,
+        }
+    }
+}
+
+#[cfg(windows)]
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x00000002;
+
+#[cfg(unix)]
+fn changed(m: &Metadata) -> SystemTime {
+    use std::os::unix::prelude::MetadataExt;
+    to_system_time(m.ctime(), m.ctime_nsec() as u32)
+}
+
+// Windows has no status change time, the creation time is used instead.
+#[cfg(not(unix))]
+fn changed(m: &Metadata) -> SystemTime {
+    m.created().unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+#[cfg(unix)]
+fn to_system_time(sec: i64, nsec: u32) -> SystemTime {
+    match sec >= 0 {
+        true => SystemTime::UNIX_EPOCH + std::time::Duration::new(sec as u64, nsec),
+        false => SystemTime::UNIX_EPOCH,
+    }
+}
+
+// Requests only the fields needed from the kernel, which avoids work on network filesystems.
+#[cfg(target_os = "linux")]
+fn statx(entry: &DirEntry, follow: bool, fields: Fields) -> io::Result<Stat> {
+    use std::{ffi::CString, mem, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(entry.path().as_os_str().as_bytes())?;
+    let mut buf: libc::statx = unsafe { mem::zeroed() };
+    record_calls(1);
+    let ret = unsafe {
+        libc::statx(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            statx_flags(follow),
+            statx_mask(fields),
+            &mut buf,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(Stat::from_statx(&buf))
+}
+
+/// Returns the statx flags to query an entry, with `follow` the target of a symbolic link.
+#[cfg(target_os = "linux")]
+pub fn statx_flags(follow: bool) -> i32 {
+    match follow {
+        true => libc::AT_STATX_SYNC_AS_STAT,
+        false => libc::AT_STATX_SYNC_AS_STAT | libc::AT_SYMLINK_NOFOLLOW,
+    }
+}
+
+/// Returns the statx mask requesting the file type and the given fields.
+#[cfg(target_os = "linux")]
+pub fn statx_mask(fields: Fields) -> u32 {
+    let mut mask = libc::STATX_TYPE;
+    if fields.size {
+        mask |= libc::STATX_SIZE;
+    }
+    if fields.modified {
+        mask |= libc::STATX_MTIME;
+    }
+    if fields.changed {
+        mask |= libc::STATX_CTIME;
+    }
+    if fields.mode {
+        mask |= libc::STATX_MODE;
+    }
+    mask
+}
+
+#[cfg(target_os = "linux")]
+impl Stat {
+    pub fn from_statx(buf: &libc::statx) -> Stat {
+        Stat {
+            is_dir: buf.stx_mode as u32 & libc::S_IFMT == libc::S_IFDIR,
+            is_symlink: buf.stx_mode as u32 & libc::S_IFMT == libc::S_IFLNK,
+            size: buf.stx_size,
+            modified: to_system_time(buf.stx_mtime.tv_sec, buf.stx_mtime.tv_nsec),
+            changed: to_system_time(buf.stx_ctime.tv_sec, buf.stx_ctime.tv_nsec),
+            mode: buf.stx_mode as u32 & !libc::S_IFMT,
+        }
+    }
+}
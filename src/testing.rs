@@ -0,0 +1,135 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Helpers to test the output of aldar against golden files.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+#[cfg(test)]
+use std::{path::PathBuf, process};
+
+use crate::{color::PlainColorizer, error::AldarError, memfs::MemoryFs, Aldar, Stats};
+#[cfg(test)]
+use crate::{
+    fsutil::{Entry, FileId, FileSystem},
+    stat::{Fields, Stat},
+};
+
+// Set to write the actual output to the golden files instead of comparing it.
+const UPDATE_VAR: &str = "ALDAR_UPDATE_GOLDEN";
+
+// Collects the output of a run, the writer given to Aldar has to be owned.
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuf {
+    // Returns what was written so far and empties the buffer.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Returns an empty directory in the temporary directory, named after `name` and the process so
+// concurrent test runs don't collide.
+#[cfg(test)]
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("aldar-{}-{}", name, process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Entries of a directory as read by a filesystem.
+#[cfg(test)]
+pub(crate) type DirEntries = io::Result<Vec<io::Result<Entry>>>;
+
+// Hands every directory read of a memory filesystem to a closure together with the read itself,
+// e.g. to record which directories are read or how many at the same time.
+#[cfg(test)]
+pub(crate) struct RecordingFs<F>(pub(crate) MemoryFs, pub(crate) F);
+
+#[cfg(test)]
+impl<F> FileSystem for RecordingFs<F>
+where
+    F: Fn(&Path, &dyn Fn() -> DirEntries) -> DirEntries + Send + Sync,
+{
+    fn read_dir(&self, path: &Path, follow_links: bool, fields: Fields) -> DirEntries {
+        (self.1)(path, &|| self.0.read_dir(path, follow_links, fields))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Stat> {
+        self.0.metadata(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.0.read_link(path)
+    }
+
+    fn file_id(&self, path: &Path) -> Option<FileId> {
+        self.0.file_id(path)
+    }
+}
+
+/// Lists `fs` with the configured `aldar` and returns the output without colors. The configured
+/// formatter is kept, only colors are turned off. Output is returned even if entries couldn't be
+/// read.
+pub fn render(aldar: &mut Aldar, fs: MemoryFs) -> (String, Result<Stats, AldarError>) {
+    let buf = SharedBuf::default();
+    let result = aldar
+        .use_filesystem(Box::new(fs))
+        .use_colorizer(Box::new(PlainColorizer))
+        .use_writer(Box::new(buf.clone()))
+        .run();
+
+    let output = String::from_utf8_lossy(&buf.take()).into_owned();
+    (output, result)
+}
+
+/// Panics if `actual` differs from the contents of the golden file at `path`. With the
+/// `ALDAR_UPDATE_GOLDEN` environment variable set the file is written instead.
+pub fn assert_golden(actual: &str, path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    if env::var_os(UPDATE_VAR).is_some() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read golden file {}: {} (set {} to create it)",
+            path.display(),
+            e,
+            UPDATE_VAR
+        )
+    });
+    assert!(
+        actual == expected,
+        "output differs from {} (set {} to update it)\n--- expected\n{}\n--- actual\n{}",
+        path.display(),
+        UPDATE_VAR,
+        expected,
+        actual
+    );
+}
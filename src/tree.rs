@@ -0,0 +1,55 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::{
+    fsutil::{self, AldarExt, Entry},
+    stat::Stat,
+};
+
+/// A node of a directory tree, built independent of rendering.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TreeNode {
+    /// Printable file name, the path as given for roots.
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub metadata: Option<Stat>,
+    /// Why the directory could not be read, if it couldn't.
+    pub error: Option<String>,
+    /// SHA-256 digest of files, if hashing is turned on.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub digest: Option<String>,
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    /// Creates a node without children from a directory entry.
+    pub fn from_entry(entry: &Entry) -> TreeNode {
+        TreeNode {
+            name: fsutil::display_name(&entry.file_name()).into_owned(),
+            path: entry.path(),
+            is_dir: entry.is_dir(),
+            metadata: entry.stat().copied(),
+            error: None,
+            digest: entry.digest().map(str::to_string),
+            children: vec![],
+        }
+    }
+
+    /// Iterates over this node and all its descendants in depth first order.
+    pub fn walk(&self) -> impl Iterator<Item = &TreeNode> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
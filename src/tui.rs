@@ -0,0 +1,455 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Interactive browser of a built tree, like ncdu: directories are expanded and collapsed with
+//! the keyboard, entries filtered while typing, opened or their path copied.
+//!
+//! ```no_run
+//! use aldar::{tui::Browser, Aldar};
+//!
+//! let tree = Aldar::new().build_tree().unwrap();
+//! Browser::new(tree).run().unwrap();
+//! ```
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{tree::TreeNode, units::Units};
+
+const HELP: &str = "↑↓ move  ←→ fold  / filter  enter open  y copy path  q quit";
+
+/// Browses a tree in the terminal until it is quit.
+pub struct Browser {
+    root: TreeNode,
+    expanded: HashSet<PathBuf>,
+    selected: usize,
+    filter: String,
+    editing_filter: bool,
+    // Paths of the entries matching the filter and their ancestors
+    matching: HashSet<PathBuf>,
+    message: Option<String>,
+    units: Units,
+}
+
+// A visible line of the tree.
+struct Row<'a> {
+    node: &'a TreeNode,
+    depth: usize,
+}
+
+impl Browser {
+    /// Creates a browser showing the entries of the root, all directories collapsed.
+    pub fn new(root: TreeNode) -> Browser {
+        let mut expanded = HashSet::new();
+        expanded.insert(root.path.clone());
+        Browser {
+            root,
+            expanded,
+            selected: 0,
+            filter: String::new(),
+            editing_filter: false,
+            matching: HashSet::new(),
+            message: None,
+            units: Units::Iec,
+        }
+    }
+
+    /// Configures the units sizes are shown in, binary ones (KiB, MiB) by default.
+    pub fn use_units(mut self, units: Units) -> Browser {
+        self.units = units;
+        self
+    }
+
+    /// Takes over the terminal and handles keys until the browser is quit.
+    pub fn run(mut self) -> io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && self.handle(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Applies a key, returns true if the browser is quit.
+    fn handle(&mut self, key: KeyEvent) -> bool {
+        self.message = None;
+        if self.editing_filter {
+            match key.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.editing_filter = false;
+                }
+                KeyCode::Enter => self.editing_filter = false,
+                _ => return false,
+            }
+            self.update_matches();
+            return false;
+        }
+
+        let rows = self.rows().len();
+        match key.code {
+            KeyCode::Char('q') => return true,
+            KeyCode::Esc if self.filter.is_empty() => return true,
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.update_matches();
+            }
+            KeyCode::Char('/') => self.editing_filter = true,
+            KeyCode::Down | KeyCode::Char('j') => self.select(self.selected.saturating_add(1)),
+            KeyCode::Up | KeyCode::Char('k') => self.select(self.selected.saturating_sub(1)),
+            KeyCode::PageDown => self.select(self.selected.saturating_add(20)),
+            KeyCode::PageUp => self.select(self.selected.saturating_sub(20)),
+            KeyCode::Home | KeyCode::Char('g') => self.select(0),
+            KeyCode::End | KeyCode::Char('G') => self.select(rows.saturating_sub(1)),
+            KeyCode::Right | KeyCode::Char('l') => self.expand(),
+            KeyCode::Left | KeyCode::Char('h') => self.collapse(),
+            KeyCode::Char(' ') | KeyCode::Tab => self.toggle(),
+            KeyCode::Enter | KeyCode::Char('o') => self.open(),
+            KeyCode::Char('y') => self.copy(),
+            _ => {}
+        }
+        false
+    }
+
+    // Returns the visible rows, below a named root its entries are indented once.
+    fn rows(&self) -> Vec<Row<'_>> {
+        let mut rows = Vec::new();
+        match self.root.name.is_empty() {
+            true => self.collect(&self.root, 0, &mut rows),
+            false => {
+                rows.push(Row {
+                    node: &self.root,
+                    depth: 0,
+                });
+                self.collect(&self.root, 1, &mut rows);
+            }
+        }
+        rows
+    }
+
+    fn collect<'a>(&'a self, node: &'a TreeNode, depth: usize, rows: &mut Vec<Row<'a>>) {
+        for child in &node.children {
+            if !self.filter.is_empty() && !self.matching.contains(&child.path) {
+                continue;
+            }
+            rows.push(Row { node: child, depth });
+            // While filtering all directories with matches are open
+            if self.filter.is_empty() && !self.expanded.contains(&child.path) {
+                continue;
+            }
+            self.collect(child, depth + 1, rows);
+        }
+    }
+
+    fn update_matches(&mut self) {
+        fn mark(node: &TreeNode, filter: &str, matching: &mut HashSet<PathBuf>) -> bool {
+            let mut found = node.name.to_lowercase().contains(filter);
+            for child in &node.children {
+                found |= mark(child, filter, matching);
+            }
+            if found {
+                matching.insert(node.path.clone());
+            }
+            found
+        }
+
+        self.matching.clear();
+        if !self.filter.is_empty() {
+            mark(&self.root, &self.filter.to_lowercase(), &mut self.matching);
+        }
+        self.select(self.selected);
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.rows().len().saturating_sub(1));
+    }
+
+    fn selected_node(&self) -> Option<&TreeNode> {
+        self.rows().get(self.selected).map(|row| row.node)
+    }
+
+    fn expand(&mut self) {
+        let Some(node) = self.selected_node() else {
+            return;
+        };
+        let path = node.path.clone();
+        match node.is_dir && !self.expanded.contains(&path) {
+            true => {
+                self.expanded.insert(path);
+            }
+            // An open directory is entered
+            false if node.is_dir && !node.children.is_empty() => {
+                self.select(self.selected + 1);
+            }
+            false => {}
+        }
+    }
+
+    fn collapse(&mut self) {
+        let rows = self.rows();
+        let Some(row) = rows.get(self.selected) else {
+            return;
+        };
+        let path = row.node.path.clone();
+        if row.node.is_dir && self.expanded.contains(&path) && row.depth > 0 {
+            self.expanded.remove(&path);
+            return;
+        }
+
+        // Otherwise the parent is selected
+        let parent = rows[..self.selected]
+            .iter()
+            .rposition(|r| r.depth < row.depth);
+        if let Some(parent) = parent {
+            self.selected = parent;
+        }
+    }
+
+    fn toggle(&mut self) {
+        let Some(node) = self.selected_node() else {
+            return;
+        };
+        if !node.is_dir {
+            return;
+        }
+        let path = node.path.clone();
+        if !self.expanded.remove(&path) {
+            self.expanded.insert(path);
+        }
+    }
+
+    fn open(&mut self) {
+        let Some(node) = self.selected_node() else {
+            return;
+        };
+        if node.is_dir {
+            return self.toggle();
+        }
+
+        let path = node.path.clone();
+        self.message = Some(match open(&path) {
+            Ok(()) => format!("opened {}", path.display()),
+            Err(e) => format!("failed to open {}: {}", path.display(), e),
+        });
+    }
+
+    // Copies the path with an OSC 52 escape sequence, most terminals put it on the clipboard,
+    // also over SSH.
+    fn copy(&mut self) {
+        let Some(node) = self.selected_node() else {
+            return;
+        };
+        let path = node.path.display().to_string();
+        let sequence = format!("\x1b]52;c;{}\x07", base64(path.as_bytes()));
+        let mut stdout = io::stdout();
+        self.message = Some(
+            match stdout.write_all(sequence.as_bytes()).and(stdout.flush()) {
+                Ok(()) => format!("copied {}", path),
+                Err(e) => format!("failed to copy {}: {}", path, e),
+            },
+        );
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [tree_area, status_area, help_area] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let rows = self.rows();
+        let items: Vec<ListItem> = rows.iter().map(|row| self.item(row)).collect();
+        let list = List::new(items).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(list, tree_area, &mut state);
+
+        let status = match (
+            &self.message,
+            self.editing_filter || !self.filter.is_empty(),
+        ) {
+            (Some(message), _) => message.clone(),
+            (None, true) => format!("/{}", self.filter),
+            (None, false) => rows
+                .get(self.selected)
+                .map(|row| row.node.path.display().to_string())
+                .unwrap_or_default(),
+        };
+        frame.render_widget(
+            Paragraph::new(status).style(Style::new().bold()),
+            status_area,
+        );
+        frame.render_widget(Paragraph::new(HELP).style(Style::new().dim()), help_area);
+    }
+
+    fn item(&self, row: &Row) -> ListItem<'static> {
+        let node = row.node;
+        let marker = match (node.is_dir, self.expanded.contains(&node.path)) {
+            (false, _) => "  ",
+            (true, _) if !self.filter.is_empty() => "▾ ",
+            (true, true) => "▾ ",
+            (true, false) => "▸ ",
+        };
+        let name_style = match node.is_dir {
+            true => Style::new().fg(Color::Blue).bold(),
+            false => Style::new(),
+        };
+
+        let mut spans = vec![
+            Span::raw(format!("{}{}", "  ".repeat(row.depth), marker)),
+            Span::styled(node.name.clone(), name_style),
+        ];
+        if let (false, Some(stat)) = (node.is_dir, node.metadata.as_ref()) {
+            let size = format!("  {}", self.units.format(stat.size));
+            spans.push(Span::styled(size, Style::new().dim()));
+        }
+        if let Some(error) = node.error.as_ref() {
+            spans.push(Span::styled(
+                format!("  [{}]", error),
+                Style::new().fg(Color::Red),
+            ));
+        }
+        ListItem::new(Line::from(spans))
+    }
+}
+
+// Opens the path with the default application of the desktop.
+fn open(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = Command::new("open");
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut command = Command::new("xdg-open");
+
+    command
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+// Encodes bytes as standard base64 with padding, as OSC 52 expects.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::crossterm::event::KeyModifiers;
+
+    fn node(path: &str, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode {
+            name: path.rsplit('/').next().unwrap().to_string(),
+            path: PathBuf::from(path),
+            is_dir: !children.is_empty(),
+            children,
+            ..TreeNode::default()
+        }
+    }
+
+    fn browser() -> Browser {
+        Browser::new(node(
+            "root",
+            vec![
+                node("root/src", vec![node("root/src/main.rs", vec![])]),
+                node("root/README.md", vec![]),
+            ],
+        ))
+    }
+
+    fn names(browser: &Browser) -> Vec<String> {
+        browser.rows().iter().map(|r| r.node.name.clone()).collect()
+    }
+
+    fn press(browser: &mut Browser, code: KeyCode) -> bool {
+        browser.handle(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn expands_and_collapses_directories() {
+        let mut browser = browser();
+        assert_eq!(names(&browser), ["root", "src", "README.md"]);
+
+        press(&mut browser, KeyCode::Down);
+        press(&mut browser, KeyCode::Right);
+        assert_eq!(names(&browser), ["root", "src", "main.rs", "README.md"]);
+
+        press(&mut browser, KeyCode::Right);
+        assert_eq!(browser.selected_node().unwrap().name, "main.rs");
+        press(&mut browser, KeyCode::Left);
+        assert_eq!(browser.selected_node().unwrap().name, "src");
+        press(&mut browser, KeyCode::Left);
+        assert_eq!(names(&browser), ["root", "src", "README.md"]);
+        assert!(press(&mut browser, KeyCode::Char('q')));
+    }
+
+    #[test]
+    fn filters_while_typing() {
+        let mut browser = browser();
+        press(&mut browser, KeyCode::Char('/'));
+        press(&mut browser, KeyCode::Char('M'));
+        press(&mut browser, KeyCode::Char('a'));
+        assert_eq!(names(&browser), ["root", "src", "main.rs"]);
+
+        press(&mut browser, KeyCode::Esc);
+        assert_eq!(names(&browser), ["root", "src", "README.md"]);
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"/tmp/a b"), "L3RtcC9hIGI=");
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Human readable sizes in binary (IEC) or decimal (SI) units.
+
+const IEC_UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+const SI_UNITS: [&str; 6] = ["kB", "MB", "GB", "TB", "PB", "EB"];
+
+/// Unit system of human readable sizes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Units {
+    /// Powers of 1024: KiB, MiB, GiB and so on.
+    #[default]
+    Iec,
+    /// Powers of 1000: kB, MB, GB and so on.
+    Si,
+}
+
+impl Units {
+    /// Size of the smallest unit, 1024 or 1000 bytes.
+    pub fn base(self) -> u64 {
+        match self {
+            Units::Iec => 1024,
+            Units::Si => 1000,
+        }
+    }
+
+    /// Width of the widest size [`format`](Units::format) returns.
+    pub fn width(self) -> usize {
+        match self {
+            // 1023.99KiB
+            Units::Iec => 10,
+            // 999.99kB
+            Units::Si => 8,
+        }
+    }
+
+    /// Formats `size` in the largest unit it reaches, with two decimals unless it is a whole
+    /// number. Sizes below the smallest unit are printed in bytes without unit.
+    pub fn format(self, size: u64) -> String {
+        let (scaled, unit) = match self.scale(size) {
+            Some(scaled) => scaled,
+            None => return size.to_string(),
+        };
+        match scaled.fract() == 0.0 {
+            true => format!("{:.0}{}", scaled, unit),
+            false => format!("{:.2}{}", scaled, unit),
+        }
+    }
+
+    /// Formats `size` four characters wide like GNU tree does, with a single letter unit and one
+    /// decimal below ten.
+    pub fn format_compact(self, size: u64) -> String {
+        let (scaled, unit) = match self.scale(size) {
+            Some(scaled) => scaled,
+            None => return format!("{:>4}", size),
+        };
+        let unit = unit.chars().next().unwrap_or_default();
+        match scaled >= 10.0 {
+            true => format!("{:>3.0}{}", scaled, unit),
+            false => format!("{:>3.1}{}", scaled, unit),
+        }
+    }
+
+    // Returns `size` in the largest unit it reaches and the label of the unit, nothing if it is
+    // below the smallest one.
+    fn scale(self, size: u64) -> Option<(f64, &'static str)> {
+        let base = self.base();
+        if size < base {
+            return None;
+        }
+
+        let labels = match self {
+            Units::Iec => IEC_UNITS,
+            Units::Si => SI_UNITS,
+        };
+        let mut unit = 0;
+        let mut scale = base;
+        while unit + 1 < labels.len() && size / scale >= base {
+            scale *= base;
+            unit += 1;
+        }
+        Some((size as f64 / scale as f64, labels[unit]))
+    }
+}
+
+/// Inserts `separator` between groups of three digits, e.g. `1,234,567`.
+pub fn group_digits(size: u64, separator: &str) -> String {
+    let digits = size.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Returns the thousands separator used by a locale like `de_DE.UTF-8`, a comma for unknown
+/// ones and the C locale.
+pub fn thousands_separator(locale: &str) -> &'static str {
+    let locale = locale.split(['.', '@']).next().unwrap_or_default();
+    let (language, territory) = locale.split_once('_').unwrap_or((locale, ""));
+    match (language, territory) {
+        (_, "CH" | "LI") => "'",
+        ("de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" | "ro" | "sl" | "hr", _)
+        | ("sr" | "vi", _) => ".",
+        ("fr" | "ru" | "uk" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "nn" | "no" | "hu", _)
+        | ("bg" | "lt" | "lv" | "et", _) => "\u{a0}",
+        _ => ",",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_iec_units() {
+        assert_eq!(Units::Iec.format(1023), "1023");
+        assert_eq!(Units::Iec.format(1024), "1KiB");
+        assert_eq!(Units::Iec.format(1536), "1.50KiB");
+        assert_eq!(Units::Iec.format(5 << 30), "5GiB");
+        assert_eq!(Units::Iec.format(3 << 40), "3TiB");
+        assert_eq!(Units::Iec.format(1 << 50), "1PiB");
+        assert_eq!(Units::Iec.format(1 << 60), "1EiB");
+        assert_eq!(Units::Iec.format(u64::MAX), "16EiB");
+    }
+
+    #[test]
+    fn formats_si_units() {
+        assert_eq!(Units::Si.format(999), "999");
+        assert_eq!(Units::Si.format(1000), "1kB");
+        assert_eq!(Units::Si.format(1024), "1.02kB");
+        assert_eq!(Units::Si.format(2_500_000), "2.50MB");
+        assert_eq!(Units::Si.format(7_000_000_000_000_000), "7PB");
+        assert_eq!(Units::Si.format(u64::MAX), "18.45EB");
+    }
+
+    #[test]
+    fn widest_size_fits() {
+        assert_eq!(Units::Iec.format(1_048_565), "1023.99KiB");
+        assert_eq!(Units::Iec.width(), 10);
+        assert_eq!(Units::Si.format(999_989), "999.99kB");
+        assert_eq!(Units::Si.width(), 8);
+    }
+
+    #[test]
+    fn groups_digits() {
+        assert_eq!(group_digits(0, ","), "0");
+        assert_eq!(group_digits(999, ","), "999");
+        assert_eq!(group_digits(1000, ","), "1,000");
+        assert_eq!(group_digits(1_234_567, "."), "1.234.567");
+        assert_eq!(group_digits(u64::MAX, ","), "18,446,744,073,709,551,615");
+    }
+
+    #[test]
+    fn separator_follows_locale() {
+        assert_eq!(thousands_separator("C"), ",");
+        assert_eq!(thousands_separator("en_US.UTF-8"), ",");
+        assert_eq!(thousands_separator("de_DE.UTF-8"), ".");
+        assert_eq!(thousands_separator("de_CH.UTF-8"), "'");
+        assert_eq!(thousands_separator("fr_FR@euro"), "\u{a0}");
+    }
+
+    #[test]
+    fn formats_compact_like_tree() {
+        assert_eq!(Units::Iec.format_compact(12), "  12");
+        assert_eq!(Units::Iec.format_compact(4096), "4.0K");
+        assert_eq!(Units::Iec.format_compact(20 << 20), " 20M");
+        assert_eq!(Units::Si.format_compact(4096), "4.1k");
+    }
+}
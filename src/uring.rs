@@ -0,0 +1,223 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Reads directories with the metadata of their entries queried in batches of statx operations
+//! through io_uring. The kernel has no io_uring operation listing a directory, entries are
+//! listed with getdents as before and their metadata looked up relative to the open directory,
+//! so paths aren't resolved again for every entry.
+
+use io_uring::{opcode, types, IoUring};
+use std::{
+    cell::RefCell,
+    ffi::CString,
+    fs::{self, DirEntry, File},
+    io, mem,
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
+    path::Path,
+};
+
+use crate::{
+    fsutil::{self, Entry},
+    stat::{self, Fields, Stat},
+};
+
+// Number of statx operations submitted at once.
+const BATCH_SIZE: usize = 256;
+
+thread_local! {
+    // Every thread reading directories uses its own ring, None if io_uring is unavailable.
+    static RING: RefCell<Option<IoUring>> = RefCell::new(IoUring::new(BATCH_SIZE as u32).ok());
+}
+
+/// Reads all entries of a directory and fetches their metadata with batched statx operations,
+/// falling back to single calls if io_uring is unavailable.
+pub fn read_entries(
+    dir: &Path,
+    follow_links: bool,
+    fields: Fields,
+) -> io::Result<Vec<io::Result<Entry>>> {
+    // Without metadata fields the file types of the directory entries are enough.
+    if fields.is_empty() {
+        return fsutil::read_entries(dir, follow_links, fields);
+    }
+
+    let dir_file = File::open(dir)?;
+    let mut entries = vec![];
+    let mut dir_entries = vec![];
+    for result in fs::read_dir(dir)? {
+        match result {
+            Ok(e) => dir_entries.push(e),
+            Err(e) => entries.push(Err(e)),
+        }
+    }
+
+    while !dir_entries.is_empty() {
+        let rest = dir_entries.split_off(dir_entries.len().min(BATCH_SIZE));
+        entries.extend(
+            stat_batch(&dir_file, dir_entries, follow_links, fields)
+                .into_iter()
+                .map(Ok),
+        );
+        dir_entries = rest;
+    }
+
+    Ok(entries)
+}
+
+fn stat_batch(dir: &File, batch: Vec<DirEntry>, follow_links: bool, fields: Fields) -> Vec<Entry> {
+    let stats = RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        let result = match ring.as_mut() {
+            Some(r) => submit(r, dir, &batch, follow_links, fields),
+            None => return None,
+        };
+        match result {
+            Ok(stats) => Some(stats),
+            Err(_) => {
+                // Don't reuse a ring which may still contain operations of this batch.
+                *ring = None;
+                None
+            }
+        }
+    });
+
+    let stats = match stats {
+        Some(stats) => stats,
+        None => {
+            return batch
+                .into_iter()
+                .map(|e| Entry::new(e, follow_links, fields))
+                .collect()
+        }
+    };
+
+    batch
+        .into_iter()
+        .zip(stats)
+        .map(|(e, stat)| {
+            // Kernels before 5.6 don't know the statx operation, retry those with a single call.
+            let stat = match stat {
+                Ok(stat) => Some(stat),
+                Err(_) => Stat::of(&e, fsutil::follows(&e, follow_links), fields).ok(),
+            };
+            Entry::with_stat(e.path(), stat)
+        })
+        .collect()
+}
+
+fn submit(
+    ring: &mut IoUring,
+    dir: &File,
+    batch: &[DirEntry],
+    follow_links: bool,
+    fields: Fields,
+) -> io::Result<Vec<io::Result<Stat>>> {
+    let names = batch
+        .iter()
+        .map(|e| CString::new(e.file_name().as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut bufs: Vec<libc::statx> = vec![unsafe { mem::zeroed() }; batch.len()];
+
+    stat::record_calls(batch.len() as u64);
+    let mask = stat::statx_mask(fields);
+    for (i, (entry, name)) in batch.iter().zip(&names).enumerate() {
+        let flags = stat::statx_flags(fsutil::follows(entry, follow_links));
+        let buf = &mut bufs[i] as *mut libc::statx as *mut types::statx;
+        let sqe = opcode::Statx::new(types::Fd(dir.as_raw_fd()), name.as_ptr(), buf)
+            .flags(flags)
+            .mask(mask)
+            .build()
+            .user_data(i as u64);
+
+        // A full queue is handed to the kernel to make room, completions wait below.
+        while unsafe { ring.submission().push(&sqe) }.is_err() {
+            if let Err(e) = ring.submit() {
+                mem::forget(names);
+                mem::forget(bufs);
+                return Err(e);
+            }
+        }
+    }
+
+    let mut results: Vec<Option<io::Result<Stat>>> = (0..batch.len()).map(|_| None).collect();
+    let mut pending = batch.len();
+    while pending > 0 {
+        match ring.submit_and_wait(pending) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                // The kernel may still write to the buffers of submitted operations.
+                mem::forget(names);
+                mem::forget(bufs);
+                return Err(e);
+            }
+        }
+
+        for cqe in ring.completion() {
+            let i = cqe.user_data() as usize;
+            results[i] = Some(match cqe.result() {
+                r if r < 0 => Err(io::Error::from_raw_os_error(-r)),
+                _ => Ok(Stat::from_statx(&bufs[i])),
+            });
+            pending -= 1;
+        }
+    }
+
+    Ok(results.into_iter().map(Option::unwrap).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fsutil::AldarExt, testing::temp_dir};
+
+    #[test]
+    fn reads_directories_larger_than_a_batch() {
+        let dir = temp_dir("uring");
+        for i in 0..BATCH_SIZE + 10 {
+            fs::write(dir.join(format!("f{}", i)), vec![0; i]).unwrap();
+        }
+        fs::create_dir(dir.join("sub")).unwrap();
+
+        let fields = Fields {
+            size: true,
+            ..Fields::default()
+        };
+        let entries = read_entries(&dir, false, fields).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(entries.len(), BATCH_SIZE + 11);
+        for entry in entries.iter().map(|e| e.as_ref().unwrap()) {
+            match entry.file_name().to_str().unwrap() {
+                "sub" => assert!(entry.is_dir()),
+                name => assert_eq!(entry.size().to_string(), name[1..]),
+            }
+        }
+    }
+
+    #[test]
+    fn submits_batches_larger_than_the_queue() {
+        let dir = temp_dir("uring-queue");
+        for i in 0..8 {
+            fs::write(dir.join(format!("f{}", i)), vec![0; i]).unwrap();
+        }
+
+        let mut ring = IoUring::new(4).unwrap();
+        let batch: Vec<DirEntry> = fs::read_dir(&dir).unwrap().map(Result::unwrap).collect();
+        let stats = submit(
+            &mut ring,
+            &File::open(&dir).unwrap(),
+            &batch,
+            false,
+            Fields::ALL,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        for (entry, stat) in batch.iter().zip(stats.unwrap()) {
+            let name = entry.file_name();
+            assert_eq!(stat.unwrap().size.to_string(), name.to_str().unwrap()[1..]);
+        }
+    }
+}
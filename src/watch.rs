@@ -0,0 +1,318 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+//! Keeps the tree on screen and redraws it as files are created, modified or removed, marking
+//! what changed.
+//!
+//! ```no_run
+//! use aldar::{watch::Watch, Aldar};
+//!
+//! let mut aldar = Aldar::new();
+//! aldar.use_path("target".to_string());
+//! Watch::new().run(&mut aldar).unwrap();
+//! ```
+
+use colored::Colorize;
+use notify::{
+    event::{ModifyKind, RenameMode},
+    EventKind, RecursiveMode, Watcher,
+};
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{error::AldarError, fsutil, testing::SharedBuf, Aldar};
+
+// Clears the terminal and moves the cursor to the top left corner.
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+/// How a path changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Created,
+    Modified,
+    Removed,
+}
+
+impl Change {
+    fn marker(self) -> String {
+        match self {
+            Change::Created => "[+]".green().bold().to_string(),
+            Change::Modified => "[~]".yellow().bold().to_string(),
+            Change::Removed => "[-]".red().bold().to_string(),
+        }
+    }
+}
+
+// Recent changes by absolute path, with the time they were seen.
+type Changes = Arc<Mutex<HashMap<PathBuf, (Change, Instant)>>>;
+
+/// Watches the roots of an [`Aldar`] and redraws the tree whenever they change.
+#[derive(Clone, Debug)]
+pub struct Watch {
+    debounce: Duration,
+    highlight: Duration,
+}
+
+impl Default for Watch {
+    fn default() -> Self {
+        Watch::new()
+    }
+}
+
+impl Watch {
+    /// Creates a watch waiting 200ms for more changes before redrawing and marking changes
+    /// for 10s.
+    pub fn new() -> Watch {
+        Watch {
+            debounce: Duration::from_millis(200),
+            highlight: Duration::from_secs(10),
+        }
+    }
+
+    /// Configures how long to wait for more changes before redrawing, so a burst of changes
+    /// like a build is drawn once.
+    pub fn use_debounce(&mut self, debounce: Duration) -> &mut Watch {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Configures how long changed entries stay marked.
+    pub fn use_highlight(&mut self, highlight: Duration) -> &mut Watch {
+        self.highlight = highlight;
+        self
+    }
+
+    /// Draws the tree to stdout and redraws it on every change until watching fails, e.g. as
+    /// a root was removed. Entries created or modified are marked after their name, removed
+    /// ones are listed below the tree.
+    pub fn run(&self, aldar: &mut Aldar) -> Result<(), AldarError> {
+        self.run_with(aldar, &mut |screen| {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(screen.as_bytes())?;
+            stdout.flush()?;
+            Ok(true)
+        })
+    }
+
+    // Hands every drawn screen to `show` until it returns false or watching fails.
+    fn run_with(
+        &self,
+        aldar: &mut Aldar,
+        show: &mut dyn FnMut(&str) -> io::Result<bool>,
+    ) -> Result<(), AldarError> {
+        let cwd = env::current_dir().unwrap_or_default();
+        let absolute = move |path: &Path| fsutil::normalize_path(&cwd.join(path));
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+        for root in aldar.roots().to_vec() {
+            watcher
+                .watch(&absolute(&root), RecursiveMode::Recursive)
+                .map_err(|e| AldarError::Io {
+                    path: root.clone(),
+                    source: io::Error::other(e),
+                })?;
+        }
+
+        let changes = Changes::default();
+        let marked = Arc::clone(&changes);
+        let absolute_entry = absolute.clone();
+        aldar.annotate_with(move |entry| {
+            let changes = marked.lock().unwrap_or_else(PoisonError::into_inner);
+            let (change, _) = changes.get(&absolute_entry(&entry.path()))?;
+            Some(change.marker())
+        });
+
+        let buffer = SharedBuf::default();
+        loop {
+            let screen = self.draw(aldar, &buffer, &changes)?;
+            if !show(&screen).map_err(AldarError::Write)? {
+                return Ok(());
+            }
+
+            // Changes are only waited for as long as the oldest one stays marked
+            let expires = self.next_expiry(&changes);
+            let event = match expires {
+                Some(timeout) => match rx.recv_timeout(timeout) {
+                    Ok(event) => event,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+                },
+                None => match rx.recv() {
+                    Ok(event) => event,
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            let deadline = Instant::now() + self.debounce;
+            let mut next = Some(event);
+            while let Some(event) = next {
+                record(&changes, event.map_err(watch_error)?);
+                let left = deadline.saturating_duration_since(Instant::now());
+                next = rx.recv_timeout(left).ok();
+            }
+        }
+    }
+
+    // Lists the tree into the buffer and returns a screen replacing the previous one at once, so
+    // it doesn't flicker.
+    fn draw(
+        &self,
+        aldar: &mut Aldar,
+        buffer: &SharedBuf,
+        changes: &Changes,
+    ) -> Result<String, AldarError> {
+        let mut changes = changes.lock().unwrap_or_else(PoisonError::into_inner);
+        changes.retain(|_, (_, seen)| seen.elapsed() < self.highlight);
+        let mut removed: Vec<&PathBuf> = changes
+            .iter()
+            .filter(|(_, (change, _))| *change == Change::Removed)
+            .map(|(path, _)| path)
+            .collect();
+        removed.sort();
+        let removed: Vec<String> = removed.iter().map(|p| p.display().to_string()).collect();
+        drop(changes);
+
+        aldar.use_writer(Box::new(buffer.clone()));
+        match aldar.run() {
+            // Unreadable entries are shown in the tree, watching goes on
+            Ok(_) | Err(AldarError::Traversal { .. }) => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut screen = String::from(CLEAR_SCREEN);
+        screen.push_str(&String::from_utf8_lossy(&buffer.take()));
+        for path in removed {
+            screen.push_str(&format!("{} {}\n", Change::Removed.marker(), path));
+        }
+        Ok(screen)
+    }
+
+    // Returns the time until the next change is no longer marked, if any is.
+    fn next_expiry(&self, changes: &Changes) -> Option<Duration> {
+        let changes = changes.lock().unwrap_or_else(PoisonError::into_inner);
+        let oldest = changes.values().map(|(_, seen)| *seen).min()?;
+        Some((oldest + self.highlight).saturating_duration_since(Instant::now()))
+    }
+}
+
+fn record(changes: &Changes, event: notify::Event) {
+    // A rename moves the first path to the second one
+    if let (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) =
+        (event.kind, event.paths.as_slice())
+    {
+        let mut changes = changes.lock().unwrap_or_else(PoisonError::into_inner);
+        changes.insert(from.clone(), (Change::Removed, Instant::now()));
+        changes.insert(to.clone(), (Change::Created, Instant::now()));
+        return;
+    }
+
+    let change = match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            Change::Created
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => Change::Removed,
+        EventKind::Modify(_) => Change::Modified,
+        EventKind::Remove(_) => Change::Removed,
+        _ => return,
+    };
+
+    let mut changes = changes.lock().unwrap_or_else(PoisonError::into_inner);
+    for path in event.paths {
+        let change = match changes.get(&path) {
+            // A file created and written to is still new
+            Some((Change::Created, _)) if change == Change::Modified => Change::Created,
+            _ => change,
+        };
+        changes.insert(path, (change, Instant::now()));
+    }
+}
+
+fn watch_error(e: notify::Error) -> AldarError {
+    AldarError::Io {
+        path: e.paths.first().cloned().unwrap_or_default(),
+        source: io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::PlainColorizer, testing::temp_dir};
+    use notify::{event::CreateKind, Event};
+    use std::fs;
+
+    fn changes_of(events: Vec<Event>) -> Vec<(PathBuf, Change)> {
+        let changes = Changes::default();
+        for event in events {
+            record(&changes, event);
+        }
+        let changes = changes.lock().unwrap();
+        let mut changes: Vec<_> = changes.iter().map(|(p, (c, _))| (p.clone(), *c)).collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        changes
+    }
+
+    #[test]
+    fn records_renames_and_new_files() {
+        let event = |kind, paths: &[&str]| {
+            paths
+                .iter()
+                .fold(Event::new(kind), |event, path| event.add_path(path.into()))
+        };
+        let changes = changes_of(vec![
+            event(EventKind::Create(CreateKind::File), &["/a"]),
+            event(EventKind::Modify(ModifyKind::Any), &["/a", "/b"]),
+            event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &["/c", "/d"],
+            ),
+        ]);
+
+        assert_eq!(
+            changes,
+            [
+                ("/a".into(), Change::Created),
+                ("/b".into(), Change::Modified),
+                ("/c".into(), Change::Removed),
+                ("/d".into(), Change::Created),
+            ]
+        );
+    }
+
+    #[test]
+    fn redraws_created_files() {
+        let root = temp_dir("watch");
+        fs::write(root.join("old"), b"").unwrap();
+        let mut aldar = Aldar::new();
+        aldar
+            .use_colorizer(Box::new(PlainColorizer))
+            .use_path(root.to_string_lossy().into_owned());
+
+        let mut screens = vec![];
+        let result = Watch::new()
+            .use_debounce(Duration::from_millis(10))
+            .run_with(&mut aldar, &mut |screen| {
+                if screens.is_empty() {
+                    fs::write(root.join("new"), b"")?;
+                }
+                screens.push(screen.to_string());
+                Ok(screens.len() < 2)
+            });
+        fs::remove_dir_all(&root).ok();
+        result.unwrap();
+
+        assert!(screens[0].starts_with(CLEAR_SCREEN));
+        assert!(screens[0].contains("old") && !screens[0].contains("new"));
+        let marker = format!("new {}", Change::Created.marker());
+        assert!(screens[1].contains(&marker), "{}", screens[1]);
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright © 2022 The Aldar Authors
+//
+// Use of this source code is governed by an BSD-style
+// license that can be found in the LICENSE file.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use aldar::{
+    compat::{Compat, Markup, MarkupFormatter},
+    memfs::MemoryFs,
+    testing::{assert_golden, render},
+    Aldar, AldarError, Event, GlyphSet, SortKey, ASCII_GLYPHSET,
+};
+
+fn project() -> MemoryFs {
+    let mut fs = MemoryFs::new();
+    fs.file("project/Cargo.toml", 412)
+        .file("project/README.md", 1850)
+        .file("project/.gitignore", 8)
+        .file("project/src/main.rs", 2048)
+        .file("project/src/lib.rs", 512)
+        .file("project/src/v10.rs", 30)
+        .file("project/src/v2.rs", 20)
+        .executable("project/scripts/build.sh", 96)
+        .dir("project/target/debug/deps")
+        .symlink("project/latest", "target/debug");
+    fs
+}
+
+fn check(aldar: &mut Aldar, golden: &str) {
+    aldar.use_path("project".to_string());
+    let (output, result) = render(aldar, project());
+    result.unwrap();
+    assert_golden(&output, format!("tests/golden/{}.txt", golden));
+}
+
+#[test]
+fn default_listing() {
+    check(&mut Aldar::new(), "default");
+}
+
+#[test]
+fn hidden_files_and_ascii() {
+    let mut aldar = Aldar::new();
+    aldar
+        .show_hidden(true)
+        .use_glyphset(Box::new(ASCII_GLYPHSET));
+    check(&mut aldar, "hidden_ascii");
+}
+
+#[test]
+fn custom_glyphs_are_measured() {
+    let mut aldar = Aldar::new();
+    aldar.use_glyphset(Box::new(GlyphSet::new("┃", "┣━━━━", "┗━━━━")));
+    check(&mut aldar, "custom_glyphs");
+}
+
+#[test]
+fn no_report() {
+    let mut aldar = Aldar::new();
+    aldar.use_path("project".to_string()).show_report(false);
+    let (output, result) = render(&mut aldar, project());
+    result.unwrap();
+
+    let expected = std::fs::read_to_string("tests/golden/default.txt").unwrap();
+    let (tree, report) = expected.rsplit_once("\n\n").unwrap();
+    assert_eq!(report, "5 directories, 8 files\n");
+    assert_eq!(output, format!("{}\n", tree));
+}
+
+#[test]
+fn depth_column() {
+    let mut aldar = Aldar::new();
+    aldar.show_depth(true).show_size(true);
+    check(&mut aldar, "depth");
+}
+
+#[test]
+fn tree_compat() {
+    let mut aldar = Aldar::new();
+    aldar
+        .use_compat(Compat::Tree)
+        .show_size(true)
+        .show_human_readable(true);
+    check(&mut aldar, "tree_compat");
+}
+
+#[test]
+fn tree_json() {
+    // The formatter is kept when render configures colors
+    let mut aldar = Aldar::new();
+    aldar
+        .use_formatter(Box::new(MarkupFormatter::new(Markup::Json).with_size(true)))
+        .use_compat(Compat::Tree)
+        .show_size(true);
+    check(&mut aldar, "tree_json");
+}
+
+#[test]
+fn dirs_only_with_level() {
+    let mut aldar = Aldar::new();
+    aldar.show_dirs_only(true).use_max_level(2);
+    check(&mut aldar, "dirs_only_level");
+}
+
+#[test]
+fn annotations() {
+    let mut aldar = Aldar::new();
+    aldar
+        .annotate_with(|e| match e.file_name().to_str() {
+            Some("main.rs") => Some("[ABC-123]".to_string()),
+            _ => None,
+        })
+        .annotate_with(|e| e.path().extension().map(|_| "(file)".to_string()));
+    check(&mut aldar, "annotations");
+}
+
+#[test]
+fn sizes_sorted_by_version() {
+    let mut aldar = Aldar::new();
+    aldar.show_size(true).use_sort_keys(&[SortKey::Version]);
+    check(&mut aldar, "sizes_version");
+}
+
+#[test]
+fn sort_keys_group_in_place() {
+    // The key takes the place of the default grouping of directories first
+    let mut aldar = Aldar::new();
+    aldar
+        .show_size(true)
+        .use_sort_keys(&[SortKey::FilesFirst, SortKey::Size]);
+    check(&mut aldar, "files_first_size");
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn patterns() {
+    let mut aldar = Aldar::new();
+    aldar
+        .set_include_patterns(&[r"\.rs$"])
+        .unwrap()
+        .set_exclude_patterns(&["^lib"])
+        .unwrap();
+    check(&mut aldar, "patterns");
+}
+
+#[test]
+fn followed_links_compacted() {
+    let mut aldar = Aldar::new();
+    aldar.follow_links(true).compact_dirs(true);
+    check(&mut aldar, "links_compact");
+}
+
+#[test]
+fn breadth_first() {
+    let mut aldar = Aldar::new();
+    aldar.breadth_first(true);
+    check(&mut aldar, "bfs");
+}
+
+#[test]
+fn post_order_sizes() {
+    let mut aldar = Aldar::new();
+    aldar.post_order(true).show_size(true);
+    check(&mut aldar, "post_order");
+}
+
+#[test]
+fn run_returns_stats() {
+    let mut aldar = Aldar::new();
+    aldar.use_path("project".to_string()).show_size(true);
+    let stats = render(&mut aldar, project()).1.unwrap();
+    assert_eq!(
+        (stats.dirs, stats.files, stats.bytes, stats.errors),
+        (5, 8, 4980, 0)
+    );
+    assert_eq!(aldar.stats(), stats);
+
+    aldar.use_path("missing".to_string());
+    assert!(render(&mut aldar, project()).1.is_err());
+    assert_eq!(aldar.stats().errors, 1);
+}
+
+fn describe(event: Event) -> String {
+    match event {
+        Event::EntryDiscovered { node, depth } => format!("{} {}", depth, node.name),
+        Event::DirEntered { path, depth } => format!("{} > {}", depth, path.display()),
+        Event::DirLeft { path, depth } => format!("{} < {}", depth, path.display()),
+        Event::Error { path, .. } => format!("error {}", path.display()),
+    }
+}
+
+#[test]
+fn events_follow_the_listing() {
+    let mut aldar = Aldar::new();
+    aldar.use_path("project/src".to_string());
+    let events = aldar.events();
+    render(&mut aldar, project()).1.unwrap();
+
+    let events: Vec<String> = events.try_iter().map(describe).collect();
+    assert_eq!(
+        events,
+        [
+            "0 > project/src",
+            "1 lib.rs",
+            "1 main.rs",
+            "1 v10.rs",
+            "1 v2.rs",
+            "0 < project/src",
+        ]
+    );
+}
+
+#[test]
+fn events_report_errors_and_nested_dirs() {
+    let mut aldar = Aldar::new();
+    aldar.use_paths(vec!["project/target".to_string(), "missing".to_string()]);
+    let events = aldar.events();
+    assert!(render(&mut aldar, project()).1.is_err());
+
+    let events: Vec<String> = events.try_iter().map(describe).collect();
+    assert_eq!(
+        events,
+        [
+            "0 > project/target",
+            "1 debug",
+            "1 > project/target/debug",
+            "2 deps",
+            "2 > project/target/debug/deps",
+            "2 < project/target/debug/deps",
+            "1 < project/target/debug",
+            "0 < project/target",
+            "error missing",
+        ]
+    );
+}
+
+#[test]
+fn missing_root() {
+    let mut aldar = Aldar::new();
+    aldar.use_path("missing".to_string());
+    let (output, result) = render(&mut aldar, project());
+    assert!(matches!(result, Err(AldarError::Traversal { .. })));
+    assert_golden(&output, "tests/golden/missing.txt");
+}
+
+#[test]
+fn link_loops_are_not_followed() {
+    let mut fs = MemoryFs::new();
+    fs.file("loop/a/file", 1).symlink("loop/a/up", "..");
+    let mut aldar = Aldar::new();
+    aldar.use_path("loop".to_string()).follow_links(true);
+    let (output, result) = render(&mut aldar, fs);
+    result.unwrap();
+    assert_golden(&output, "tests/golden/loop.txt");
+}
+
+#[test]
+fn cancelled_scan_reports_what_was_listed() {
+    let token = Arc::new(AtomicBool::new(false));
+    let cancel = token.clone();
+    let mut aldar = Aldar::new();
+    aldar
+        .use_path("project".to_string())
+        .with_cancellation(token)
+        .on_progress(move |_| cancel.store(true, Ordering::Relaxed));
+    let (output, result) = render(&mut aldar, project());
+    assert!(matches!(result, Err(AldarError::Cancelled)));
+    assert_golden(&output, "tests/golden/cancelled.txt");
+}